@@ -748,7 +748,7 @@ fn build_multi_rt() -> tokio::runtime::Runtime {
 
 #[inline]
 fn time_now() -> OffsetDateTime {
-    OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+    OffsetDateTime::now_utc()
 }
 
 fn keys() -> Vec<SessionKey> {
@@ -0,0 +1,509 @@
+//! A trait for implementing session stores.
+//!
+//! [`SessionBackend`] only requires a store to implement the four basic
+//! operations on a session, and provides default implementations of the
+//! rest. To use a backend with `SessionLayer`, wrap it in a [`BackendStore`],
+//! which implements [`SessionStore`].
+//!
+//! # Stability
+//!
+//! Unlike the rest of this crate, this module follows [Semantic Versioning],
+//! so a backend doesn't have to be released in lockstep with `tower-sesh`.
+//! Depend on `tower-sesh-core` with a caret requirement on the first version
+//! providing every method the backend implements:
+//!
+//! ```toml
+//! tower-sesh-core = "0.1.0-alpha.3"
+//! ```
+//!
+//! Cargo then resolves it to whichever version `tower-sesh` requires. Note
+//! that a requirement of `"0.1"` would not match pre-release versions.
+//!
+//! The guarantee covers [`SessionBackend`], [`BackendStore`], and the types
+//! appearing in their signatures: [`SessionKey`], [`Record`], [`Ttl`],
+//! [`Version`], [`UpdateIf`], [`CreateWithKey`], [`SessionIndex`],
+//! [`IndexedSession`](crate::store::IndexedSession),
+//! [`StorageId`](crate::store::StorageId), and
+//! [`Error`](crate::store::Error). New capabilities are only ever added as
+//! methods with default implementations, so existing backends keep compiling.
+//!
+//! [Semantic Versioning]: https://semver.org/
+//!
+//! # Example
+//!
+//! ```
+//! use async_trait::async_trait;
+//! use tower_sesh_core::{
+//!     backend::SessionBackend,
+//!     store::Result,
+//!     Record, SessionKey, Ttl,
+//! };
+//!
+//! struct MyStore {
+//!     /* ... */
+//! }
+//!
+//! #[async_trait]
+//! impl<T> SessionBackend<T> for MyStore
+//! where
+//!     T: Send + Sync + 'static,
+//! {
+//!     async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+//!         /* ... */
+//! # unimplemented!()
+//!     }
+//!
+//!     async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+//!         /* ... */
+//! # unimplemented!()
+//!     }
+//!
+//!     async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+//!         /* ... */
+//! # unimplemented!()
+//!     }
+//!
+//!     async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+//!         /* ... */
+//! # unimplemented!()
+//!     }
+//! }
+//! ```
+
+use async_trait::async_trait;
+
+use crate::{
+    store::{
+        CreateWithKey, Result, SessionIndex, SessionStoreImpl, SessionStoreRng, UpdateIf, Version,
+    },
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+/// An interface for implementing a session store.
+///
+/// Only [`create`], [`load`], [`update`], and [`delete`] are required. The
+/// remaining methods are optional capabilities, which have default
+/// implementations built on the required methods. A store should override an
+/// optional capability if it can perform the operation more efficiently, or
+/// atomically.
+///
+/// # Optional capabilities
+///
+/// | Method                    | Since           |
+/// |---------------------------|-----------------|
+/// | [`update_ttl`]            | `0.1.0-alpha.3` |
+/// | [`cycle_key`]             | `0.1.0-alpha.3` |
+/// | [`update_if`]             | `0.1.0-alpha.3` |
+/// | [`create_with_version`]   | `0.1.0-alpha.3` |
+/// | [`update_with_version`]   | `0.1.0-alpha.3` |
+/// | [`create_with_key`]       | `0.1.0-alpha.3` |
+/// | [`load_many`]             | `0.1.0-alpha.3` |
+/// | [`delete_many`]           | `0.1.0-alpha.3` |
+/// | [`as_index`]              | `0.1.0-alpha.3` |
+/// | [`purge_expired`]         | `0.1.0-alpha.3` |
+///
+/// [`create`]: SessionBackend::create
+/// [`load`]: SessionBackend::load
+/// [`update`]: SessionBackend::update
+/// [`delete`]: SessionBackend::delete
+/// [`update_ttl`]: SessionBackend::update_ttl
+/// [`cycle_key`]: SessionBackend::cycle_key
+/// [`update_if`]: SessionBackend::update_if
+/// [`create_with_version`]: SessionBackend::create_with_version
+/// [`update_with_version`]: SessionBackend::update_with_version
+/// [`create_with_key`]: SessionBackend::create_with_key
+/// [`load_many`]: SessionBackend::load_many
+/// [`delete_many`]: SessionBackend::delete_many
+/// [`as_index`]: SessionBackend::as_index
+/// [`purge_expired`]: SessionBackend::purge_expired
+#[async_trait]
+pub trait SessionBackend<T>: 'static + Send + Sync {
+    /// Creates a session, returning the session key that may be used to
+    /// subsequently retrieve the session.
+    ///
+    /// Implementors should randomly generate a session key and perform
+    /// collision resolution (even though collisions are statistically
+    /// improbable).
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey>;
+
+    /// Returns a record containing the data and expiry corresponding to the
+    /// session identified by the provided session key.
+    ///
+    /// If there is no session identified by the given session key, or if it
+    /// has expired, `Ok(None)` is returned.
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>>;
+
+    /// Updates the session identified by the provided session key.
+    ///
+    /// If no session identified by the session key exists, or if it has
+    /// expired, it should be created.
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()>;
+
+    /// Deletes the session identified by the provided session key.
+    ///
+    /// If no session identified by the session key exists, this should be a
+    /// no-op with an `Ok` result.
+    async fn delete(&self, session_key: &SessionKey) -> Result<()>;
+
+    /// Updates the expiry of the session identified by the provided session
+    /// key, without changing its data.
+    ///
+    /// If no session identified by the session key exists, or if it has
+    /// expired, this should be a no-op with an `Ok` result.
+    ///
+    /// The default implementation calls [`load`] followed by [`update`].
+    ///
+    /// [`load`]: SessionBackend::load
+    /// [`update`]: SessionBackend::update
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()>
+    where
+        T: Send + Sync,
+    {
+        match self.load(session_key).await? {
+            Some(record) => self.update(session_key, &record.data, ttl).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the session identified by the provided session key to a newly
+    /// generated session key, replacing its data and expiry with `data` and
+    /// `ttl`. Returns the new session key.
+    ///
+    /// After this returns successfully, the old session key should no longer
+    /// identify any session. If no session identified by the old session key
+    /// exists, a new session should be created anyway.
+    ///
+    /// The default implementation calls [`create`] followed by [`delete`].
+    ///
+    /// [`create`]: SessionBackend::create
+    /// [`delete`]: SessionBackend::delete
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey>
+    where
+        T: Sync,
+    {
+        let new_session_key = self.create(data, ttl).await?;
+        self.delete(session_key).await?;
+        Ok(new_session_key)
+    }
+
+    /// Updates the session identified by the provided session key, but only
+    /// if its current version is `version`.
+    ///
+    /// Stores which support optimistic concurrency should return records with
+    /// a [`Version`] from [`load`], and override this method to compare and
+    /// set the session atomically. See [`UpdateIf`] for the possible results.
+    ///
+    /// The default implementation calls [`update`] unconditionally, which is
+    /// correct for stores which don't track versions.
+    ///
+    /// [`load`]: SessionBackend::load
+    /// [`update`]: SessionBackend::update
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        self.update(session_key, data, ttl).await?;
+        Ok(UpdateIf::Updated(None))
+    }
+
+    /// Creates a session whose version is `version`, returning its session
+    /// key and the version it was stored with.
+    ///
+    /// Caching layers such as `CachingStore` use this and
+    /// [`update_with_version`] to keep the version of a cached session in
+    /// step with the backend. Stores which derive the version from the stored
+    /// data should return the derived version rather than `version`.
+    ///
+    /// The default implementation calls [`create`] and returns `None` as the
+    /// version, since the version assigned by [`create`] isn't known.
+    ///
+    /// [`create`]: SessionBackend::create
+    /// [`update_with_version`]: SessionBackend::update_with_version
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        let session_key = self.create(data, ttl).await?;
+        Ok((session_key, None))
+    }
+
+    /// Updates the session identified by the provided session key, recording
+    /// `version` as its version instead of generating a new one. Returns the
+    /// version the session was stored with.
+    ///
+    /// The default implementation calls [`update`] and returns `None`, since
+    /// the version assigned by [`update`] isn't known.
+    ///
+    /// [`update`]: SessionBackend::update
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        self.update(session_key, data, ttl).await?;
+        Ok(None)
+    }
+
+    /// Creates a session under the provided session key, chosen by the
+    /// caller, unless a session identified by it already exists. See
+    /// [`CreateWithKey`] for the possible results.
+    ///
+    /// Stores which wrap a backend use this when their stored data depends on
+    /// the session key, such as `EncryptedStore`.
+    ///
+    /// The default implementation calls [`load`] followed by
+    /// [`update_with_version`], which isn't atomic.
+    ///
+    /// [`load`]: SessionBackend::load
+    /// [`update_with_version`]: SessionBackend::update_with_version
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey>
+    where
+        T: Send + Sync,
+    {
+        if self.load(session_key).await?.is_some() {
+            return Ok(CreateWithKey::Occupied);
+        }
+        let version = self
+            .update_with_version(session_key, data, ttl, version)
+            .await?;
+        Ok(CreateWithKey::Created(version))
+    }
+
+    /// Returns the records of the sessions identified by the provided session
+    /// keys, in the same order. Missing or expired sessions are `None`.
+    ///
+    /// The default implementation calls [`load`] for each session key.
+    ///
+    /// [`load`]: SessionBackend::load
+    async fn load_many(&self, session_keys: &[SessionKey]) -> Result<Vec<Option<Record<T>>>>
+    where
+        T: Send,
+    {
+        let mut records = Vec::with_capacity(session_keys.len());
+        for session_key in session_keys {
+            records.push(self.load(session_key).await?);
+        }
+        Ok(records)
+    }
+
+    /// Deletes the sessions identified by the provided session keys.
+    ///
+    /// The default implementation calls [`delete`] for each session key.
+    ///
+    /// [`delete`]: SessionBackend::delete
+    async fn delete_many(&self, session_keys: &[SessionKey]) -> Result<()> {
+        for session_key in session_keys {
+            self.delete(session_key).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the store's [`SessionIndex`] capability, if it has one.
+    ///
+    /// The default implementation returns `None`.
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        None
+    }
+
+    /// Removes every expired session from the store, returning the number of
+    /// sessions removed.
+    ///
+    /// Stores which don't remove expired sessions on their own should
+    /// override this, so that abandoned sessions can be cleaned up
+    /// periodically. The default implementation does nothing and returns `0`.
+    async fn purge_expired(&self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+/// Adapts a [`SessionBackend`] into a [`SessionStore`].
+///
+/// # Example
+///
+/// ```ignore
+/// use tower_sesh::SessionLayer;
+/// use tower_sesh_core::backend::BackendStore;
+///
+/// let store = BackendStore::new(MyStore::new());
+/// let layer = SessionLayer::new(Arc::new(store), key);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BackendStore<B> {
+    backend: B,
+}
+
+impl<B> BackendStore<B> {
+    /// Wraps a session backend.
+    pub fn new(backend: B) -> BackendStore<B> {
+        BackendStore { backend }
+    }
+
+    /// Returns a reference to the wrapped backend.
+    pub fn get_ref(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns a mutable reference to the wrapped backend.
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Consumes `self`, returning the wrapped backend.
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+}
+
+impl<B> From<B> for BackendStore<B> {
+    fn from(backend: B) -> Self {
+        BackendStore::new(backend)
+    }
+}
+
+impl<T, B> SessionStore<T> for BackendStore<B>
+where
+    B: SessionBackend<T>,
+    T: 'static + Send + Sync,
+{
+}
+
+#[async_trait]
+impl<T, B> SessionStoreImpl<T> for BackendStore<B>
+where
+    B: SessionBackend<T>,
+    T: 'static + Send + Sync,
+{
+    #[inline]
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        self.backend.create(data, ttl).await
+    }
+
+    #[inline]
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        self.backend.load(session_key).await
+    }
+
+    #[inline]
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.backend.update(session_key, data, ttl).await
+    }
+
+    #[inline]
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        self.backend.update_ttl(session_key, ttl).await
+    }
+
+    #[inline]
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        self.backend.delete(session_key).await
+    }
+
+    #[inline]
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        self.backend.cycle_key(session_key, data, ttl).await
+    }
+
+    #[inline]
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        self.backend
+            .update_if(session_key, data, ttl, version)
+            .await
+    }
+
+    #[inline]
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        self.backend.create_with_version(data, ttl, version).await
+    }
+
+    #[inline]
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        self.backend
+            .update_with_version(session_key, data, ttl, version)
+            .await
+    }
+
+    #[inline]
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        self.backend
+            .create_with_key(session_key, data, ttl, version)
+            .await
+    }
+
+    #[inline]
+    async fn load_many(&self, session_keys: &[SessionKey]) -> Result<Vec<Option<Record<T>>>> {
+        self.backend.load_many(session_keys).await
+    }
+
+    #[inline]
+    async fn delete_many(&self, session_keys: &[SessionKey]) -> Result<()> {
+        self.backend.delete_many(session_keys).await
+    }
+
+    #[inline]
+    async fn purge_expired(&self) -> Result<u64> {
+        self.backend.purge_expired().await
+    }
+
+    #[inline]
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        self.backend.as_index()
+    }
+}
+
+impl<Rng, B> SessionStoreRng<Rng> for BackendStore<B>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+    B: SessionStoreRng<Rng>,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.backend.rng(rng);
+    }
+}
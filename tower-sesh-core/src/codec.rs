@@ -0,0 +1,425 @@
+//! Serialization formats used by session stores to encode session data.
+//!
+//! A store which saves session data as bytes, such as `RedisStore`, is generic
+//! over a [`Codec`] so that the format can be chosen to suit other consumers of
+//! the same data. The built-in codecs are each enabled with a cargo feature:
+//!
+//! | Codec           | Feature   |
+//! |-----------------|-----------|
+//! | [`Json`]        | `json`    |
+//! | [`MessagePack`] | `msgpack` |
+//! | [`Cbor`]        | `cbor`    |
+//!
+//! Any codec can be wrapped in [`Compressed`] to compress large payloads,
+//! which requires the `zstd` or `lz4` feature.
+//!
+//! A codec must use a self-describing format, since session data may be
+//! deserialized with [`Deserializer::deserialize_any`].
+//!
+//! [`Deserializer::deserialize_any`]: serde::Deserializer::deserialize_any
+//!
+//! # Headers
+//!
+//! Codecs which wrap another codec, such as [`Compressed`] and [`Versioned`],
+//! mark the data they produce with a header, so that data without a header
+//! can still be decoded. A header starts with the byte `0xc1`, followed by a
+//! byte identifying the wrapping codec. `0xc1` is never used by MessagePack
+//! and can't begin a JSON document. In CBOR it denotes an epoch-based
+//! date/time tag, which isn't produced by serializing Rust data types with
+//! serde.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::store::{Error, Result};
+
+/// A serialization format used to encode session data.
+pub trait Codec: 'static + Send + Sync {
+    /// Serializes a value into bytes.
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized;
+
+    /// Deserializes a value from bytes produced by [`encode`].
+    ///
+    /// [`encode`]: Codec::encode
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned;
+}
+
+/// The [JSON] format.
+///
+/// [JSON]: https://www.json.org
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        serde_json::to_vec(value).map_err(Error::serde)
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(Error::serde)
+    }
+}
+
+/// The [MessagePack] format.
+///
+/// Structs are encoded as maps with field names as keys, rather than as
+/// arrays.
+///
+/// [MessagePack]: https://msgpack.org
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        rmp_serde::to_vec_named(value).map_err(Error::serde)
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        rmp_serde::from_slice(bytes).map_err(Error::serde)
+    }
+}
+
+/// The [CBOR] format.
+///
+/// [CBOR]: https://cbor.io
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(Error::serde)?;
+        Ok(bytes)
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        ciborium::from_reader(bytes).map_err(Error::serde)
+    }
+}
+
+const HEADER_MAGIC: u8 = 0xc1;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+const LZ4_TAG: u8 = 1;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+const ZSTD_TAG: u8 = 2;
+const VERSIONED_TAG: u8 = 0x80;
+
+/// A codec which records the schema version of the data it encodes.
+///
+/// Data is encoded with a header containing `version`. Decoding data with a
+/// different schema version fails with an error of kind
+/// [`ErrorKind::Outdated`], which contains the data encoded by the wrapped
+/// codec so that it can be migrated to the current schema. Data without a
+/// header, such as data stored before versioning was enabled, has schema
+/// version 1.
+///
+/// This is usually created from a migration chain with
+/// `tower_sesh::migrate::Migrations::codec`, rather than directly.
+///
+/// [`ErrorKind::Outdated`]: crate::store::ErrorKind::Outdated
+#[derive(Clone, Copy, Debug)]
+pub struct Versioned<K> {
+    codec: K,
+    version: u32,
+}
+
+impl<K: Codec> Versioned<K> {
+    /// Wraps `codec`, recording `version` as the schema version of encoded
+    /// data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `version` is zero.
+    #[track_caller]
+    pub fn new(codec: K, version: u32) -> Versioned<K> {
+        assert!(version != 0, "schema versions start at 1");
+        Versioned { codec, version }
+    }
+
+    /// Returns the current schema version.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl<K: Codec> Codec for Versioned<K> {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let encoded = self.codec.encode(value)?;
+
+        let mut bytes = Vec::with_capacity(encoded.len() + 6);
+        bytes.push(HEADER_MAGIC);
+        bytes.push(VERSIONED_TAG);
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (version, payload) = match bytes {
+            [HEADER_MAGIC, VERSIONED_TAG, a, b, c, d, payload @ ..] => {
+                (u32::from_be_bytes([*a, *b, *c, *d]), payload)
+            }
+            _ => (1, bytes),
+        };
+
+        if version == self.version {
+            self.codec.decode(payload)
+        } else {
+            Err(Error::outdated(version, payload.to_vec()))
+        }
+    }
+}
+
+/// A codec which compresses the output of another codec.
+///
+/// Data is only compressed if its encoded size is at least the
+/// [threshold](Compressed::threshold), and compressing it makes it smaller.
+/// Compressed data is marked with a header, so data which was stored
+/// uncompressed (including data stored before compression was enabled) can
+/// still be decoded. Data compressed with any supported algorithm can be
+/// decoded, regardless of the algorithm used to compress new data.
+///
+/// See the [module-level documentation](self#headers) for the format of the
+/// header.
+///
+/// To guard against payloads which decompress to a huge size, decompressed
+/// data may be at most [`max_decompressed_size`] bytes. Data larger than this
+/// is stored uncompressed.
+///
+/// [`max_decompressed_size`]: Compressed::max_decompressed_size
+///
+/// The uncompressed and compressed size of each compressed payload is reported
+/// at the debug level.
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh_core::codec::{Compressed, MessagePack};
+///
+/// let codec = Compressed::zstd(MessagePack).threshold(4096);
+/// ```
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[derive(Clone, Copy, Debug)]
+pub struct Compressed<K> {
+    codec: K,
+    algorithm: Algorithm,
+    threshold: usize,
+    max_decompressed_size: usize,
+}
+
+/// A compression algorithm used by [`Compressed`].
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// The [LZ4] algorithm, which is very fast but compresses less.
+    ///
+    /// [LZ4]: https://lz4.org
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// The [Zstandard] algorithm at its default compression level.
+    ///
+    /// [Zstandard]: https://facebook.github.io/zstd/
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+impl Algorithm {
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => LZ4_TAG,
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => ZSTD_TAG,
+        }
+    }
+
+    fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => Ok(lz4_flex::compress_prepend_size(bytes)),
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => zstd::encode_all(bytes, 0).map_err(Error::serde),
+        }
+    }
+
+    /// Decompresses `bytes`, failing if the decompressed data would be larger
+    /// than `max_size`.
+    fn decompress(id: u8, bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        match id {
+            #[cfg(feature = "lz4")]
+            LZ4_TAG => {
+                // The decompressed size is prepended as a little-endian `u32`,
+                // and is checked before anything is allocated
+                let Some((size, compressed)) = bytes.split_first_chunk::<4>() else {
+                    return Err(Error::serde("truncated LZ4 payload"));
+                };
+                let size = u32::from_le_bytes(*size) as usize;
+                if size > max_size {
+                    return Err(err_too_large(max_size));
+                }
+                lz4_flex::decompress(compressed, size).map_err(Error::serde)
+            }
+            #[cfg(feature = "zstd")]
+            ZSTD_TAG => {
+                use std::io::Read;
+
+                // Read one byte past the limit to tell whether it's exceeded
+                let mut decompressed = Vec::new();
+                zstd::Decoder::new(bytes)
+                    .and_then(|decoder| {
+                        decoder
+                            .take(max_size as u64 + 1)
+                            .read_to_end(&mut decompressed)
+                    })
+                    .map_err(Error::serde)?;
+                if decompressed.len() > max_size {
+                    return Err(err_too_large(max_size));
+                }
+                Ok(decompressed)
+            }
+            _ => Err(Error::serde(format!(
+                "unsupported compression algorithm {id}"
+            ))),
+        }
+    }
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+impl<K: Codec> Compressed<K> {
+    const DEFAULT_THRESHOLD: usize = 1024;
+    const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
+    /// Compresses the output of `codec` with `algorithm`.
+    pub fn new(codec: K, algorithm: Algorithm) -> Compressed<K> {
+        Compressed {
+            codec,
+            algorithm,
+            threshold: Self::DEFAULT_THRESHOLD,
+            max_decompressed_size: Self::DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Compresses the output of `codec` with [LZ4](Algorithm::Lz4).
+    #[cfg(feature = "lz4")]
+    pub fn lz4(codec: K) -> Compressed<K> {
+        Compressed::new(codec, Algorithm::Lz4)
+    }
+
+    /// Compresses the output of `codec` with [Zstandard](Algorithm::Zstd).
+    #[cfg(feature = "zstd")]
+    pub fn zstd(codec: K) -> Compressed<K> {
+        Compressed::new(codec, Algorithm::Zstd)
+    }
+
+    /// Set the size in bytes at which encoded data is compressed.
+    ///
+    /// Default is 1024.
+    pub fn threshold(mut self, threshold: usize) -> Compressed<K> {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set the maximum size in bytes of decompressed data.
+    ///
+    /// Decoding compressed data which decompresses to more than this fails
+    /// with an error of kind [`ErrorKind::Serde`], and encoded data larger
+    /// than this isn't compressed. Lowering the limit may make data which was
+    /// compressed under a higher limit impossible to decode.
+    ///
+    /// Default is 1 MiB.
+    ///
+    /// [`ErrorKind::Serde`]: crate::store::ErrorKind::Serde
+    pub fn max_decompressed_size(mut self, max_decompressed_size: usize) -> Compressed<K> {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+impl<K: Codec> Codec for Compressed<K> {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let encoded = self.codec.encode(value)?;
+        if encoded.len() < self.threshold || encoded.len() > self.max_decompressed_size {
+            return Ok(encoded);
+        }
+
+        let compressed = self.algorithm.compress(&encoded)?;
+        if compressed.len() + 2 >= encoded.len() {
+            return Ok(encoded);
+        }
+
+        debug!(
+            algorithm = ?self.algorithm,
+            uncompressed = encoded.len(),
+            compressed = compressed.len() + 2,
+            ratio = encoded.len() as f64 / (compressed.len() + 2) as f64,
+            "compressed session data"
+        );
+
+        let mut bytes = Vec::with_capacity(compressed.len() + 2);
+        bytes.push(HEADER_MAGIC);
+        bytes.push(self.algorithm.id());
+        bytes.extend_from_slice(&compressed);
+        Ok(bytes)
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match bytes {
+            [HEADER_MAGIC, id @ (LZ4_TAG | ZSTD_TAG), compressed @ ..] => {
+                let decompressed =
+                    Algorithm::decompress(*id, compressed, self.max_decompressed_size)?;
+                self.codec.decode(&decompressed)
+            }
+            _ => self.codec.decode(bytes),
+        }
+    }
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[cold]
+fn err_too_large(max_size: usize) -> Error {
+    Error::serde(format!(
+        "decompressed session data is larger than the limit of {max_size} bytes"
+    ))
+}
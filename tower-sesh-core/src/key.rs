@@ -7,7 +7,9 @@ use std::{
 };
 
 use base64::Engine;
+use hmac::{Hmac, Mac};
 use rand::distr::{Distribution, StandardUniform};
+use sha2::Sha256;
 
 /// A 128-bit session identifier.
 // `NonZeroU128` is used so that `Option<SessionKey>` has the same size as
@@ -107,6 +109,68 @@ impl From<SessionKey> for NonZeroU128 {
     }
 }
 
+/// Derives the key a session is stored under from its session key.
+///
+/// By default, stores address sessions by their session key. Since a session
+/// key is a bearer token, anyone who can list the keys of a store (e.g. from a
+/// leaked Redis key dump) could use them to hijack sessions. A store configured
+/// with a `KeyHasher` instead addresses each session by a keyed hash
+/// (HMAC-SHA256) of its session key, which can't be reversed without the
+/// hasher's secret. The cookie sent to the client still carries the session
+/// key itself.
+///
+/// The derived key is itself a [`SessionKey`], so that it can be encoded and
+/// stored the same way.
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh_core::key::KeyHasher;
+///
+/// fn secret() -> Vec<u8> {
+///     // TODO: Where do you get a secret?
+/// # vec![0; 32]
+/// }
+///
+/// let hasher = KeyHasher::new(&secret());
+/// ```
+#[derive(Clone)]
+pub struct KeyHasher {
+    mac: Hmac<Sha256>,
+}
+
+impl KeyHasher {
+    /// Creates a hasher from a secret, which should be at least 32 bytes
+    /// generated by a cryptographically secure random number generator.
+    ///
+    /// Changing the secret makes every existing session unreachable.
+    pub fn new(secret: &[u8]) -> KeyHasher {
+        KeyHasher {
+            mac: Hmac::new_from_slice(secret).expect("HMAC can take a key of any size"),
+        }
+    }
+
+    /// Returns the key the session identified by `session_key` is stored
+    /// under.
+    #[must_use]
+    pub fn hash(&self, session_key: &SessionKey) -> SessionKey {
+        let mut mac = self.mac.clone();
+        mac.update(&session_key.0.get().to_le_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let mut truncated = [0; const { SessionKey::DECODED_LEN }];
+        truncated.copy_from_slice(&digest[..SessionKey::DECODED_LEN]);
+        SessionKey(NonZeroU128::new(u128::from_le_bytes(truncated)).unwrap_or(NonZeroU128::MIN))
+    }
+}
+
+/// Debug implementation does not leak secret.
+impl fmt::Debug for KeyHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyHasher(..)")
+    }
+}
+
 /// The error type returned when decoding a session key fails.
 #[derive(Debug)]
 pub enum DecodeSessionKeyError {
@@ -157,6 +221,19 @@ mod test {
         );
     }
 
+    #[test]
+    fn hash_is_keyed() {
+        let session_key = SessionKey::try_from(42).unwrap();
+        let hasher = KeyHasher::new(b"secret");
+
+        assert_eq!(hasher.hash(&session_key), hasher.hash(&session_key));
+        assert_ne!(hasher.hash(&session_key), session_key);
+        assert_ne!(
+            hasher.hash(&session_key),
+            KeyHasher::new(b"other secret").hash(&session_key)
+        );
+    }
+
     impl Arbitrary for SessionKey {
         fn arbitrary(g: &mut quickcheck::Gen) -> Self {
             SessionKey::from(NonZeroU128::arbitrary(g))
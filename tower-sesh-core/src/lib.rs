@@ -11,6 +11,9 @@
 //! ```
 //!
 //! And then keep releases in sync with `tower-sesh-core`.
+//!
+//! The exception is the [`backend`] module, which follows Semantic Versioning
+//! and is the recommended way to implement a custom session store.
 
 #![warn(missing_debug_implementations)]
 #![deny(rustdoc::broken_intra_doc_links)]
@@ -38,7 +41,10 @@ pub mod __private {
     pub use ::tracing;
 }
 
+pub mod backend;
+pub mod codec;
 pub mod key;
+pub mod reaper;
 pub mod store;
 pub mod time;
 pub mod util;
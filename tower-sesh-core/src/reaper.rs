@@ -0,0 +1,140 @@
+//! Periodic removal of expired sessions.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::store::Result;
+
+/// A handle to a background task which periodically removes expired sessions
+/// from a store.
+///
+/// The reaper runs on its own thread, so it works with any async runtime (or
+/// none at all). It runs until [`shutdown`] is called, or until every `Arc`
+/// referencing the store has been dropped. Dropping the handle detaches the
+/// reaper without stopping it.
+///
+/// Reapers are created by the `spawn_reaper` method of stores which don't
+/// remove expired sessions on their own.
+///
+/// [`shutdown`]: Reaper::shutdown
+pub struct Reaper {
+    shared: Arc<Shared>,
+    thread: thread::JoinHandle<()>,
+}
+
+struct Shared {
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+    removed: AtomicU64,
+}
+
+impl Reaper {
+    /// Spawns a thread which calls `sweep` with the store every `interval`.
+    ///
+    /// `sweep` removes expired sessions from the store, returning the number
+    /// of sessions removed. The number of sessions removed by each sweep is
+    /// logged at the debug level, and the running total is available from
+    /// [`Reaper::removed`]. Errors are logged, and don't stop the reaper.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, or if the OS fails to create a thread.
+    #[track_caller]
+    pub fn spawn<S>(
+        store: &Arc<S>,
+        interval: Duration,
+        sweep: impl FnMut(&S) -> Result<u64> + Send + 'static,
+    ) -> Reaper
+    where
+        S: Send + Sync + 'static,
+    {
+        assert!(!interval.is_zero(), "reaper interval must be non-zero");
+
+        let shared = Arc::new(Shared {
+            stopped: Mutex::new(false),
+            condvar: Condvar::new(),
+            removed: AtomicU64::new(0),
+        });
+        let store = Arc::downgrade(store);
+
+        let thread = thread::Builder::new()
+            .name("tower-sesh-reaper".to_owned())
+            .spawn({
+                let shared = Arc::clone(&shared);
+                move || run(&store, &shared, interval, sweep)
+            })
+            .unwrap_or_else(|err| panic!("failed to spawn tower-sesh-reaper thread: {err}"));
+
+        Reaper { shared, thread }
+    }
+
+    /// Returns the total number of sessions removed by the reaper so far.
+    pub fn removed(&self) -> u64 {
+        self.shared.removed.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Stops the reaper, waiting for a sweep in progress to finish.
+    ///
+    /// Returns the total number of sessions removed by the reaper.
+    pub fn shutdown(self) -> u64 {
+        *self.shared.stopped.lock() = true;
+        self.shared.condvar.notify_one();
+
+        // The thread doesn't panic unless the sweep does, in which case
+        // there's nothing left to stop.
+        let _ = self.thread.join();
+
+        self.shared.removed.load(atomic::Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Reaper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reaper")
+            .field("removed", &self.removed())
+            .finish_non_exhaustive()
+    }
+}
+
+fn run<S>(
+    store: &Weak<S>,
+    shared: &Shared,
+    interval: Duration,
+    mut sweep: impl FnMut(&S) -> Result<u64>,
+) {
+    loop {
+        {
+            let mut stopped = shared.stopped.lock();
+            if !*stopped {
+                shared.condvar.wait_for(&mut stopped, interval);
+            }
+            if *stopped {
+                return;
+            }
+        }
+
+        let Some(store) = store.upgrade() else {
+            return;
+        };
+        match sweep(&store) {
+            Ok(removed) => {
+                shared.removed.fetch_add(removed, atomic::Ordering::Relaxed);
+                if removed > 0 {
+                    debug!(removed, "purged expired sessions");
+                }
+            }
+            Err(_err) => {
+                error!(err = %crate::util::Report::new(_err), "error purging expired sessions");
+            }
+        }
+    }
+}
@@ -2,6 +2,14 @@
 //!
 //! # Implementing `SessionStore`
 //!
+//! <div class="warning">
+//!
+//! The traits in this module do not follow semantic versioning. Store
+//! implementations outside of `tower-sesh` should prefer
+//! [`SessionBackend`](crate::backend::SessionBackend), which does.
+//!
+//! </div>
+//!
 //! `SessionStore` is sealed with the `SessionStoreImpl` trait. To implement
 //! `SessionStore`, implement `SessionStoreImpl` too:
 //!
@@ -66,6 +74,67 @@ pub trait SessionStoreImpl<T>: 'static + Send + Sync {
     /// improbable).
     async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey>;
 
+    /// Creates a session like [`create`], recording `version` as its version
+    /// instead of generating a new one. Returns the session key along with
+    /// the version the session was stored with.
+    ///
+    /// Like [`update_with_version`], this is used by caching layers, and the
+    /// returned version differs from `version` if the store derives versions
+    /// from the stored data. The default implementation calls [`create`] and
+    /// returns `None`, since the version assigned by [`create`] isn't known.
+    ///
+    /// [`create`]: SessionStoreImpl::create
+    /// [`update_with_version`]: SessionStoreImpl::update_with_version
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        let session_key = self.create(data, ttl).await?;
+        Ok((session_key, None))
+    }
+
+    /// Creates a session under the provided session key, chosen by the
+    /// caller, unless a session identified by it already exists. The session
+    /// is stored with `version` as its version, as with
+    /// [`create_with_version`].
+    ///
+    /// This is used by stores which wrap another store, and whose stored data
+    /// depends on the session key. The caller is responsible for generating
+    /// the session key randomly, and for retrying with a new session key if
+    /// [`CreateWithKey::Occupied`] is returned.
+    ///
+    /// The default implementation calls [`load`] followed by
+    /// [`update_with_version`]. Implementors should override this if the
+    /// store is able to perform both operations atomically.
+    ///
+    /// [`create_with_version`]: SessionStoreImpl::create_with_version
+    /// [`load`]: SessionStoreImpl::load
+    /// [`update_with_version`]: SessionStoreImpl::update_with_version
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey>
+    where
+        T: Send + Sync,
+    {
+        if self.load(session_key).await?.is_some() {
+            return Ok(CreateWithKey::Occupied);
+        }
+        let version = self
+            .update_with_version(session_key, data, ttl, version)
+            .await?;
+        Ok(CreateWithKey::Created(version))
+    }
+
     /// Returns a record containing the data and expiry corresponding to the
     /// session identified by the provided session key.
     ///
@@ -91,6 +160,227 @@ pub trait SessionStoreImpl<T>: 'static + Send + Sync {
     /// If no session identified by the session key exists, this should be a
     /// no-op with an `Ok` result.
     async fn delete(&self, session_key: &SessionKey) -> Result<()>;
+
+    /// Moves the session identified by the provided session key to a newly
+    /// generated session key, replacing its data and expiry with `data` and
+    /// `ttl`. Returns the new session key.
+    ///
+    /// After this returns successfully, the old session key should no longer
+    /// identify any session. If no session identified by the old session key
+    /// exists, a new session should be created anyway.
+    ///
+    /// The default implementation calls [`create`] followed by [`delete`].
+    /// Implementors should override this if the store is able to perform both
+    /// operations atomically.
+    ///
+    /// [`create`]: SessionStoreImpl::create
+    /// [`delete`]: SessionStoreImpl::delete
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey>
+    where
+        T: Sync,
+    {
+        let new_session_key = self.create(data, ttl).await?;
+        self.delete(session_key).await?;
+        Ok(new_session_key)
+    }
+
+    /// Updates the session identified by the provided session key, but only
+    /// if its current version is `version`.
+    ///
+    /// If the session was modified since it was loaded with `version`, it is
+    /// left untouched and [`UpdateIf::Conflict`] is returned with the current
+    /// record. If no session identified by the session key exists, or if it
+    /// has expired, [`UpdateIf::Conflict`] is returned with `None`.
+    ///
+    /// The default implementation calls [`update`] unconditionally, which is
+    /// correct for stores which don't track versions (that is, stores which
+    /// always return records with a `version` of `None`).
+    ///
+    /// [`update`]: SessionStoreImpl::update
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        self.update(session_key, data, ttl).await?;
+        Ok(UpdateIf::Updated(None))
+    }
+
+    /// Updates the session identified by the provided session key, recording
+    /// `version` as its version instead of generating a new one. Returns the
+    /// version the session was stored with.
+    ///
+    /// This is used by caching layers to keep the version of a cached session
+    /// in step with the backing store. Stores which derive the version from
+    /// the stored data should return the derived version rather than
+    /// `version`. The default implementation calls [`update`] and returns
+    /// `None`, since the version assigned by [`update`] isn't known.
+    ///
+    /// [`update`]: SessionStoreImpl::update
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>>
+    where
+        T: Sync,
+    {
+        let _ = version;
+        self.update(session_key, data, ttl).await?;
+        Ok(None)
+    }
+
+    /// Returns the records of the sessions identified by the provided session
+    /// keys, in the same order. Missing or expired sessions are `None`.
+    ///
+    /// The default implementation calls [`load`] for each session key.
+    ///
+    /// [`load`]: SessionStoreImpl::load
+    async fn load_many(&self, session_keys: &[SessionKey]) -> Result<Vec<Option<Record<T>>>>
+    where
+        T: Send,
+    {
+        let mut records = Vec::with_capacity(session_keys.len());
+        for session_key in session_keys {
+            records.push(self.load(session_key).await?);
+        }
+        Ok(records)
+    }
+
+    /// Deletes the sessions identified by the provided session keys.
+    ///
+    /// The default implementation calls [`delete`] for each session key.
+    ///
+    /// [`delete`]: SessionStoreImpl::delete
+    async fn delete_many(&self, session_keys: &[SessionKey]) -> Result<()> {
+        for session_key in session_keys {
+            self.delete(session_key).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes every expired session from the store, returning the number of
+    /// sessions removed.
+    ///
+    /// Stores which remove expired sessions on their own, such as Redis, can
+    /// rely on the default implementation, which does nothing and returns
+    /// `0`.
+    async fn purge_expired(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Returns the store's [`SessionIndex`] capability, if it has one.
+    ///
+    /// The default implementation returns `None`.
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        None
+    }
+}
+
+/// An optional capability of a session store to look up sessions by the
+/// principal they belong to, such as a user id.
+///
+/// This makes it possible to list every session of a user, or to log them out
+/// everywhere after a password reset. Sessions are associated with a principal
+/// by the middleware when [`Session::set_principal`] is called.
+///
+/// Deleting or expiring a session implicitly removes it from the index, so
+/// [`sessions`] only returns sessions which currently exist.
+///
+/// [`Session::set_principal`]: https://docs.rs/tower-sesh/latest/tower_sesh/struct.Session.html#method.set_principal
+/// [`sessions`]: SessionIndex::sessions
+#[async_trait]
+pub trait SessionIndex: 'static + Send + Sync {
+    /// Associates the session identified by the provided session key with a
+    /// principal.
+    ///
+    /// A session can only be associated with one principal; associating it
+    /// with a different principal has unspecified results.
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()>;
+
+    /// Returns the principal the session identified by the provided session
+    /// key is associated with, if any.
+    ///
+    /// The middleware uses this to carry the association over to the new
+    /// session key when a session's key is cycled.
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>>;
+
+    /// Returns every unexpired session associated with the principal, in no
+    /// particular order.
+    ///
+    /// Sessions are identified by their [`StorageId`], which can be passed to
+    /// [`delete_session`] to delete an individual session.
+    ///
+    /// [`delete_session`]: SessionIndex::delete_session
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>>;
+
+    /// Deletes the session identified by `id`, if it is associated with the
+    /// principal.
+    ///
+    /// Deleting a session which doesn't exist, or which belongs to another
+    /// principal, is not an error.
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()>;
+
+    /// Deletes every session associated with the principal.
+    async fn delete_sessions(&self, principal: &str) -> Result<()>;
+}
+
+/// A session returned by [`SessionIndex::sessions`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct IndexedSession {
+    pub id: StorageId,
+    pub ttl: Ttl,
+}
+
+impl IndexedSession {
+    #[inline]
+    pub fn new(id: StorageId, ttl: Ttl) -> IndexedSession {
+        IndexedSession { id, ttl }
+    }
+}
+
+/// Identifies a session by how it is stored, as listed by
+/// [`SessionIndex::sessions`].
+///
+/// If the store is configured with a [`KeyHasher`], sessions are stored under
+/// a hash of their session key, so the session key held by the client can't
+/// be recovered from a `StorageId`. For this reason, a `StorageId` isn't a
+/// [`SessionKey`]: it can't be used to load a session, only to delete one
+/// through [`SessionIndex::delete_session`].
+///
+/// [`KeyHasher`]: crate::key::KeyHasher
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct StorageId(String);
+
+/// Debug implementation does not leak secret.
+impl fmt::Debug for StorageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StorageId(..)")
+    }
+}
+
+impl StorageId {
+    /// Returns a `StorageId` from the string a store identifies a session by,
+    /// such as the Base64-encoded key the session is stored under.
+    #[inline]
+    pub fn new(id: impl Into<String>) -> StorageId {
+        StorageId(id.into())
+    }
+
+    /// Returns the string the store identifies the session by.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 /// A trait allowing a session store to override its source of randomness, for
@@ -191,15 +481,91 @@ pub trait SessionStoreRng<Rng: rand::CryptoRng + Send + 'static> {
 pub struct Record<T> {
     pub data: T,
     pub ttl: Ttl,
+
+    /// The version of the session when it was loaded, if the store tracks
+    /// versions. See [`Version`].
+    pub version: Option<Version>,
 }
 
 impl<T> Record<T> {
     #[inline]
     pub fn new(data: T, ttl: Ttl) -> Record<T> {
-        Record { data, ttl }
+        Record {
+            data,
+            ttl,
+            version: None,
+        }
+    }
+
+    /// Sets the version of this record.
+    #[inline]
+    pub fn with_version(mut self, version: Version) -> Record<T> {
+        self.version = Some(version);
+        self
     }
 }
 
+/// An opaque identifier for the state of a stored session.
+///
+/// A store which tracks versions assigns a new version whenever a session's
+/// data is written, so that two records for the same session have equal
+/// versions only if nothing was written to the session in between. Changing
+/// only a session's expiry doesn't change its version.
+///
+/// Versions are used for optimistic concurrency control: a session is written
+/// back with [`update_if`] using the version it was loaded with, and the write
+/// is rejected if the session was modified in the meantime.
+///
+/// [`update_if`]: SessionStoreImpl::update_if
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Version(u64);
+
+impl Version {
+    /// Creates a version from its raw representation.
+    #[inline]
+    pub const fn from_u64(n: u64) -> Version {
+        Version(n)
+    }
+
+    /// Returns the raw representation of this version.
+    #[inline]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// The result of a conditional update with [`update_if`].
+///
+/// [`update_if`]: SessionStoreImpl::update_if
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum UpdateIf<T> {
+    /// The session was updated. Contains the session's new version, if the
+    /// store tracks versions.
+    Updated(Option<Version>),
+
+    /// The session was modified or removed since it was loaded, so it was not
+    /// updated. Contains the current record, or `None` if the session no
+    /// longer exists.
+    Conflict(Option<Record<T>>),
+}
+
+/// The result of creating a session under a chosen session key with
+/// [`create_with_key`].
+///
+/// [`create_with_key`]: SessionStoreImpl::create_with_key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CreateWithKey {
+    /// The session was created. Contains the version it was stored with, if
+    /// the store tracks versions.
+    Created(Option<Version>),
+
+    /// A session identified by the session key already exists, so it was left
+    /// untouched.
+    Occupied,
+}
+
 /// An error returned by [`SessionStore`] methods.
 pub struct Error {
     kind: ErrorKind,
@@ -217,6 +583,34 @@ pub enum ErrorKind {
 
     /// Error occurred from serializing/deserializing.
     Serde(Box<dyn StdError + Send + Sync>),
+
+    /// The session data was stored with a different schema version, so it
+    /// must be migrated before it can be deserialized.
+    Outdated(Outdated),
+}
+
+/// Session data stored with a different schema version than the current one.
+///
+/// See [`ErrorKind::Outdated`].
+#[derive(Debug)]
+pub struct Outdated {
+    schema_version: u32,
+    payload: Vec<u8>,
+}
+
+impl Outdated {
+    /// Returns the schema version the session data was stored with.
+    #[inline]
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    /// Returns the serialized session data, without any schema version
+    /// header.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
 }
 
 impl Error {
@@ -241,6 +635,17 @@ impl Error {
         Error::new(ErrorKind::Serde(err.into()))
     }
 
+    /// Creates a new error for session data stored with a different schema
+    /// version than the current one.
+    #[cold]
+    #[must_use]
+    pub fn outdated(schema_version: u32, payload: Vec<u8>) -> Error {
+        Error::new(ErrorKind::Outdated(Outdated {
+            schema_version,
+            payload,
+        }))
+    }
+
     /// Creates a new error from a string containing a custom error message.
     #[cold]
     #[must_use]
@@ -279,6 +684,10 @@ impl fmt::Debug for Error {
                 builder.field("kind", &"Serde");
                 builder.field("source", err);
             }
+            Outdated(outdated) => {
+                builder.field("kind", &"Outdated");
+                builder.field("schema_version", &outdated.schema_version);
+            }
         }
 
         builder.finish()
@@ -292,6 +701,11 @@ impl fmt::Display for Error {
             Message(msg) => f.write_str(msg),
             Store(_) => f.write_str("session store error"),
             Serde(_) => f.write_str("session serialization error"),
+            Outdated(outdated) => write!(
+                f,
+                "session data has outdated schema version {}",
+                outdated.schema_version
+            ),
         }
     }
 }
@@ -303,6 +717,7 @@ impl StdError for Error {
             Message(_) => None,
             Store(err) => Some(err.as_ref()),
             Serde(err) => Some(err.as_ref()),
+            Outdated(_) => None,
         }
     }
 }
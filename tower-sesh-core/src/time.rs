@@ -1,6 +1,9 @@
 //! Utilities and types related to time.
 
-use time::{OffsetDateTime, UtcOffset};
+use std::{fmt, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use time::OffsetDateTime;
 
 /// An instant in time, represented as a date and time with a timezone offset.
 ///
@@ -14,22 +17,101 @@ const WEEK_IN_SECONDS: u32 = 60 * 60 * 24 * 7;
 /// Default expiry offset for a session, in seconds.
 pub const SESSION_EXPIRY_SECONDS_DEFAULT: u32 = 2 * WEEK_IN_SECONDS;
 
-/// Returns the current date and time with the local system's UTC offset.
-///
-/// If the system's UTC offset could not be determined, then UTC is used. In
-/// addition, a warning is logged if the `tracing` feature is enabled.
+/// Returns the current date and time in UTC, according to [`SystemClock`].
 #[inline]
 pub fn now() -> Ttl {
-    let t = Ttl::now_utc();
-    match UtcOffset::local_offset_at(t) {
-        Ok(offset) => t.to_offset(offset),
-        Err(_err) => {
-            warn!(
-                "failed to get system time zone; \
-                falling back to UTC \
-                (this may result in surprising bugs!)"
-            );
-            t
+    SystemClock.now()
+}
+
+/// A source of the current date and time, used to compute and check session
+/// expiry.
+///
+/// `SessionLayer` and the built-in session stores use [`SystemClock`] by
+/// default. Tests may use [`MockClock`] instead, to control the passage of
+/// time deterministically.
+pub trait Clock: 'static + Send + Sync + fmt::Debug {
+    /// Returns the current date and time in UTC.
+    fn now(&self) -> Ttl;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    #[inline]
+    fn now(&self) -> Ttl {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    #[inline]
+    fn now(&self) -> Ttl {
+        (**self).now()
+    }
+}
+
+/// A [`Clock`] which reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Ttl {
+        Ttl::now_utc()
+    }
+}
+
+/// A [`Clock`] which only moves forward when it is advanced manually.
+///
+/// Clones of a `MockClock` share the same time, so a clone can be passed to
+/// a session store or `SessionLayer` while the original is used to advance
+/// time.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tower_sesh_core::time::{Clock, MockClock};
+///
+/// let clock = MockClock::default();
+/// let start = clock.now();
+///
+/// clock.clone().advance(Duration::from_secs(60));
+/// assert_eq!(clock.now() - start, Duration::from_secs(60));
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<Ttl>>,
+}
+
+impl MockClock {
+    /// Creates a clock whose current time is `now`.
+    pub fn new(now: Ttl) -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(now.to_offset(time::UtcOffset::UTC))),
         }
     }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+
+    /// Sets the clock's current time to `now`.
+    pub fn set(&self, now: Ttl) {
+        *self.now.lock() = now.to_offset(time::UtcOffset::UTC);
+    }
+}
+
+impl Default for MockClock {
+    /// Creates a clock whose current time is the current system time.
+    fn default() -> Self {
+        MockClock::new(SystemClock.now())
+    }
+}
+
+impl Clock for MockClock {
+    #[inline]
+    fn now(&self) -> Ttl {
+        *self.now.lock()
+    }
 }
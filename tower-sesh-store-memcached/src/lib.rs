@@ -0,0 +1,701 @@
+//! The memcached store for [`tower-sesh`].
+//!
+//! [`tower-sesh`]: https://docs.rs/tower-sesh/latest/tower_sesh/
+//!
+//! # Storage
+//!
+//! Each session is stored in a single item, keyed by appending its
+//! Base64-encoded session key to the store's [key prefix]. The item holds a
+//! short header, recording the session's expiry and version, followed by the
+//! session data serialized with a [`Codec`].
+//!
+//! Items are given the session's expiry as an absolute Unix timestamp, so
+//! memcached evicts sessions once they expire, and [`purge_expired`] isn't
+//! needed. Since memcached may also evict sessions early when it runs out of
+//! memory, it's best suited to deployments where losing a session is an
+//! inconvenience rather than an error.
+//!
+//! The expiry in the header duplicates the item's expiry, which memcached
+//! only keeps to the second, by its own clock, and doesn't return with the
+//! item. The header is what lets the store return a session's exact expiry,
+//! and check it against the store's [clock]. The cost is that extending a
+//! session's expiry rewrites its item, rather than taking a single `touch`.
+//!
+//! [key prefix]: MemcachedStore::key_prefix
+//! [clock]: MemcachedStore::clock
+//! [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+//!
+//! # Multiple servers
+//!
+//! Sessions are distributed among the servers passed to
+//! [`MemcachedStore::new`] by consistent hashing, compatible with
+//! [libketama], so adding or removing a server only moves the sessions on
+//! the affected part of the hash ring. Every process sharing the servers must
+//! list the same servers, written the same way.
+//!
+//! [libketama]: https://github.com/RJ/ketama
+
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+#![warn(missing_debug_implementations)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![doc(test(
+    no_crate_inject,
+    attr(
+        deny(warnings, rust_2018_idioms, single_use_lifetimes),
+        allow(dead_code, unused_assignments, unused_variables)
+    )
+))]
+
+use std::{borrow::Cow, fmt, io, marker::PhantomData, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use pool::ServerPool;
+use protocol::{Item, Reply, Store};
+use rand::{rngs::ThreadRng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{CreateWithKey, Error, Result, SessionStoreImpl, UpdateIf, Version},
+    time::{Clock, SystemClock},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+pub use tower_sesh_core::codec;
+
+mod pool;
+mod protocol;
+
+/// A session store backed by one or more memcached servers.
+///
+/// See the [crate-level documentation](crate) for how sessions are stored.
+pub struct MemcachedStore<T, K: Codec = MessagePack> {
+    pool: ServerPool,
+    config: Config,
+    codec: K,
+    clock: Arc<dyn Clock>,
+
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    key_prefix: Cow<'static, str>,
+    hasher: Option<KeyHasher>,
+}
+
+const DEFAULT_KEY_PREFIX: &str = "session:";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            key_prefix: Cow::Borrowed(DEFAULT_KEY_PREFIX),
+            hasher: None,
+        }
+    }
+}
+
+/// The maximum number of attempts at an operation which can conflict with
+/// concurrent writers, or collide with an existing session key.
+const MAX_RETRIES: usize = 8;
+
+/// Layout of the header preceding the session data in an item:
+///
+/// ```text
+/// +----------------+----------------+-----------------+---------+
+/// | format version | expiry seconds | expiry nanosecs | version |
+/// +----------------+----------------+-----------------+---------+
+///         1                8                 4              8
+/// ```
+///
+/// The expiry is a Unix timestamp. All integers are big-endian.
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 1 + 8 + 4 + 8;
+
+/// Expiry times above this many seconds are treated by memcached as Unix
+/// timestamps, and those at or below it as relative to the current time.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+impl<T> MemcachedStore<T> {
+    /// Returns a store using the memcached servers at `servers`, with default
+    /// configuration values.
+    ///
+    /// Each server is given as `<host>:<port>`. Connections are opened when
+    /// they are first needed, so this doesn't check that the servers are
+    /// reachable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `servers` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tower_sesh_store_memcached::MemcachedStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// let store = MemcachedStore::<SessionData>::new(["10.0.0.1:11211", "10.0.0.2:11211"]);
+    /// ```
+    pub fn new<I>(servers: I) -> MemcachedStore<T>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let servers = servers.into_iter().map(Into::into).collect();
+
+        MemcachedStore {
+            pool: ServerPool::new(servers, DEFAULT_TIMEOUT),
+            config: Config::default(),
+            codec: MessagePack,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "test-util")]
+            rng: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K: Codec> MemcachedStore<T, K> {
+    /// Set the key prefix used to store sessions.
+    ///
+    /// When a session is stored, its memcached key is constructed by
+    /// appending the Base64-encoded session key to the prefix, e.g.
+    /// `session:ym5hy39HMVwYUJpPW6x_sQ`.
+    ///
+    /// Default is `"session:"`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix contains whitespace or control characters, or
+    /// is too long for the resulting keys to fit in memcached's 250-byte
+    /// limit.
+    pub fn key_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> MemcachedStore<T, K> {
+        let prefix = prefix.into();
+        assert!(
+            protocol::is_valid_key(&format!("{prefix}{}", "A".repeat(SessionKey::ENCODED_LEN))),
+            "invalid memcached key prefix: {prefix:?}"
+        );
+        self.config.key_prefix = prefix;
+        self
+    }
+
+    /// Store each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// The memcached key of a session is then constructed by appending the
+    /// Base64-encoded hash to the prefix, so the session keys held by clients
+    /// can't be recovered from the keys in memcached. See [`KeyHasher`] for
+    /// details.
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> MemcachedStore<T, K> {
+        self.config.hasher = Some(hasher);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data.
+    ///
+    /// Note that changing the codec of an existing deployment will make
+    /// previously stored sessions unreadable.
+    ///
+    /// Default is [`MessagePack`].
+    pub fn codec<K2: Codec>(self, codec: K2) -> MemcachedStore<T, K2> {
+        MemcachedStore {
+            pool: self.pool,
+            config: self.config,
+            codec,
+            clock: self.clock,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set the [`Clock`] used to check whether sessions have expired.
+    ///
+    /// memcached expires sessions by its own clock, with a precision of one
+    /// second, so this only determines whether a session which memcached
+    /// hasn't evicted yet is treated as expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> MemcachedStore<T, K> {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set how long a request to a server may take, including connecting to
+    /// it, before it fails.
+    ///
+    /// Default is 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> MemcachedStore<T, K> {
+        self.pool.set_timeout(timeout);
+        self
+    }
+}
+
+impl<T, K: Codec> fmt::Debug for MemcachedStore<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemcachedStore")
+            .field("pool", &self.pool)
+            .field("config", &self.config)
+            .field("codec", &std::any::type_name::<K>())
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+/// A session as it is stored in memcached.
+struct Entry {
+    ttl: Ttl,
+    version: Version,
+    data: Vec<u8>,
+}
+
+impl<T, K: Codec> MemcachedStore<T, K> {
+    fn memcached_key(&self, session_key: &SessionKey) -> String {
+        let mut key = String::with_capacity(self.config.key_prefix.len() + SessionKey::ENCODED_LEN);
+        key.push_str(&self.config.key_prefix);
+        match &self.config.hasher {
+            Some(hasher) => key.push_str(&hasher.hash(session_key).encode()),
+            None => key.push_str(&session_key.encode()),
+        }
+        key
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn to_record(&self, entry: Entry) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.codec.decode(&entry.data)?;
+        Ok(Record::new(data, entry.ttl).with_version(entry.version))
+    }
+
+    /// Stores an item holding a session.
+    async fn store(&self, command: Store, key: &str, entry: &Entry) -> Result<Reply> {
+        let item = encode_item(entry);
+        let exptime = exptime(entry.ttl);
+
+        self.pool
+            .with_connection(key, |mut conn| async move {
+                let stored = conn.store(command, key, exptime, &item).await?;
+                Ok((conn, stored))
+            })
+            .await
+            .map_err(Error::store)
+    }
+
+    /// Fetches the unexpired session stored under `key`, along with the CAS
+    /// value of its item.
+    async fn fetch(&self, key: &str) -> Result<Option<(Entry, u64)>> {
+        let item = self
+            .pool
+            .with_connection(key, |mut conn| async move {
+                let item = conn.gets(key).await?;
+                Ok((conn, item))
+            })
+            .await
+            .map_err(Error::store)?;
+
+        let Some(Item { data, cas }) = item else {
+            return Ok(None);
+        };
+        let entry = decode_item(data)?;
+        if entry.ttl < self.clock.now() {
+            return Ok(None);
+        }
+
+        Ok(Some((entry, cas)))
+    }
+
+    /// Like [`fetch`], but also updates the expiry of the item to `ttl` with
+    /// `gats`, in the same round trip. The expiry in the session's header is
+    /// left as it was.
+    ///
+    /// [`fetch`]: MemcachedStore::fetch
+    async fn fetch_and_touch(&self, key: &str, ttl: Ttl) -> Result<Option<(Entry, u64)>> {
+        let item = self
+            .pool
+            .with_connection(key, |mut conn| async move {
+                let item = conn.gats(exptime(ttl), key).await?;
+                Ok((conn, item))
+            })
+            .await
+            .map_err(Error::store)?;
+
+        let Some(Item { data, cas }) = item else {
+            return Ok(None);
+        };
+        let entry = decode_item(data)?;
+        if entry.ttl < self.clock.now() {
+            // Don't keep the expired session around until `ttl`
+            let exptime = exptime(entry.ttl);
+            self.pool
+                .with_connection(key, |mut conn| async move {
+                    conn.touch(key, exptime).await?;
+                    Ok((conn, ()))
+                })
+                .await
+                .map_err(Error::store)?;
+            return Ok(None);
+        }
+
+        Ok(Some((entry, cas)))
+    }
+
+    /// Stores a session under a randomly generated session key with `add`,
+    /// retrying if the key is taken, and returns the session key.
+    async fn add(&self, entry: &Entry) -> Result<SessionKey> {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            let key = self.memcached_key(&session_key);
+
+            match self.store(Store::Add, &key, entry).await? {
+                Reply::Stored => return Ok(session_key),
+                Reply::NotStored => continue,
+                reply => return Err(err_unexpected_reply("add", reply)),
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+}
+
+impl<T, K: Codec> SessionStore<T> for MemcachedStore<T, K> where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned
+{
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionStoreImpl<T> for MemcachedStore<T, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        let entry = Entry {
+            ttl,
+            version,
+            data: self.serialize(data)?,
+        };
+
+        let session_key = self.add(&entry).await?;
+        Ok((session_key, Some(version)))
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let key = self.memcached_key(session_key);
+        let entry = Entry {
+            ttl,
+            version,
+            data: self.serialize(data)?,
+        };
+
+        match self.store(Store::Add, &key, &entry).await? {
+            Reply::Stored => Ok(CreateWithKey::Created(Some(version))),
+            Reply::NotStored => Ok(CreateWithKey::Occupied),
+            reply => Err(err_unexpected_reply("add", reply)),
+        }
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let key = self.memcached_key(session_key);
+
+        self.fetch(&key)
+            .await?
+            .map(|(entry, _)| self.to_record(entry))
+            .transpose()
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.update_with_version(session_key, data, ttl, new_version())
+            .await?;
+        Ok(())
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        let key = self.memcached_key(session_key);
+
+        // `touch` alone would leave a stale expiry in the item's header, which
+        // `load` would then take the session to have expired by (see "Storage"
+        // in the crate docs for why the header keeps an expiry). The item's
+        // expiry is updated with `gats` as it's fetched, so the session lives
+        // on even if the rewrite fails, then the item is rewritten with `cas`
+        // to update its header, which doesn't lose concurrent updates
+
+        for _ in 0..MAX_RETRIES {
+            let Some((entry, cas)) = self.fetch_and_touch(&key, ttl).await? else {
+                return Ok(());
+            };
+
+            match self
+                .store(Store::Cas(cas), &key, &Entry { ttl, ..entry })
+                .await?
+            {
+                Reply::Stored | Reply::NotFound => return Ok(()),
+                Reply::Exists => continue,
+                reply => return Err(err_unexpected_reply("cas", reply)),
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        let key = &*self.memcached_key(session_key);
+
+        self.pool
+            .with_connection(key, |mut conn| async move {
+                conn.delete(key).await?;
+                Ok((conn, ()))
+            })
+            .await
+            .map_err(Error::store)
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let entry = Entry {
+            ttl,
+            version: new_version(),
+            data: self.serialize(data)?,
+        };
+
+        // The old and new keys may live on different servers, so this can't
+        // be atomic. Storing the new session first means that a failure
+        // never loses the session.
+        let new_session_key = self.add(&entry).await?;
+        self.delete(session_key).await?;
+
+        Ok(new_session_key)
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let key = self.memcached_key(session_key);
+        let entry = Entry {
+            ttl,
+            version: new_version(),
+            data: self.serialize(data)?,
+        };
+
+        for _ in 0..MAX_RETRIES {
+            let Some((current, cas)) = self.fetch(&key).await? else {
+                return Ok(UpdateIf::Conflict(None));
+            };
+            if current.version != version {
+                return self
+                    .to_record(current)
+                    .map(|record| UpdateIf::Conflict(Some(record)));
+            }
+
+            match self.store(Store::Cas(cas), &key, &entry).await? {
+                Reply::Stored => return Ok(UpdateIf::Updated(Some(entry.version))),
+                Reply::NotFound => return Ok(UpdateIf::Conflict(None)),
+                // Modified since it was fetched; fetch the current session
+                Reply::Exists => continue,
+                reply => return Err(err_unexpected_reply("cas", reply)),
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let key = self.memcached_key(session_key);
+        let entry = Entry {
+            ttl,
+            version,
+            data: self.serialize(data)?,
+        };
+
+        match self.store(Store::Set, &key, &entry).await? {
+            Reply::Stored => Ok(Some(version)),
+            reply => Err(err_unexpected_reply("set", reply)),
+        }
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<T, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for MemcachedStore<T, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+/// Returns a version for newly written session data.
+///
+/// Versions are random, since there's no counter shared by every process
+/// using the servers.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+/// Returns the expiry of an item holding a session which expires at `ttl`.
+///
+/// memcached expires items with a precision of one second, so this rounds up
+/// to avoid evicting sessions early.
+fn exptime(ttl: Ttl) -> i64 {
+    let seconds = ttl
+        .unix_timestamp()
+        .saturating_add(i64::from(ttl.nanosecond() > 0));
+
+    if seconds <= MAX_RELATIVE_EXPTIME {
+        // A timestamp this small would be taken as a relative expiry, but it's
+        // long past anyway. A negative expiry expires the item immediately.
+        -1
+    } else {
+        seconds
+    }
+}
+
+fn encode_item(entry: &Entry) -> Vec<u8> {
+    let mut item = Vec::with_capacity(HEADER_LEN + entry.data.len());
+    item.push(FORMAT_VERSION);
+    item.extend_from_slice(&entry.ttl.unix_timestamp().to_be_bytes());
+    item.extend_from_slice(&entry.ttl.nanosecond().to_be_bytes());
+    item.extend_from_slice(&entry.version.as_u64().to_be_bytes());
+    item.extend_from_slice(&entry.data);
+    item
+}
+
+fn decode_item(mut item: Vec<u8>) -> Result<Entry> {
+    if item.len() < HEADER_LEN {
+        return Err(Error::message("session item is truncated"));
+    }
+    if item[0] != FORMAT_VERSION {
+        return Err(Error::message(format!(
+            "unsupported session item format version {}",
+            item[0]
+        )));
+    }
+
+    let seconds = i64::from_be_bytes(item[1..9].try_into().unwrap());
+    let nanoseconds = u32::from_be_bytes(item[9..13].try_into().unwrap());
+    let version = u64::from_be_bytes(item[13..21].try_into().unwrap());
+    let ttl = Ttl::from_unix_timestamp(seconds)
+        .and_then(|ttl| ttl.replace_nanosecond(nanoseconds))
+        .map_err(|err| Error::message(format!("invalid expiry: {}", err)))?;
+
+    item.drain(..HEADER_LEN);
+    Ok(Entry {
+        ttl,
+        version: Version::from_u64(version),
+        data: item,
+    })
+}
+
+#[cold]
+fn err_unexpected_reply(command: &str, reply: Reply) -> Error {
+    Error::store(io::Error::other(format!(
+        "unexpected reply to `{command}` from memcached: {reply:?}"
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        fn require_traits<T: SessionStore<()> + Send + Sync + 'static>() {}
+
+        require_traits::<MemcachedStore<()>>();
+    }
+
+    #[test]
+    fn item_round_trips() {
+        let entry = Entry {
+            ttl: Ttl::from_unix_timestamp(1_700_000_000)
+                .unwrap()
+                .replace_nanosecond(999_999_999)
+                .unwrap(),
+            version: Version::from_u64(u64::MAX),
+            data: b"data".to_vec(),
+        };
+
+        let decoded = decode_item(encode_item(&entry)).unwrap();
+        assert_eq!(decoded.ttl, entry.ttl);
+        assert_eq!(decoded.version, entry.version);
+        assert_eq!(decoded.data, entry.data);
+    }
+
+    #[test]
+    fn exptime_rounds_up_to_the_second() {
+        let ttl = Ttl::from_unix_timestamp(1_700_000_000).unwrap();
+        assert_eq!(exptime(ttl), 1_700_000_000);
+        assert_eq!(exptime(ttl.replace_nanosecond(1).unwrap()), 1_700_000_001);
+    }
+
+    #[test]
+    fn exptime_is_never_relative() {
+        assert_eq!(exptime(Ttl::UNIX_EPOCH), -1);
+        assert_eq!(
+            exptime(Ttl::from_unix_timestamp(MAX_RELATIVE_EXPTIME).unwrap()),
+            -1
+        );
+        assert_eq!(exptime(Ttl::from_unix_timestamp(-1_000_000).unwrap()), -1);
+    }
+}
@@ -0,0 +1,193 @@
+//! Connections to a pool of memcached servers.
+
+use std::{fmt, future::Future, io, time::Duration};
+
+use md5::{Digest, Md5};
+
+use crate::protocol::Connection;
+
+/// The number of points each server is given on the hash ring.
+///
+/// Each MD5 digest yields four points, so this is compatible with
+/// [libketama], as used by most memcached clients.
+///
+/// [libketama]: https://github.com/RJ/ketama
+const POINTS_PER_SERVER: usize = 160;
+
+/// The maximum number of idle connections kept open to each server.
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+/// A pool of memcached servers, which distributes keys among the servers by
+/// consistent hashing.
+pub(crate) struct ServerPool {
+    servers: Vec<Server>,
+    ring: Vec<(u32, usize)>,
+    timeout: Duration,
+}
+
+struct Server {
+    addr: String,
+    idle: parking_lot::Mutex<Vec<Connection>>,
+}
+
+impl ServerPool {
+    /// # Panics
+    ///
+    /// Panics if `addrs` is empty.
+    pub(crate) fn new(addrs: Vec<String>, timeout: Duration) -> ServerPool {
+        assert!(!addrs.is_empty(), "at least one server is required");
+
+        let ring = ring(&addrs);
+        let servers = addrs
+            .into_iter()
+            .map(|addr| Server {
+                addr,
+                idle: parking_lot::Mutex::new(Vec::new()),
+            })
+            .collect();
+
+        ServerPool {
+            servers,
+            ring,
+            timeout,
+        }
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Runs `f` with a connection to the server responsible for `key`.
+    ///
+    /// The connection is returned to the pool only if `f` succeeds, since a
+    /// failed request may leave unread data on the connection.
+    pub(crate) async fn with_connection<U, F, Fut>(&self, key: &str, f: F) -> io::Result<U>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = io::Result<(Connection, U)>>,
+    {
+        let server = &self.servers[server_for(&self.ring, key)];
+
+        let request = async {
+            let idle = server.idle.lock().pop();
+            let conn = match idle {
+                Some(conn) => conn,
+                None => Connection::connect(&server.addr).await?,
+            };
+            f(conn).await
+        };
+        let (conn, output) =
+            tokio::time::timeout(self.timeout, request)
+                .await
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("request to memcached server {} timed out", server.addr),
+                    )
+                })??;
+
+        let mut idle = server.idle.lock();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(conn);
+        }
+
+        Ok(output)
+    }
+}
+
+impl fmt::Debug for ServerPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerPool")
+            .field(
+                "servers",
+                &self.servers.iter().map(|s| &s.addr).collect::<Vec<_>>(),
+            )
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Builds the hash ring, mapping points on the ring to indices of `addrs`.
+fn ring(addrs: &[String]) -> Vec<(u32, usize)> {
+    let mut ring = Vec::with_capacity(addrs.len() * POINTS_PER_SERVER);
+    for (index, addr) in addrs.iter().enumerate() {
+        for i in 0..POINTS_PER_SERVER / 4 {
+            let digest = Md5::digest(format!("{addr}-{i}"));
+            for chunk in digest.chunks_exact(4) {
+                let point = u32::from_le_bytes(chunk.try_into().unwrap());
+                ring.push((point, index));
+            }
+        }
+    }
+    ring.sort_unstable();
+    ring
+}
+
+/// Returns the index of the server responsible for `key`: the server owning
+/// the first point on the ring at or after the key's hash.
+fn server_for(ring: &[(u32, usize)], key: &str) -> usize {
+    let digest = Md5::digest(key);
+    let hash = u32::from_le_bytes(digest[..4].try_into().unwrap());
+
+    let i = ring.partition_point(|&(point, _)| point < hash);
+    ring.get(i).unwrap_or(&ring[0]).1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addrs(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("10.0.0.{i}:11211")).collect()
+    }
+
+    fn keys() -> impl Iterator<Item = String> {
+        (0..10_000).map(|i| format!("session:{i}"))
+    }
+
+    #[test]
+    fn single_server_owns_every_key() {
+        let ring = ring(&addrs(1));
+        assert!(keys().all(|key| server_for(&ring, &key) == 0));
+    }
+
+    #[test]
+    fn keys_are_spread_across_servers() {
+        let ring = ring(&addrs(4));
+
+        let mut counts = [0; 4];
+        for key in keys() {
+            counts[server_for(&ring, &key)] += 1;
+        }
+        for count in counts {
+            assert!((1_500..=3_500).contains(&count), "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn adding_a_server_only_moves_keys_to_it() {
+        let before = ring(&addrs(4));
+        let after = ring(&addrs(5));
+
+        let mut moved = 0;
+        for key in keys() {
+            let (old, new) = (server_for(&before, &key), server_for(&after, &key));
+            if old != new {
+                assert_eq!(new, 4);
+                moved += 1;
+            }
+        }
+        assert!((1_000..=3_000).contains(&moved), "{moved}");
+    }
+
+    #[test]
+    fn mapping_is_stable() {
+        // Every process sharing the servers must agree on where keys live, so
+        // the mapping must never change between versions of this crate
+        let ring = ring(&addrs(3));
+        let servers: Vec<_> = (0..8)
+            .map(|i| server_for(&ring, &format!("session:{i}")))
+            .collect();
+        assert_eq!(servers, [2, 1, 0, 0, 1, 1, 0, 1]);
+    }
+}
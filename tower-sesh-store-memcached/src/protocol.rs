@@ -0,0 +1,213 @@
+//! A minimal client for the memcached [text protocol].
+//!
+//! Only the commands used by the store are implemented.
+//!
+//! [text protocol]: https://github.com/memcached/memcached/blob/master/doc/protocol.txt
+
+use std::io;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+/// The maximum length of a key, in bytes.
+pub(crate) const MAX_KEY_LEN: usize = 250;
+
+/// A connection to a memcached server.
+#[derive(Debug)]
+pub(crate) struct Connection {
+    stream: BufStream<TcpStream>,
+    line: String,
+}
+
+/// The storage commands.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Store {
+    /// Store the item, unless it already exists.
+    Add,
+    /// Store the item unconditionally.
+    Set,
+    /// Store the item, if it hasn't been modified since it was fetched with the
+    /// given CAS value.
+    Cas(u64),
+}
+
+/// The reply to a storage command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reply {
+    /// The item was stored.
+    Stored,
+    /// The item wasn't stored, because the condition of an `add` wasn't met.
+    NotStored,
+    /// The item wasn't stored, because it was modified after it was fetched.
+    Exists,
+    /// The item wasn't stored, because it no longer exists.
+    NotFound,
+}
+
+/// An item fetched with `gets` or `gats`.
+#[derive(Debug)]
+pub(crate) struct Item {
+    pub(crate) data: Vec<u8>,
+    pub(crate) cas: u64,
+}
+
+impl Connection {
+    pub(crate) async fn connect(addr: &str) -> io::Result<Connection> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream: BufStream::new(stream),
+            line: String::new(),
+        })
+    }
+
+    /// Stores an item, with an expiry in the format expected by memcached.
+    pub(crate) async fn store(
+        &mut self,
+        command: Store,
+        key: &str,
+        exptime: i64,
+        data: &[u8],
+    ) -> io::Result<Reply> {
+        let header = match command {
+            Store::Add => format!("add {key} 0 {exptime} {}\r\n", data.len()),
+            Store::Set => format!("set {key} 0 {exptime} {}\r\n", data.len()),
+            Store::Cas(cas) => format!("cas {key} 0 {exptime} {} {cas}\r\n", data.len()),
+        };
+        self.stream.write_all(header.as_bytes()).await?;
+        self.stream.write_all(data).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+
+        match self.read_line().await? {
+            "STORED" => Ok(Reply::Stored),
+            "NOT_STORED" => Ok(Reply::NotStored),
+            "EXISTS" => Ok(Reply::Exists),
+            "NOT_FOUND" => Ok(Reply::NotFound),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    /// Fetches an item along with its CAS value.
+    pub(crate) async fn gets(&mut self, key: &str) -> io::Result<Option<Item>> {
+        self.send(&format!("gets {key}\r\n")).await?;
+        self.read_item().await
+    }
+
+    /// Fetches an item along with its CAS value, and updates its expiry, in
+    /// the format expected by memcached.
+    pub(crate) async fn gats(&mut self, exptime: i64, key: &str) -> io::Result<Option<Item>> {
+        self.send(&format!("gats {exptime} {key}\r\n")).await?;
+        self.read_item().await
+    }
+
+    /// Reads the reply to `gets` or `gats`.
+    async fn read_item(&mut self) -> io::Result<Option<Item>> {
+        let line = self.read_line().await?;
+        if line == "END" {
+            return Ok(None);
+        }
+
+        // VALUE <key> <flags> <bytes> <cas unique>
+        let mut parts = line.split(' ');
+        let (Some("VALUE"), Some(_key), Some(_flags), Some(len), Some(cas), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return Err(unexpected_reply(line));
+        };
+        let len: usize = len.parse().map_err(|_| unexpected_reply(line))?;
+        let cas: u64 = cas.parse().map_err(|_| unexpected_reply(line))?;
+
+        let mut data = vec![0; len + 2];
+        self.stream.read_exact(&mut data).await?;
+        if !data.ends_with(b"\r\n") {
+            return Err(invalid_data("data block isn't terminated by CRLF"));
+        }
+        data.truncate(len);
+
+        match self.read_line().await? {
+            "END" => Ok(Some(Item { data, cas })),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    /// Updates the expiry of an item, in the format expected by memcached,
+    /// returning whether it existed.
+    pub(crate) async fn touch(&mut self, key: &str, exptime: i64) -> io::Result<bool> {
+        self.send(&format!("touch {key} {exptime}\r\n")).await?;
+
+        match self.read_line().await? {
+            "TOUCHED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    /// Deletes an item, returning whether it existed.
+    pub(crate) async fn delete(&mut self, key: &str) -> io::Result<bool> {
+        self.send(&format!("delete {key}\r\n")).await?;
+
+        match self.read_line().await? {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    async fn send(&mut self, command: &str) -> io::Result<()> {
+        self.stream.write_all(command.as_bytes()).await?;
+        self.stream.flush().await
+    }
+
+    /// Reads a line of the reply, without the trailing CRLF.
+    async fn read_line(&mut self) -> io::Result<&str> {
+        self.line.clear();
+        if self.stream.read_line(&mut self.line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let Some(line) = self.line.strip_suffix("\r\n") else {
+            return Err(invalid_data("reply isn't terminated by CRLF"));
+        };
+        Ok(line)
+    }
+}
+
+/// Returns whether `key` can be used as a memcached key.
+///
+/// Keys must be at most 250 bytes long, and must not contain whitespace or
+/// control characters.
+pub(crate) fn is_valid_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_LEN && key.bytes().all(|b| b.is_ascii_graphic() || b >= 0x80)
+}
+
+#[cold]
+fn unexpected_reply(reply: &str) -> io::Error {
+    // Covers `ERROR`, `CLIENT_ERROR <message>`, and `SERVER_ERROR <message>`
+    io::Error::other(format!("unexpected reply from memcached: {reply:?}"))
+}
+
+#[cold]
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_keys() {
+        assert!(is_valid_key("session:ym5hy39HMVwYUJpPW6x_sQ"));
+        assert!(is_valid_key(&"a".repeat(MAX_KEY_LEN)));
+        assert!(!is_valid_key(&"a".repeat(MAX_KEY_LEN + 1)));
+        assert!(!is_valid_key("session key"));
+        assert!(!is_valid_key("session\r\nkey"));
+    }
+}
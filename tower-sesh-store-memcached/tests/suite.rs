@@ -0,0 +1,138 @@
+#![cfg(feature = "test-util")]
+
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sesh_store_memcached::MemcachedStore;
+
+mod server {
+    use std::{
+        env,
+        net::{TcpListener, TcpStream},
+        process::{Child, Command, Stdio},
+        thread,
+        time::Duration,
+    };
+
+    use anyhow::{bail, Context};
+
+    /// Spawns a `memcached` server listening on a random local port.
+    ///
+    /// The binary is looked up in `PATH`, unless the `MEMCACHED` environment
+    /// variable is set to its path.
+    pub fn spawn() -> anyhow::Result<ServerGuard> {
+        let program = env::var_os("MEMCACHED").unwrap_or_else(|| "memcached".into());
+
+        // Reserve a free port. This is racy, but another process is unlikely
+        // to take the port before memcached does.
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+        let child = Command::new(&program)
+            .args(["--listen=127.0.0.1", "--udp-port=0"])
+            .arg(format!("--port={port}"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("failed to spawn {program:?}"))?;
+
+        // If we return early, this kills the server
+        let mut guard = ServerGuard { child, port };
+
+        let mut attempts = 0;
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            if let Some(status) = guard.child.try_wait()? {
+                bail!("memcached exited with {status}");
+            }
+            if attempts >= 100 {
+                bail!("timed out waiting for memcached to listen on port {port}");
+            }
+            attempts += 1;
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Ok(guard)
+    }
+
+    #[must_use = "if unused the server will immediately be killed"]
+    #[derive(Debug)]
+    pub struct ServerGuard {
+        child: Child,
+        pub port: u16,
+    }
+
+    impl ServerGuard {
+        pub fn addr(&self) -> String {
+            format!("127.0.0.1:{}", self.port)
+        }
+    }
+
+    impl Drop for ServerGuard {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+fn store<T>(servers: &[server::ServerGuard]) -> MemcachedStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    MemcachedStore::new(servers.iter().map(server::ServerGuard::addr))
+}
+
+mod memcached_store {
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::{server, store};
+
+    test_suite! {
+        guard: servers = [server::spawn().unwrap()],
+        clock: clock = MockClock::default(),
+        store: store(&servers).clock(clock.clone()),
+    }
+}
+
+mod memcached_store_system_clock {
+    use tower_sesh_test::test_suite;
+
+    use super::{server, store};
+
+    test_suite! {
+        guard: servers = [server::spawn().unwrap()],
+        store: store(&servers),
+    }
+}
+
+mod memcached_store_multiple_servers {
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::{server, store};
+
+    test_suite! {
+        guard: servers = [
+            server::spawn().unwrap(),
+            server::spawn().unwrap(),
+            server::spawn().unwrap(),
+        ],
+        clock: clock = MockClock::default(),
+        store: store(&servers).clock(clock.clone()),
+    }
+}
+
+mod memcached_caching_store {
+    use tower_sesh::store::{CachingStore, MemoryStore};
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::{server, store};
+
+    test_suite! {
+        guard: servers = [server::spawn().unwrap()],
+        clock: clock = MockClock::default(),
+        store: CachingStore::from_cache_and_store(
+            MemoryStore::new().clock(clock.clone()),
+            store(&servers).clock(clock.clone()),
+        ),
+    }
+}
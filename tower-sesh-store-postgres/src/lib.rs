@@ -0,0 +1,918 @@
+//! The PostgreSQL store for [`tower-sesh`].
+//!
+//! [`tower-sesh`]: https://docs.rs/tower-sesh/latest/tower_sesh/
+//!
+//! # Schema
+//!
+//! [`PostgresStore::migrate`] creates the following tables in the store's
+//! [schema](PostgresStore::schema) (`public` by default), named after the
+//! store's [table name](PostgresStore::table_name) (`tower_sesh_sessions` by
+//! default):
+//!
+//! - `tower_sesh_sessions` holds the sessions. Each session is stored under
+//!   its Base64-encoded session key in the `id` column, with its serialized
+//!   data in the `data` column (`bytea`), its version, and its expiry in the
+//!   `expires_at` column (`timestamptz`, which is indexed).
+//! - `tower_sesh_sessions_principals` indexes sessions by principal. See
+//!   [`SessionIndex`].
+//! - `tower_sesh_sessions_migrations` records the migrations which have been
+//!   applied, so that future versions of this crate can upgrade the schema
+//!   in place.
+//!
+//! Expired sessions are never loaded, but they aren't removed from the
+//! database until [`purge_expired`] is called. Call it periodically, or let a
+//! [`Reaper`] spawned by [`PostgresStore::spawn_reaper`] do so.
+//!
+//! [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+//!
+//! # TLS
+//!
+//! This crate doesn't enable TLS support in [`sqlx`]. To connect to a server
+//! over TLS, depend on `sqlx` directly with one of its `tls-*` features
+//! enabled.
+
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+#![warn(missing_debug_implementations)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![doc(test(
+    no_crate_inject,
+    attr(
+        deny(warnings, rust_2018_idioms, single_use_lifetimes),
+        allow(dead_code, unused_assignments, unused_variables)
+    )
+))]
+
+use std::{borrow::Cow, fmt, marker::PhantomData, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use rand::{rngs::ThreadRng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    postgres::{PgPool, PgRow},
+    Row,
+};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{
+        CreateWithKey, Error, IndexedSession, Result, SessionIndex, SessionStoreImpl, StorageId,
+        UpdateIf, Version,
+    },
+    time::{Clock, SystemClock},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+pub use sqlx;
+pub use tower_sesh_core::{codec, reaper::Reaper};
+
+/// A session store backed by a PostgreSQL database.
+///
+/// See the [crate-level documentation](crate#schema) for the tables used by
+/// the store.
+pub struct PostgresStore<T, K: Codec = MessagePack> {
+    pool: PgPool,
+    config: Config,
+    codec: K,
+    clock: Arc<dyn Clock>,
+
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    schema: Cow<'static, str>,
+    table_name: Cow<'static, str>,
+    hasher: Option<KeyHasher>,
+}
+
+const DEFAULT_SCHEMA: &str = "public";
+const DEFAULT_TABLE_NAME: &str = "tower_sesh_sessions";
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            schema: Cow::Borrowed(DEFAULT_SCHEMA),
+            table_name: Cow::Borrowed(DEFAULT_TABLE_NAME),
+            hasher: None,
+        }
+    }
+}
+
+/// The schema migrations, in the order they are applied.
+///
+/// `{sessions}` and `{principals}` are replaced with the qualified table
+/// names, and `{index_prefix}` with the prefix of index names. Migrations must
+/// never be changed once released; change the schema by appending a migration
+/// instead.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE {sessions} (
+        id TEXT PRIMARY KEY,
+        data BYTEA NOT NULL,
+        expires_at TIMESTAMPTZ NOT NULL,
+        version BIGINT NOT NULL
+    );
+    CREATE INDEX "{index_prefix}_expires_at" ON {sessions} (expires_at);
+    CREATE TABLE {principals} (
+        principal TEXT NOT NULL,
+        id TEXT NOT NULL,
+        PRIMARY KEY (principal, id)
+    );
+    CREATE INDEX "{index_prefix}_principals_id" ON {principals} (id);
+"#];
+
+impl<T> PostgresStore<T> {
+    /// Connects to a PostgreSQL server and returns a store with default
+    /// configuration values, creating its tables if they don't exist.
+    ///
+    /// `url` should be a string in the following format:
+    ///
+    /// ```not_rust
+    /// postgres://[<user>[:<password>]@]<host>[:<port>][/<database>][?<options>]
+    /// ```
+    ///
+    /// See [`PgConnectOptions`](sqlx::postgres::PgConnectOptions) for the
+    /// supported options.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_postgres::PostgresStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = PostgresStore::<SessionData>::open("postgres://localhost/app").await?;
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub async fn open(url: &str) -> sqlx::Result<PostgresStore<T>> {
+        let pool = PgPool::connect(url).await?;
+
+        let store = PostgresStore::with_pool(pool);
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    /// Returns a store using an existing connection pool, with default
+    /// configuration values.
+    ///
+    /// Unlike [`open`], this doesn't create the store's tables. Call
+    /// [`migrate`] once the store is configured.
+    ///
+    /// [`open`]: PostgresStore::open
+    /// [`migrate`]: PostgresStore::migrate
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sqlx::PgPool;
+    /// use tower_sesh_store_postgres::PostgresStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let pool = PgPool::connect("postgres://localhost/app").await?;
+    /// let store = PostgresStore::<SessionData>::with_pool(pool)
+    ///     .schema("auth")
+    ///     .table_name("sessions");
+    /// store.migrate().await?;
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub fn with_pool(pool: PgPool) -> PostgresStore<T> {
+        PostgresStore {
+            pool,
+            config: Config::default(),
+            codec: MessagePack,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "test-util")]
+            rng: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K: Codec> PostgresStore<T, K> {
+    /// Set the schema containing the store's tables.
+    ///
+    /// [`migrate`](PostgresStore::migrate) creates the schema if it doesn't
+    /// exist.
+    ///
+    /// Default is `"public"`.
+    pub fn schema(mut self, schema: impl Into<Cow<'static, str>>) -> PostgresStore<T, K> {
+        self.config.schema = schema.into();
+        self
+    }
+
+    /// Set the name of the table used to store sessions.
+    ///
+    /// The tables used to index sessions and to record migrations are named
+    /// by appending `_principals` and `_migrations` to this name.
+    ///
+    /// Default is `"tower_sesh_sessions"`.
+    pub fn table_name(mut self, table_name: impl Into<Cow<'static, str>>) -> PostgresStore<T, K> {
+        self.config.table_name = table_name.into();
+        self
+    }
+
+    /// Store each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// The `id` of a session is then the Base64-encoded hash, so the session
+    /// keys held by clients can't be recovered from the database. See
+    /// [`KeyHasher`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_core::key::KeyHasher;
+    /// use tower_sesh_store_postgres::PostgresStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// fn secret() -> Vec<u8> {
+    ///     // TODO: Where do you get a secret?
+    /// # vec![0; 32]
+    /// }
+    ///
+    /// # tokio_test::block_on(async {
+    /// let store = PostgresStore::<SessionData>::open("postgres://localhost/app")
+    ///     .await?
+    ///     .hash_keys(KeyHasher::new(&secret()));
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> PostgresStore<T, K> {
+        self.config.hasher = Some(hasher);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data.
+    ///
+    /// Note that changing the codec of an existing deployment will make
+    /// previously stored sessions unreadable.
+    ///
+    /// Large payloads can be compressed by wrapping the codec in
+    /// [`Compressed`](codec::Compressed), which requires the `zstd` or `lz4`
+    /// feature.
+    ///
+    /// Default is [`MessagePack`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "json")] {
+    /// use tower_sesh_store_postgres::{codec::Json, PostgresStore};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = PostgresStore::<SessionData>::open("postgres://localhost/app")
+    ///     .await?
+    ///     .codec(Json);
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// # }
+    /// ```
+    pub fn codec<K2: Codec>(self, codec: K2) -> PostgresStore<T, K2> {
+        PostgresStore {
+            pool: self.pool,
+            config: self.config,
+            codec,
+            clock: self.clock,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set the [`Clock`] used to check whether sessions have expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> PostgresStore<T, K> {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the connection pool used by the store.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Spawns a background task which removes expired sessions from the
+    /// database every `interval`, as [`purge_expired`] does.
+    ///
+    /// The number of sessions removed by each sweep is logged at the debug
+    /// level, and the running total is available from [`Reaper::removed`].
+    ///
+    /// The reaper runs on its own thread, but its queries are driven by the
+    /// Tokio runtime this is called from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, if the OS fails to create a thread, or
+    /// if called outside of a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// use tower_sesh_store_postgres::PostgresStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = Arc::new(PostgresStore::<SessionData>::open("postgres://localhost/app").await?);
+    /// let reaper = store.spawn_reaper(Duration::from_secs(60 * 60));
+    ///
+    /// // ...
+    ///
+    /// reaper.shutdown();
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    ///
+    /// [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+    #[track_caller]
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> Reaper
+    where
+        T: 'static,
+    {
+        let handle = tokio::runtime::Handle::current();
+        Reaper::spawn(self, interval, move |store| handle.block_on(store.purge()))
+    }
+
+    /// Creates the store's schema and tables if they don't exist, and
+    /// upgrades them to the latest schema.
+    ///
+    /// This is called by [`open`]. Migrations run in a single transaction
+    /// holding an advisory lock, so it is safe to call this from multiple
+    /// processes at once.
+    ///
+    /// [`open`]: PostgresStore::open
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the migrations fail, or if the tables were created
+    /// by a newer version of this crate.
+    pub async fn migrate(&self) -> sqlx::Result<()> {
+        let migrations_table = self.table(&format!("{}_migrations", self.config.table_name));
+
+        let mut tx = self.pool.begin().await?;
+
+        // Serializes concurrent migrations of the same tables, which would
+        // otherwise race to create them
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(format!(
+                "tower-sesh:{}.{}",
+                self.config.schema, self.config.table_name
+            ))
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::raw_sql(&format!(
+            "CREATE SCHEMA IF NOT EXISTS {schema};
+            CREATE TABLE IF NOT EXISTS {migrations_table} (
+                version INTEGER PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            );",
+            schema = quote_identifier(&self.config.schema),
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        let applied: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {migrations_table}"))
+            .fetch_one(&mut *tx)
+            .await?;
+        let applied = usize::try_from(applied).unwrap_or(usize::MAX);
+        if applied > MIGRATIONS.len() {
+            return Err(sqlx::Error::Configuration(
+                format!(
+                    "the session tables were migrated by a newer version of `{}` \
+                     (schema version {applied}, latest known version {})",
+                    env!("CARGO_PKG_NAME"),
+                    MIGRATIONS.len(),
+                )
+                .into(),
+            ));
+        }
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let migration = migration
+                .replace("{sessions}", &self.sessions_table())
+                .replace("{principals}", &self.principals_table())
+                .replace(
+                    "{index_prefix}",
+                    &self.config.table_name.replace('"', "\"\""),
+                );
+            sqlx::raw_sql(&migration).execute(&mut *tx).await?;
+
+            sqlx::query(&format!(
+                "INSERT INTO {migrations_table} (version) VALUES ($1)"
+            ))
+            .bind(version as i32 + 1)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
+
+impl<T, K: Codec> fmt::Debug for PostgresStore<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresStore")
+            .field("pool", &self.pool)
+            .field("config", &self.config)
+            .field("codec", &std::any::type_name::<K>())
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+impl<T, K: Codec> PostgresStore<T, K> {
+    /// Removes every expired session, along with its principal, returning
+    /// the number of sessions removed.
+    async fn purge(&self) -> Result<u64> {
+        let purged: i64 = sqlx::query_scalar(&format!(
+            "WITH purged AS (DELETE FROM {sessions} WHERE expires_at < $1 RETURNING id),
+            unindexed AS (DELETE FROM {principals} WHERE id IN (SELECT id FROM purged))
+            SELECT COUNT(*) FROM purged",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(self.clock.now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(purged as u64)
+    }
+
+    /// Returns the qualified name of the table `name` in the store's schema.
+    fn table(&self, name: &str) -> String {
+        format!(
+            "{}.{}",
+            quote_identifier(&self.config.schema),
+            quote_identifier(name)
+        )
+    }
+
+    fn sessions_table(&self) -> String {
+        self.table(&self.config.table_name)
+    }
+
+    fn principals_table(&self) -> String {
+        self.table(&format!("{}_principals", self.config.table_name))
+    }
+
+    /// Returns the `id` the session identified by `session_key` is stored
+    /// under.
+    fn id(&self, session_key: &SessionKey) -> String {
+        match &self.config.hasher {
+            Some(hasher) => hasher.hash(session_key).encode(),
+            None => session_key.encode(),
+        }
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.codec.decode(bytes)
+    }
+
+    fn to_record(&self, row: &PgRow) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.deserialize(row.try_get("data").map_err(Error::store)?)?;
+        let ttl: Ttl = row.try_get("expires_at").map_err(Error::store)?;
+        let version = row.try_get::<i64, _>("version").map_err(Error::store)?;
+        Ok(Record::new(data, ttl).with_version(Version::from_u64(version as u64)))
+    }
+
+    /// Inserts or replaces the session stored under `id`.
+    async fn upsert(&self, id: &str, data: &T, ttl: Ttl, version: Version) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let serialized = self.serialize(data)?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (id, data, expires_at, version) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO UPDATE SET
+                data = excluded.data,
+                expires_at = excluded.expires_at,
+                version = excluded.version",
+            self.sessions_table()
+        ))
+        .bind(id)
+        .bind(serialized)
+        .bind(ttl)
+        .bind(version.as_u64() as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+}
+
+impl<T, K: Codec> SessionStore<T> for PostgresStore<T, K> where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned
+{
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionStoreImpl<T> for PostgresStore<T, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            if let CreateWithKey::Created(version) = self
+                .create_with_key(&session_key, data, ttl, version)
+                .await?
+            {
+                return Ok((session_key, version));
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let inserted = sqlx::query(&format!(
+            "INSERT INTO {} (id, data, expires_at, version) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO NOTHING",
+            self.sessions_table()
+        ))
+        .bind(self.id(session_key))
+        .bind(self.serialize(data)?)
+        .bind(ttl)
+        .bind(version.as_u64() as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?
+        .rows_affected()
+            > 0;
+
+        if inserted {
+            Ok(CreateWithKey::Created(Some(version)))
+        } else {
+            Ok(CreateWithKey::Occupied)
+        }
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let row = sqlx::query(&format!(
+            "SELECT data, expires_at, version FROM {} WHERE id = $1 AND expires_at >= $2",
+            self.sessions_table()
+        ))
+        .bind(self.id(session_key))
+        .bind(self.clock.now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        row.map(|row| self.to_record(&row)).transpose()
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.upsert(&self.id(session_key), data, ttl, new_version())
+            .await
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET expires_at = $1 WHERE id = $2 AND expires_at >= $3",
+            self.sessions_table()
+        ))
+        .bind(ttl)
+        .bind(self.id(session_key))
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        sqlx::query(&format!(
+            "WITH unindexed AS (DELETE FROM {principals} WHERE id = $1)
+            DELETE FROM {sessions} WHERE id = $1",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(self.id(session_key))
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let serialized = self.serialize(data)?;
+        let insert_sql = format!(
+            "INSERT INTO {} (id, data, expires_at, version) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO NOTHING",
+            self.sessions_table()
+        );
+
+        let mut tx = self.pool.begin().await.map_err(Error::store)?;
+
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        let mut new_session_key = None;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+
+            let inserted = sqlx::query(&insert_sql)
+                .bind(self.id(&session_key))
+                .bind(&serialized)
+                .bind(ttl)
+                .bind(new_version().as_u64() as i64)
+                .execute(&mut *tx)
+                .await
+                .map_err(Error::store)?
+                .rows_affected()
+                > 0;
+
+            if inserted {
+                new_session_key = Some(session_key);
+                break;
+            }
+        }
+        let Some(new_session_key) = new_session_key else {
+            return Err(Error::max_iterations_reached());
+        };
+
+        sqlx::query(&format!(
+            "WITH unindexed AS (DELETE FROM {principals} WHERE id = $1)
+            DELETE FROM {sessions} WHERE id = $1",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(self.id(session_key))
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+
+        tx.commit().await.map_err(Error::store)?;
+
+        Ok(new_session_key)
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let id = self.id(session_key);
+        let serialized = self.serialize(data)?;
+        let now = self.clock.now();
+        let new_version = new_version();
+
+        let updated = sqlx::query(&format!(
+            "UPDATE {} SET data = $1, expires_at = $2, version = $3
+            WHERE id = $4 AND version = $5 AND expires_at >= $6",
+            self.sessions_table()
+        ))
+        .bind(serialized)
+        .bind(ttl)
+        .bind(new_version.as_u64() as i64)
+        .bind(id.as_str())
+        .bind(version.as_u64() as i64)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?
+        .rows_affected()
+            > 0;
+
+        if updated {
+            return Ok(UpdateIf::Updated(Some(new_version)));
+        }
+
+        let current = sqlx::query(&format!(
+            "SELECT data, expires_at, version FROM {} WHERE id = $1 AND expires_at >= $2",
+            self.sessions_table()
+        ))
+        .bind(id.as_str())
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        current
+            .map(|row| self.to_record(&row))
+            .transpose()
+            .map(UpdateIf::Conflict)
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        self.upsert(&self.id(session_key), data, ttl, version)
+            .await?;
+        Ok(Some(version))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        self.purge().await
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionIndex for PostgresStore<T, K>
+where
+    T: 'static,
+{
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (principal, id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            self.principals_table()
+        ))
+        .bind(principal)
+        .bind(self.id(session_key))
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>> {
+        sqlx::query_scalar(&format!(
+            "SELECT principal FROM {} WHERE id = $1 LIMIT 1",
+            self.principals_table()
+        ))
+        .bind(self.id(session_key))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::store)
+    }
+
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>> {
+        let rows = sqlx::query(&format!(
+            "SELECT s.id, s.expires_at FROM {principals} p
+            JOIN {sessions} s ON s.id = p.id
+            WHERE p.principal = $1 AND s.expires_at >= $2",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(principal)
+        .bind(self.clock.now())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        rows.iter()
+            .map(|row| {
+                let id: &str = row.try_get("id").map_err(Error::store)?;
+                let ttl: Ttl = row.try_get("expires_at").map_err(Error::store)?;
+                Ok(IndexedSession::new(StorageId::new(id), ttl))
+            })
+            .collect()
+    }
+
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()> {
+        sqlx::query(&format!(
+            "WITH unindexed AS (DELETE FROM {principals} WHERE principal = $1 AND id = $2 RETURNING id)
+            DELETE FROM {sessions} WHERE id IN (SELECT id FROM unindexed)",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(principal)
+        .bind(id.as_str())
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, principal: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "WITH unindexed AS (DELETE FROM {principals} WHERE principal = $1 RETURNING id)
+            DELETE FROM {sessions} WHERE id IN (SELECT id FROM unindexed)",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(principal)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<T, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for PostgresStore<T, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+/// Quotes an SQL identifier, such as a table name.
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Returns a version for newly written session data.
+///
+/// Versions are random rather than sequential, so that a session which is
+/// deleted and then recreated under the same key doesn't reuse a version.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        fn require_traits<T: SessionStore<()> + Send + Sync + 'static>() {}
+
+        require_traits::<PostgresStore<()>>();
+    }
+
+    #[test]
+    fn quote_identifier_escapes_quotes() {
+        assert_eq!(quote_identifier("sessions"), r#""sessions""#);
+        assert_eq!(quote_identifier(r#"my "sessions""#), r#""my ""sessions""""#);
+    }
+}
@@ -0,0 +1,808 @@
+//! The [redb] store for [`tower-sesh`].
+//!
+//! [redb] is an embedded, transactional key-value database, so
+//! [`RedbStore`] keeps sessions on disk without an external service. Like
+//! `MemoryStore`, it is best suited to single-instance deployments: the
+//! database file can only be opened by one process at a time.
+//!
+//! [redb]: https://docs.rs/redb
+//! [`tower-sesh`]: https://docs.rs/tower-sesh/latest/tower_sesh/
+//!
+//! # Tables
+//!
+//! The store uses the following tables, named after the store's
+//! [table name](RedbStore::table_name) (`tower_sesh_sessions` by default):
+//!
+//! - `tower_sesh_sessions` holds the sessions. Each session is stored under
+//!   its Base64-encoded session key, with its expiry (a Unix timestamp in
+//!   nanoseconds), its version, and its serialized data.
+//! - `tower_sesh_sessions_expiry` indexes sessions by expiry, so that expired
+//!   sessions can be found without scanning every session.
+//!
+//! Expired sessions are never loaded, but they aren't removed from the
+//! database until [`purge_expired`] is called. Call it periodically, or let a
+//! [`Reaper`] spawned by [`RedbStore::spawn_reaper`] do so.
+//!
+//! [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+//!
+//! # Durability
+//!
+//! By default, every change to a session is synced to disk before it
+//! completes. This can be relaxed with [`RedbStore::durability`].
+
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+#![warn(missing_debug_implementations)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![doc(test(
+    no_crate_inject,
+    attr(
+        deny(warnings, rust_2018_idioms, single_use_lifetimes),
+        allow(dead_code, unused_assignments, unused_variables)
+    )
+))]
+
+use std::{borrow::Cow, fmt, marker::PhantomData, path::Path, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use rand::{rngs::ThreadRng, Rng};
+use redb::{Database, Durability, ReadableTable, TableDefinition, TableError};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{CreateWithKey, Error, Result, SessionStoreImpl, UpdateIf, Version},
+    time::{Clock, SystemClock},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+pub use redb;
+pub use tower_sesh_core::{codec, reaper::Reaper};
+
+/// A session store backed by an embedded [redb] database.
+///
+/// See the [crate-level documentation](crate#tables) for the tables used by
+/// the store.
+///
+/// [redb]: https://docs.rs/redb
+pub struct RedbStore<T, K: Codec = MessagePack> {
+    db: Arc<Database>,
+    config: Config,
+    codec: K,
+    clock: Arc<dyn Clock>,
+
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    tables: Arc<TableNames>,
+    durability: Durability,
+    hasher: Option<KeyHasher>,
+}
+
+#[derive(Debug)]
+struct TableNames {
+    sessions: Cow<'static, str>,
+    expiry: String,
+}
+
+const DEFAULT_TABLE_NAME: &str = "tower_sesh_sessions";
+
+impl TableNames {
+    fn new(table_name: Cow<'static, str>) -> TableNames {
+        TableNames {
+            expiry: format!("{table_name}_expiry"),
+            sessions: table_name,
+        }
+    }
+
+    /// Maps a session ID to the session's expiry (a Unix timestamp in
+    /// nanoseconds), version, and serialized data.
+    fn sessions(&self) -> TableDefinition<'_, &'static str, (i128, u64, &'static [u8])> {
+        TableDefinition::new(&self.sessions)
+    }
+
+    /// Holds the expiry and ID of every session, ordered by expiry.
+    fn expiry(&self) -> TableDefinition<'_, (i128, &'static str), ()> {
+        TableDefinition::new(&self.expiry)
+    }
+}
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            tables: Arc::new(TableNames::new(Cow::Borrowed(DEFAULT_TABLE_NAME))),
+            durability: Durability::Immediate,
+            hasher: None,
+        }
+    }
+}
+
+/// A session as it is stored in the database.
+struct Entry {
+    expires_at: i128,
+    version: Version,
+    data: Vec<u8>,
+}
+
+impl<T> RedbStore<T> {
+    /// Opens the database at `path` and returns a store with default
+    /// configuration values, creating the database if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database can't be opened, such as when it is
+    /// already open in another process.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_redb::RedbStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// let store = RedbStore::<SessionData>::open("sessions.redb")?;
+    /// # Ok::<(), redb::DatabaseError>(())
+    /// ```
+    pub fn open(path: impl AsRef<Path>) -> Result<RedbStore<T>, redb::DatabaseError> {
+        let db = Database::create(path)?;
+        Ok(RedbStore::with_database(db))
+    }
+
+    /// Returns a store using an existing database, with default configuration
+    /// values.
+    ///
+    /// The database may be shared with the rest of the application, as long
+    /// as it doesn't use the store's tables.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::sync::Arc;
+    ///
+    /// use redb::Database;
+    /// use tower_sesh_store_redb::RedbStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// let db = Arc::new(Database::create("app.redb")?);
+    /// let store = RedbStore::<SessionData>::with_database(Arc::clone(&db)).table_name("sessions");
+    /// # Ok::<(), redb::DatabaseError>(())
+    /// ```
+    pub fn with_database(db: impl Into<Arc<Database>>) -> RedbStore<T> {
+        RedbStore {
+            db: db.into(),
+            config: Config::default(),
+            codec: MessagePack,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "test-util")]
+            rng: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K: Codec> RedbStore<T, K> {
+    /// Set the name of the table used to store sessions.
+    ///
+    /// The table used to index sessions by expiry is named by appending
+    /// `_expiry` to this name.
+    ///
+    /// Default is `"tower_sesh_sessions"`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `table_name` is empty.
+    pub fn table_name(mut self, table_name: impl Into<Cow<'static, str>>) -> RedbStore<T, K> {
+        let table_name = table_name.into();
+        assert!(!table_name.is_empty(), "table name must not be empty");
+        self.config.tables = Arc::new(TableNames::new(table_name));
+        self
+    }
+
+    /// Set the [`Durability`] of changes to sessions.
+    ///
+    /// With [`Durability::Eventual`], changes are written to disk some time
+    /// after they complete, so that a crash may lose the most recent changes.
+    /// This makes writes considerably faster, since they no longer wait for
+    /// `fsync`.
+    ///
+    /// Default is [`Durability::Immediate`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_redb::{redb::Durability, RedbStore};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// let store = RedbStore::<SessionData>::open("sessions.redb")?
+    ///     .durability(Durability::Eventual);
+    /// # Ok::<(), redb::DatabaseError>(())
+    /// ```
+    pub fn durability(mut self, durability: Durability) -> RedbStore<T, K> {
+        self.config.durability = durability;
+        self
+    }
+
+    /// Store each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// Sessions are then stored under the Base64-encoded hash, so the session
+    /// keys held by clients can't be recovered from the database. See
+    /// [`KeyHasher`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_core::key::KeyHasher;
+    /// use tower_sesh_store_redb::RedbStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// fn secret() -> Vec<u8> {
+    ///     // TODO: Where do you get a secret?
+    /// # vec![0; 32]
+    /// }
+    ///
+    /// let store = RedbStore::<SessionData>::open("sessions.redb")?
+    ///     .hash_keys(KeyHasher::new(&secret()));
+    /// # Ok::<(), redb::DatabaseError>(())
+    /// ```
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> RedbStore<T, K> {
+        self.config.hasher = Some(hasher);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data.
+    ///
+    /// Note that changing the codec of an existing deployment will make
+    /// previously stored sessions unreadable.
+    ///
+    /// Large payloads can be compressed by wrapping the codec in
+    /// [`Compressed`](codec::Compressed), which requires the `zstd` or `lz4`
+    /// feature.
+    ///
+    /// Default is [`MessagePack`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "json")] {
+    /// use tower_sesh_store_redb::{codec::Json, RedbStore};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # fn main() -> Result<(), redb::DatabaseError> {
+    /// let store = RedbStore::<SessionData>::open("sessions.redb")?.codec(Json);
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    pub fn codec<K2: Codec>(self, codec: K2) -> RedbStore<T, K2> {
+        RedbStore {
+            db: self.db,
+            config: self.config,
+            codec,
+            clock: self.clock,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set the [`Clock`] used to check whether sessions have expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> RedbStore<T, K> {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the database used by the store.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Spawns a background task which removes expired sessions from the
+    /// database every `interval`, as [`purge_expired`] does.
+    ///
+    /// The number of sessions removed by each sweep is logged at the debug
+    /// level, and the running total is available from [`Reaper::removed`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, or if the OS fails to create a thread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// use tower_sesh_store_redb::RedbStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// let store = Arc::new(RedbStore::<SessionData>::open("sessions.redb")?);
+    /// let reaper = store.spawn_reaper(Duration::from_secs(60 * 60));
+    ///
+    /// // ...
+    ///
+    /// reaper.shutdown();
+    /// # Ok::<(), redb::DatabaseError>(())
+    /// ```
+    ///
+    /// [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> Reaper
+    where
+        T: 'static,
+    {
+        Reaper::spawn(self, interval, |store| {
+            let now = store.now();
+            let config = &store.config;
+            write(&store.db, &config.tables, config.durability, |tables| {
+                purge(tables, now)
+            })
+        })
+    }
+}
+
+impl<T, K: Codec> fmt::Debug for RedbStore<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedbStore")
+            .field("db", &self.db)
+            .field("config", &self.config)
+            .field("codec", &std::any::type_name::<K>())
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+impl<T, K: Codec> RedbStore<T, K> {
+    fn id(&self, session_key: &SessionKey) -> String {
+        match &self.config.hasher {
+            Some(hasher) => hasher.hash(session_key).encode(),
+            None => session_key.encode(),
+        }
+    }
+
+    fn now(&self) -> i128 {
+        self.clock.now().unix_timestamp_nanos()
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn to_record(&self, entry: Entry) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.codec.decode(&entry.data)?;
+        let ttl = to_ttl(entry.expires_at)?;
+        Ok(Record::new(data, ttl).with_version(entry.version))
+    }
+
+    /// Runs `f` in a read transaction on a thread pool.
+    ///
+    /// If the store's tables haven't been created yet, `f` isn't called and
+    /// `U::default()` is returned.
+    async fn read<U, F>(&self, f: F) -> Result<U>
+    where
+        U: Default + Send + 'static,
+        F: FnOnce(&SessionsTable) -> Result<U> + Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        let tables = Arc::clone(&self.config.tables);
+
+        blocking::unblock(move || {
+            let tx = db.begin_read().map_err(Error::store)?;
+            let sessions = match tx.open_table(tables.sessions()) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => return Ok(U::default()),
+                Err(err) => return Err(Error::store(err)),
+            };
+            f(&sessions)
+        })
+        .await
+    }
+
+    /// Runs `f` in a write transaction on a thread pool, committing the
+    /// transaction if `f` succeeds.
+    async fn write<U, F>(&self, f: F) -> Result<U>
+    where
+        U: Send + 'static,
+        F: FnOnce(&mut TablesMut<'_>) -> Result<U> + Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        let tables = Arc::clone(&self.config.tables);
+        let durability = self.config.durability;
+
+        blocking::unblock(move || write(&db, &tables, durability, f)).await
+    }
+
+    /// Stores a session under a randomly generated session key, retrying if
+    /// the key is taken, and returns the session key.
+    ///
+    /// Each attempt checks for a collision and stores the session in a single
+    /// transaction. `f` is called in the same transaction once the session is
+    /// stored.
+    async fn create_entry<F>(&self, entry: Arc<Entry>, f: F) -> Result<SessionKey>
+    where
+        F: Fn(&mut TablesMut<'_>) -> Result<()> + Clone + Send + 'static,
+    {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            let created = self
+                .create_entry_at(&session_key, Arc::clone(&entry), f.clone())
+                .await?;
+
+            if created {
+                return Ok(session_key);
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    /// Stores a session under `session_key`, returning `false` without storing
+    /// anything if the key is taken.
+    ///
+    /// `f` is called in the same transaction once the session is stored.
+    async fn create_entry_at<F>(
+        &self,
+        session_key: &SessionKey,
+        entry: Arc<Entry>,
+        f: F,
+    ) -> Result<bool>
+    where
+        F: FnOnce(&mut TablesMut<'_>) -> Result<()> + Send + 'static,
+    {
+        let id = self.id(session_key);
+        self.write(move |tables| {
+            if tables.get(&id)?.is_some() {
+                return Ok(false);
+            }
+            tables.insert(&id, &entry)?;
+            f(tables)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+}
+
+impl<T, K: Codec> SessionStore<T> for RedbStore<T, K> where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned
+{
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionStoreImpl<T> for RedbStore<T, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        let entry = Entry {
+            expires_at: ttl.unix_timestamp_nanos(),
+            version,
+            data: self.serialize(data)?,
+        };
+
+        let session_key = self.create_entry(Arc::new(entry), |_| Ok(())).await?;
+        Ok((session_key, Some(version)))
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let entry = Entry {
+            expires_at: ttl.unix_timestamp_nanos(),
+            version,
+            data: self.serialize(data)?,
+        };
+
+        if self
+            .create_entry_at(session_key, Arc::new(entry), |_| Ok(()))
+            .await?
+        {
+            Ok(CreateWithKey::Created(Some(version)))
+        } else {
+            Ok(CreateWithKey::Occupied)
+        }
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let id = self.id(session_key);
+        let now = self.now();
+
+        let entry = self
+            .read(move |sessions| Ok(get(sessions, &id)?.filter(|entry| entry.expires_at >= now)))
+            .await?;
+
+        entry.map(|entry| self.to_record(entry)).transpose()
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.update_with_version(session_key, data, ttl, new_version())
+            .await?;
+        Ok(())
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        let id = self.id(session_key);
+        let now = self.now();
+        let expires_at = ttl.unix_timestamp_nanos();
+
+        self.write(move |tables| match tables.get(&id)? {
+            Some(entry) if entry.expires_at >= now => tables.insert(
+                &id,
+                &Entry {
+                    expires_at,
+                    ..entry
+                },
+            ),
+            _ => Ok(()),
+        })
+        .await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        let id = self.id(session_key);
+
+        self.write(move |tables| tables.remove(&id)).await
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let entry = Entry {
+            expires_at: ttl.unix_timestamp_nanos(),
+            version: new_version(),
+            data: self.serialize(data)?,
+        };
+        let old_id: Arc<str> = self.id(session_key).into();
+
+        self.create_entry(Arc::new(entry), move |tables| tables.remove(&old_id))
+            .await
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let id = self.id(session_key);
+        let now = self.now();
+        let new_version = new_version();
+        let entry = Entry {
+            expires_at: ttl.unix_timestamp_nanos(),
+            version: new_version,
+            data: self.serialize(data)?,
+        };
+
+        let conflict = self
+            .write(move |tables| match tables.get(&id)? {
+                Some(current) if current.expires_at >= now && current.version == version => {
+                    tables.insert(&id, &entry).map(|()| None)
+                }
+                Some(current) if current.expires_at >= now => Ok(Some(Some(current))),
+                _ => Ok(Some(None)),
+            })
+            .await?;
+
+        match conflict {
+            None => Ok(UpdateIf::Updated(Some(new_version))),
+            Some(None) => Ok(UpdateIf::Conflict(None)),
+            Some(Some(current)) => self
+                .to_record(current)
+                .map(|record| UpdateIf::Conflict(Some(record))),
+        }
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let id = self.id(session_key);
+        let entry = Entry {
+            expires_at: ttl.unix_timestamp_nanos(),
+            version,
+            data: self.serialize(data)?,
+        };
+
+        self.write(move |tables| tables.insert(&id, &entry)).await?;
+        Ok(Some(version))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let now = self.now();
+
+        self.write(move |tables| purge(tables, now)).await
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<T, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for RedbStore<T, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+type SessionsTable = redb::ReadOnlyTable<&'static str, (i128, u64, &'static [u8])>;
+
+struct TablesMut<'txn> {
+    sessions: redb::Table<'txn, &'static str, (i128, u64, &'static [u8])>,
+    expiry: redb::Table<'txn, (i128, &'static str), ()>,
+}
+
+impl TablesMut<'_> {
+    fn get(&self, id: &str) -> Result<Option<Entry>> {
+        get(&self.sessions, id)
+    }
+
+    /// Inserts or replaces the session stored under `id`, keeping the expiry
+    /// table in sync.
+    fn insert(&mut self, id: &str, entry: &Entry) -> Result<()> {
+        let previous = self
+            .sessions
+            .insert(
+                id,
+                (
+                    entry.expires_at,
+                    entry.version.as_u64(),
+                    entry.data.as_slice(),
+                ),
+            )
+            .map_err(Error::store)?;
+        if let Some(previous) = previous {
+            let (expires_at, _, _) = previous.value();
+            self.expiry.remove((expires_at, id)).map_err(Error::store)?;
+        }
+        self.expiry
+            .insert((entry.expires_at, id), ())
+            .map_err(Error::store)?;
+        Ok(())
+    }
+
+    /// Removes the session stored under `id`, if it exists.
+    fn remove(&mut self, id: &str) -> Result<()> {
+        let previous = self.sessions.remove(id).map_err(Error::store)?;
+        if let Some(previous) = previous {
+            let (expires_at, _, _) = previous.value();
+            self.expiry.remove((expires_at, id)).map_err(Error::store)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the session stored under `id`, if it exists.
+/// Runs `f` in a write transaction, committing the transaction if `f`
+/// succeeds.
+fn write<U, F>(db: &Database, tables: &TableNames, durability: Durability, f: F) -> Result<U>
+where
+    F: FnOnce(&mut TablesMut<'_>) -> Result<U>,
+{
+    let mut tx = db.begin_write().map_err(Error::store)?;
+    tx.set_durability(durability);
+    let output = {
+        let mut tables = TablesMut {
+            sessions: tx.open_table(tables.sessions()).map_err(Error::store)?,
+            expiry: tx.open_table(tables.expiry()).map_err(Error::store)?,
+        };
+        f(&mut tables)?
+    };
+    tx.commit().map_err(Error::store)?;
+    Ok(output)
+}
+
+/// Removes every session which expired before `now`, returning the number of
+/// sessions removed.
+fn purge(tables: &mut TablesMut<'_>, now: i128) -> Result<u64> {
+    let expired = tables
+        .expiry
+        .range::<(i128, &str)>(..(now, ""))
+        .and_then(|range| {
+            range
+                .map(|item| item.map(|(key, _)| key.value().1.to_owned()))
+                .collect::<redb::Result<Vec<_>>>()
+        })
+        .map_err(Error::store)?;
+
+    for id in &expired {
+        tables.remove(id)?;
+    }
+
+    Ok(expired.len() as u64)
+}
+
+fn get<Table>(sessions: &Table, id: &str) -> Result<Option<Entry>>
+where
+    Table: ReadableTable<&'static str, (i128, u64, &'static [u8])>,
+{
+    let Some(guard) = sessions.get(id).map_err(Error::store)? else {
+        return Ok(None);
+    };
+    let (expires_at, version, data) = guard.value();
+    Ok(Some(Entry {
+        expires_at,
+        version: Version::from_u64(version),
+        data: data.to_vec(),
+    }))
+}
+
+/// Returns a version for newly written session data.
+///
+/// Versions are random rather than sequential, so that a session which is
+/// deleted and recreated under the same key can't reuse an old version.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+fn to_ttl(timestamp: i128) -> Result<Ttl> {
+    Ttl::from_unix_timestamp_nanos(timestamp)
+        .map_err(|err| Error::message(format!("invalid timestamp: {}", err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        fn require_traits<T: SessionStore<()> + Send + Sync + 'static>() {}
+
+        require_traits::<RedbStore<()>>();
+    }
+}
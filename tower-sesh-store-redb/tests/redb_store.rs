@@ -0,0 +1,182 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use redb::{Database, ReadableTableMetadata, TableDefinition};
+use tempfile::TempDir;
+use tower_sesh_core::{
+    time::{Clock, MockClock},
+    SessionStore, Ttl,
+};
+use tower_sesh_store_redb::RedbStore;
+
+const EXPIRY_TABLE: TableDefinition<'_, (i128, &str), ()> =
+    TableDefinition::new("tower_sesh_sessions_expiry");
+
+fn path(dir: &TempDir) -> std::path::PathBuf {
+    dir.path().join("sessions.redb")
+}
+
+fn ttl(clock: &MockClock) -> Ttl {
+    clock.now() + Duration::from_secs(60)
+}
+
+fn expiry_len(db: &Database) -> u64 {
+    let tx = db.begin_read().unwrap();
+    tx.open_table(EXPIRY_TABLE).unwrap().len().unwrap()
+}
+
+#[tokio::test]
+async fn sessions_survive_reopening() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+
+    let store = RedbStore::<String>::open(path(&dir))
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<String> = &store;
+    let session_key = dyn_store
+        .create(&"hello".to_owned(), ttl(&clock))
+        .await
+        .unwrap();
+    drop(store);
+
+    let reopened = RedbStore::<String>::open(path(&dir))
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_reopened: &dyn SessionStore<String> = &reopened;
+    let record = dyn_reopened.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "hello");
+    assert_eq!(record.ttl, ttl(&clock));
+}
+
+#[tokio::test]
+async fn load_before_first_write_returns_none() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RedbStore::<()>::open(path(&dir)).unwrap();
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let session_key = rand::random();
+    assert!(dyn_store.load(&session_key).await.unwrap().is_none());
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn table_name_is_configurable() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Database::create(path(&dir)).unwrap());
+    let store = RedbStore::<u32>::with_database(Arc::clone(&db)).table_name("sessions");
+    let dyn_store: &dyn SessionStore<u32> = &store;
+
+    let session_key = dyn_store
+        .create(&42, Ttl::now_utc() + Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert_eq!(
+        dyn_store.load(&session_key).await.unwrap().unwrap().data,
+        42
+    );
+
+    let tx = db.begin_read().unwrap();
+    let sessions: TableDefinition<'_, &str, (i128, u64, &[u8])> = TableDefinition::new("sessions");
+    assert_eq!(tx.open_table(sessions).unwrap().len().unwrap(), 1);
+    let expiry: TableDefinition<'_, (i128, &str), ()> = TableDefinition::new("sessions_expiry");
+    assert_eq!(tx.open_table(expiry).unwrap().len().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn expiry_index_tracks_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = RedbStore::<()>::open(path(&dir))
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let session_key = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    assert_eq!(expiry_len(store.database()), 1);
+
+    dyn_store
+        .update_ttl(&session_key, clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    dyn_store
+        .update(
+            &session_key,
+            &(),
+            clock.now() + Duration::from_secs(10 * 60),
+        )
+        .await
+        .unwrap();
+    assert_eq!(expiry_len(store.database()), 1);
+
+    let session_key = dyn_store
+        .cycle_key(&session_key, &(), ttl(&clock))
+        .await
+        .unwrap();
+    assert_eq!(expiry_len(store.database()), 1);
+
+    dyn_store.delete(&session_key).await.unwrap();
+    assert_eq!(expiry_len(store.database()), 0);
+}
+
+#[tokio::test]
+async fn purge_expired_returns_number_of_sessions_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = RedbStore::<()>::open(path(&dir))
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let long_lived = dyn_store
+        .create(&(), clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    let extended = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    dyn_store
+        .update_ttl(&extended, clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+
+    clock.advance(Duration::from_secs(2 * 60));
+
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+    assert!(dyn_store.load(&long_lived).await.unwrap().is_some());
+    assert!(dyn_store.load(&extended).await.unwrap().is_some());
+    assert_eq!(expiry_len(store.database()), 2);
+}
+
+#[tokio::test]
+async fn reaper_removes_expired_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = Arc::new(
+        RedbStore::<()>::open(path(&dir))
+            .unwrap()
+            .clock(clock.clone()),
+    );
+    let dyn_store: &dyn SessionStore<()> = store.as_ref();
+
+    let reaper = store.spawn_reaper(Duration::from_millis(1));
+
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    clock.advance(Duration::from_secs(2 * 60));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reaper.removed() < 3 {
+        assert!(Instant::now() < deadline, "reaper didn't remove sessions");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(reaper.shutdown(), 3);
+    assert_eq!(expiry_len(store.database()), 0);
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "test-util")]
+
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::TempDir;
+use tower_sesh_store_redb::RedbStore;
+
+fn store<T>(dir: &TempDir) -> RedbStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    RedbStore::open(dir.path().join("sessions.redb")).expect("failed to open database")
+}
+
+mod redb_store {
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: store(&dir).clock(clock.clone()),
+    }
+}
+
+mod redb_store_system_clock {
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        store: store(&dir),
+    }
+}
+
+mod redb_store_eventual_durability {
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_store_redb::redb::Durability;
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: store(&dir).durability(Durability::Eventual).clock(clock.clone()),
+    }
+}
+
+mod redb_caching_store {
+    use tower_sesh::store::{CachingStore, MemoryStore};
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: CachingStore::from_cache_and_store(
+            MemoryStore::new().clock(clock.clone()),
+            store(&dir).clock(clock.clone()),
+        ),
+    }
+}
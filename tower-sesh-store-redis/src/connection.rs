@@ -1,9 +1,20 @@
 //! Custom Redis connection implementations.
 
+#[cfg(feature = "sentinel")]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use std::{error::Error, fmt};
 
 use async_trait::async_trait;
 use futures_util::FutureExt;
+#[cfg(feature = "sentinel")]
+use redis::{
+    aio::MultiplexedConnection,
+    sentinel::{Sentinel, SentinelNodeConnectionInfo},
+    AsyncConnectionConfig, IntoConnectionInfo,
+};
 use redis::{
     aio::{ConnectionLike, ConnectionManager, ConnectionManagerConfig},
     Client, Cmd, Pipeline, RedisError, RedisFuture, RedisResult, Value,
@@ -106,6 +117,12 @@ pub trait GetConnection: Send + Sync + 'static + private::Sealed {
     type Connection: ConnectionLike + Send;
 
     async fn connection(&self) -> Result<Self::Connection, GetConnectionError>;
+
+    /// Returns a connection to a replica for read-only requests, if reading
+    /// from replicas is enabled.
+    async fn replica_connection(&self) -> Result<Option<Self::Connection>, GetConnectionError> {
+        Ok(None)
+    }
 }
 
 #[async_trait]
@@ -119,6 +136,404 @@ impl GetConnection for ConnectionManagerWithRetry {
 }
 impl private::Sealed for ConnectionManagerWithRetry {}
 
+/// A connection to a [Redis Cluster].
+///
+/// Requests are routed to the node serving the slot of their keys. Redirects
+/// and dropped connections are handled by the underlying
+/// [`ClusterConnection`], which also reconnects and refreshes the slot map
+/// when the cluster topology changes.
+///
+/// [Redis Cluster]: https://redis.io/docs/latest/operate/oss_and_stack/management/scaling/
+/// [`ClusterConnection`]: redis::cluster_async::ClusterConnection
+#[cfg(feature = "cluster")]
+#[derive(Clone)]
+pub struct ClusterConnection(redis::cluster_async::ClusterConnection);
+
+#[cfg(feature = "cluster")]
+impl ClusterConnection {
+    #[inline]
+    pub(crate) async fn new(client: redis::cluster::ClusterClient) -> RedisResult<Self> {
+        client.get_async_connection().await.map(Self::from)
+    }
+}
+
+#[cfg(feature = "cluster")]
+impl fmt::Debug for ClusterConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClusterConnection")
+    }
+}
+
+#[cfg(feature = "cluster")]
+impl From<redis::cluster_async::ClusterConnection> for ClusterConnection {
+    #[inline]
+    fn from(value: redis::cluster_async::ClusterConnection) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "cluster")]
+impl From<ClusterConnection> for redis::cluster_async::ClusterConnection {
+    #[inline]
+    fn from(value: ClusterConnection) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "cluster")]
+#[async_trait]
+impl GetConnection for ClusterConnection {
+    type Connection = redis::cluster_async::ClusterConnection;
+
+    #[inline]
+    async fn connection(&self) -> Result<Self::Connection, GetConnectionError> {
+        Ok(self.0.clone())
+    }
+}
+#[cfg(feature = "cluster")]
+impl private::Sealed for ClusterConnection {}
+
+/// Options for a [`SentinelConnection`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use redis::AsyncConnectionConfig;
+/// use tower_sesh_store_redis::{connection::SentinelConfig, RedisStore};
+///
+/// # type SessionData = ();
+/// #
+/// # tokio_test::block_on(async {
+/// let config = SentinelConfig::default()
+///     .set_connection_config(
+///         AsyncConnectionConfig::new().set_response_timeout(Duration::from_secs(1)),
+///     )
+///     .set_read_from_replicas(true);
+/// let store = RedisStore::<SessionData, _>::open_sentinel_with_config(
+///     ["redis://10.0.0.1:26379/", "redis://10.0.0.2:26379/"],
+///     "mymaster",
+///     config,
+/// )
+/// .await?;
+/// # Ok::<(), redis::RedisError>(())
+/// # }).unwrap();
+/// ```
+#[cfg(feature = "sentinel")]
+#[derive(Clone, Default)]
+pub struct SentinelConfig {
+    connection_config: AsyncConnectionConfig,
+    node_connection_info: Option<SentinelNodeConnectionInfo>,
+    read_from_replicas: bool,
+}
+
+#[cfg(feature = "sentinel")]
+impl SentinelConfig {
+    /// Set the configuration of connections to the primary and replicas, such
+    /// as timeouts.
+    pub fn set_connection_config(mut self, config: AsyncConnectionConfig) -> SentinelConfig {
+        self.connection_config = config;
+        self
+    }
+
+    /// Set the information used to connect to the primary and replicas, such
+    /// as credentials, the database, and whether to use TLS.
+    ///
+    /// The connection information of the sentinels themselves is taken from
+    /// their URLs.
+    pub fn set_node_connection_info(mut self, info: SentinelNodeConnectionInfo) -> SentinelConfig {
+        self.node_connection_info = Some(info);
+        self
+    }
+
+    /// Set whether sessions are loaded from a replica.
+    ///
+    /// This takes load off the primary, which still serves every write. Since
+    /// replication is asynchronous, a replica may not have a session yet, or
+    /// may have an outdated copy of it. Sessions not found on a replica are
+    /// looked up on the primary, but an outdated session may be loaded if it
+    /// was updated moments before.
+    ///
+    /// If no replica is available, sessions are loaded from the primary.
+    ///
+    /// Default is `false`.
+    pub fn set_read_from_replicas(mut self, read_from_replicas: bool) -> SentinelConfig {
+        self.read_from_replicas = read_from_replicas;
+        self
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl fmt::Debug for SentinelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentinelConfig")
+            .field("read_from_replicas", &self.read_from_replicas)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Connections to a primary and its replicas, which are discovered through
+/// [Redis Sentinel].
+///
+/// The primary is looked up from the sentinels when the store is opened. If
+/// a request fails because its connection was dropped, or because the server
+/// is no longer the primary after a failover, the primary is looked up again
+/// and the request is retried once.
+///
+/// [Redis Sentinel]: https://redis.io/docs/latest/operate/oss_and_stack/management/sentinel/
+#[cfg(feature = "sentinel")]
+#[derive(Clone)]
+pub struct SentinelConnection(Arc<SentinelShared>);
+
+#[cfg(feature = "sentinel")]
+struct SentinelShared {
+    sentinel: futures_util::lock::Mutex<Sentinel>,
+    master_name: String,
+    config: SentinelConfig,
+    primary: parking_lot::Mutex<Option<Node>>,
+    replica: parking_lot::Mutex<Option<Node>>,
+    generation: AtomicU64,
+}
+
+/// A connection to a server, along with the generation it was discovered in,
+/// which identifies it when it needs to be replaced.
+#[cfg(feature = "sentinel")]
+#[derive(Clone)]
+struct Node {
+    generation: u64,
+    connection: MultiplexedConnection,
+}
+
+#[cfg(feature = "sentinel")]
+#[derive(Clone, Copy, Debug)]
+enum Role {
+    Primary,
+    Replica,
+}
+
+#[cfg(feature = "sentinel")]
+impl SentinelConnection {
+    pub(crate) async fn new<I: IntoConnectionInfo>(
+        sentinels: Vec<I>,
+        master_name: String,
+        config: SentinelConfig,
+    ) -> RedisResult<Self> {
+        let shared = Arc::new(SentinelShared {
+            sentinel: futures_util::lock::Mutex::new(Sentinel::build(sentinels)?),
+            master_name,
+            config,
+            primary: parking_lot::Mutex::new(None),
+            replica: parking_lot::Mutex::new(None),
+            generation: AtomicU64::new(0),
+        });
+        shared.node(Role::Primary).await?;
+        Ok(Self(shared))
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl SentinelShared {
+    fn slot(&self, role: Role) -> &parking_lot::Mutex<Option<Node>> {
+        match role {
+            Role::Primary => &self.primary,
+            Role::Replica => &self.replica,
+        }
+    }
+
+    async fn node(&self, role: Role) -> RedisResult<Node> {
+        let node = self.slot(role).lock().clone();
+        match node {
+            Some(node) => Ok(node),
+            None => self.discover(role, None).await,
+        }
+    }
+
+    /// Looks up the server with the given role from the sentinels and
+    /// connects to it, unless the connection of generation `stale` has
+    /// already been replaced.
+    async fn discover(&self, role: Role, stale: Option<u64>) -> RedisResult<Node> {
+        let mut sentinel = self.sentinel.lock().await;
+
+        // Another request may have replaced the connection while this one was
+        // waiting for the lock
+        if let Some(node) = &*self.slot(role).lock() {
+            if Some(node.generation) != stale {
+                return Ok(node.clone());
+            }
+        }
+
+        let info = self.config.node_connection_info.as_ref();
+        let client = match role {
+            Role::Primary => sentinel.async_master_for(&self.master_name, info).await?,
+            Role::Replica => match sentinel.async_replica_for(&self.master_name, info).await {
+                Ok(client) => client,
+                // Read from the primary while no replica is available
+                Err(_) => sentinel.async_master_for(&self.master_name, info).await?,
+            },
+        };
+        let connection = client
+            .get_multiplexed_async_connection_with_config(&self.config.connection_config)
+            .await?;
+
+        let node = Node {
+            generation: self.generation.fetch_add(1, Ordering::Relaxed),
+            connection,
+        };
+        *self.slot(role).lock() = Some(node.clone());
+        Ok(node)
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl fmt::Debug for SentinelConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentinelConnection")
+            .field("master_name", &self.0.master_name)
+            .field("config", &self.0.config)
+            .finish()
+    }
+}
+
+#[cfg(feature = "sentinel")]
+#[async_trait]
+impl GetConnection for SentinelConnection {
+    type Connection = SentinelNodeConnection;
+
+    async fn connection(&self) -> Result<Self::Connection, GetConnectionError> {
+        self.node_connection(Role::Primary).await
+    }
+
+    async fn replica_connection(&self) -> Result<Option<Self::Connection>, GetConnectionError> {
+        if self.0.config.read_from_replicas {
+            self.node_connection(Role::Replica).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+#[cfg(feature = "sentinel")]
+impl private::Sealed for SentinelConnection {}
+
+#[cfg(feature = "sentinel")]
+impl SentinelConnection {
+    async fn node_connection(
+        &self,
+        role: Role,
+    ) -> Result<SentinelNodeConnection, GetConnectionError> {
+        let node = self.0.node(role).await?;
+        Ok(SentinelNodeConnection {
+            shared: Arc::clone(&self.0),
+            role,
+            node,
+        })
+    }
+}
+
+/// A connection to the primary or a replica, acquired from a
+/// [`SentinelConnection`].
+#[doc(hidden)]
+#[cfg(feature = "sentinel")]
+pub struct SentinelNodeConnection {
+    shared: Arc<SentinelShared>,
+    role: Role,
+    node: Node,
+}
+
+#[cfg(feature = "sentinel")]
+impl SentinelNodeConnection {
+    async fn rediscover(&mut self) -> RedisResult<()> {
+        self.node = self
+            .shared
+            .discover(self.role, Some(self.node.generation))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl fmt::Debug for SentinelNodeConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentinelNodeConnection")
+            .field("role", &self.role)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl ConnectionLike for SentinelNodeConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        (async move {
+            match self.node.connection.send_packed_command(cmd).await {
+                Err(err) if is_failover(&err) => {
+                    self.rediscover().await?;
+                    if !is_retryable(&err) {
+                        return Err(err);
+                    }
+                    self.node.connection.send_packed_command(cmd).await
+                }
+                result @ (Err(_) | Ok(_)) => result,
+            }
+        })
+        .boxed()
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        (async move {
+            match self
+                .node
+                .connection
+                .send_packed_commands(cmd, offset, count)
+                .await
+            {
+                Err(err) if is_failover(&err) => {
+                    self.rediscover().await?;
+                    if !is_retryable(&err) {
+                        return Err(err);
+                    }
+                    self.node
+                        .connection
+                        .send_packed_commands(cmd, offset, count)
+                        .await
+                }
+                result @ (Err(_) | Ok(_)) => result,
+            }
+        })
+        .boxed()
+    }
+
+    #[inline]
+    fn get_db(&self) -> i64 {
+        self.node.connection.get_db()
+    }
+}
+
+/// Returns whether a request may have failed because of a failover, in which
+/// case the server should be looked up again.
+///
+/// A dropped connection means the server may be down. A `READONLY` error
+/// means a former primary was demoted to a replica.
+#[cfg(feature = "sentinel")]
+fn is_failover(err: &RedisError) -> bool {
+    err.is_unrecoverable_error() || err.kind() == redis::ErrorKind::ReadOnly
+}
+
+/// Returns whether a request which failed because of a failover can be
+/// retried on the server that was looked up again.
+///
+/// A `READONLY` error means the command was rejected without running. A
+/// command sent before the connection dropped may already have run, and
+/// retrying it would run a script which isn't idempotent twice.
+#[cfg(feature = "sentinel")]
+fn is_retryable(err: &RedisError) -> bool {
+    err.kind() == redis::ErrorKind::ReadOnly
+}
+
 /// An error returned by [`GetConnection`] methods.
 #[doc(hidden)]
 pub struct GetConnectionError(RedisError);
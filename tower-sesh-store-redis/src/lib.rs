@@ -16,10 +16,14 @@
 #[cfg(not(any(feature = "rt_tokio", feature = "rt_async-std")))]
 compile_error!("Either the `rt_tokio` or `rt_async-std` feature must be enabled.");
 
-use std::{borrow::Cow, fmt, marker::PhantomData};
+use std::{borrow::Cow, fmt, marker::PhantomData, num::NonZeroU128, sync::LazyLock};
 
 use async_trait::async_trait;
+#[cfg(feature = "cluster")]
+use connection::ClusterConnection;
 use connection::{ConnectionManagerWithRetry, GetConnection};
+#[cfg(feature = "sentinel")]
+use connection::{SentinelConfig, SentinelConnection};
 use rand::{rngs::ThreadRng, Rng};
 use redis::{
     aio::ConnectionManagerConfig, AsyncCommands, Client, ExistenceCheck, IntoConnectionInfo,
@@ -27,18 +31,25 @@ use redis::{
 };
 use serde::{de::DeserializeOwned, Serialize};
 use tower_sesh_core::{
-    store::{Error, Result, SessionStoreImpl},
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{
+        CreateWithKey, Error, IndexedSession, Result, SessionIndex, SessionStoreImpl, StorageId,
+        UpdateIf, Version,
+    },
     time::SESSION_EXPIRY_SECONDS_DEFAULT,
     Record, SessionKey, SessionStore, Ttl,
 };
 
 pub use redis;
+pub use tower_sesh_core::codec;
 
 pub mod connection;
 
-pub struct RedisStore<T, C: GetConnection = ConnectionManagerWithRetry> {
+pub struct RedisStore<T, C: GetConnection = ConnectionManagerWithRetry, K: Codec = MessagePack> {
     client: C,
     config: Config,
+    codec: K,
 
     #[cfg(feature = "test-util")]
     rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
@@ -49,15 +60,25 @@ pub struct RedisStore<T, C: GetConnection = ConnectionManagerWithRetry> {
 #[derive(Clone, Debug)]
 struct Config {
     key_prefix: Cow<'static, str>,
+    hasher: Option<KeyHasher>,
+    /// Whether Redis keys contain a hash tag. Set when connected to a cluster.
+    hash_tags: bool,
 }
 
 const DEFAULT_KEY_PREFIX: &str = "session:";
+const INDEX_KEY_INFIX: &str = "principal:";
+const PRINCIPAL_KEY_SUFFIX: &str = ":principal";
+
+/// Length of a hash tag in a Redis key, e.g. `{3f1c}`.
+const HASH_TAG_LEN: usize = 6;
 
 impl Default for Config {
     #[inline]
     fn default() -> Self {
         Self {
             key_prefix: Cow::Borrowed(DEFAULT_KEY_PREFIX),
+            hasher: None,
+            hash_tags: false,
         }
     }
 }
@@ -125,6 +146,109 @@ impl<T> RedisStore<T> {
     }
 }
 
+#[cfg(feature = "cluster")]
+impl<T> RedisStore<T, ClusterConnection> {
+    /// Connects to a [Redis Cluster] and returns a store with default
+    /// configuration values.
+    ///
+    /// `nodes` should contain the URLs of one or more nodes of the cluster, in
+    /// the format accepted by [`open`]. The rest of the cluster is discovered
+    /// from these nodes.
+    ///
+    /// In a cluster, the Redis key of a session contains a [hash tag], e.g.
+    /// `session:{3f1c}ym5hy39HMVwYUJpPW6x_sQ`. When the session key is
+    /// cycled, the new session key is chosen so that it has the same hash tag,
+    /// which keeps the old and new Redis keys in the same slot and lets the
+    /// session be moved atomically.
+    ///
+    /// [Redis Cluster]: https://redis.io/docs/latest/operate/oss_and_stack/management/scaling/
+    /// [`open`]: RedisStore::open
+    /// [hash tag]: https://redis.io/docs/latest/operate/oss_and_stack/reference/cluster-spec/#hash-tags
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_redis::RedisStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = RedisStore::<SessionData, _>::open_cluster([
+    ///     "redis://10.0.0.1:6379/",
+    ///     "redis://10.0.0.2:6379/",
+    ///     "redis://10.0.0.3:6379/",
+    /// ])
+    /// .await?;
+    /// # Ok::<(), redis::RedisError>(())
+    /// # }).unwrap();
+    /// ```
+    pub async fn open_cluster<I: IntoConnectionInfo>(
+        nodes: impl IntoIterator<Item = I>,
+    ) -> RedisResult<RedisStore<T, ClusterConnection>> {
+        let client = redis::cluster::ClusterClient::new(nodes)?;
+        ClusterConnection::new(client).await.map(|client| {
+            let mut store = RedisStore::with_client(client);
+            store.config.hash_tags = true;
+            store
+        })
+    }
+}
+
+#[cfg(feature = "sentinel")]
+impl<T> RedisStore<T, SentinelConnection> {
+    /// Connects to the primary of a set of servers monitored by [Redis
+    /// Sentinel] and returns a store with default configuration values.
+    ///
+    /// `sentinels` should contain the URLs of one or more sentinels, in the
+    /// format accepted by [`open`], and `master_name` the name the sentinels
+    /// monitor the primary under. The store connects to the new primary after
+    /// a failover. See [`SentinelConnection`] for details.
+    ///
+    /// [Redis Sentinel]: https://redis.io/docs/latest/operate/oss_and_stack/management/sentinel/
+    /// [`open`]: RedisStore::open
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_redis::RedisStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = RedisStore::<SessionData, _>::open_sentinel(
+    ///     ["redis://10.0.0.1:26379/", "redis://10.0.0.2:26379/"],
+    ///     "mymaster",
+    /// )
+    /// .await?;
+    /// # Ok::<(), redis::RedisError>(())
+    /// # }).unwrap();
+    /// ```
+    pub async fn open_sentinel<I: IntoConnectionInfo>(
+        sentinels: impl IntoIterator<Item = I>,
+        master_name: impl Into<String>,
+    ) -> RedisResult<RedisStore<T, SentinelConnection>> {
+        Self::open_sentinel_with_config(sentinels, master_name, SentinelConfig::default()).await
+    }
+
+    /// Connects to the primary of a set of servers monitored by [Redis
+    /// Sentinel] and returns a store with the given configuration.
+    ///
+    /// This allows you to configure connection timeouts, the credentials used
+    /// to connect to the primary and replicas, and whether sessions are loaded
+    /// from replicas. See [`SentinelConfig`] for an example.
+    ///
+    /// [Redis Sentinel]: https://redis.io/docs/latest/operate/oss_and_stack/management/sentinel/
+    pub async fn open_sentinel_with_config<I: IntoConnectionInfo>(
+        sentinels: impl IntoIterator<Item = I>,
+        master_name: impl Into<String>,
+        config: SentinelConfig,
+    ) -> RedisResult<RedisStore<T, SentinelConnection>> {
+        SentinelConnection::new(sentinels.into_iter().collect(), master_name.into(), config)
+            .await
+            .map(RedisStore::with_client)
+    }
+}
+
 impl<T, C: GetConnection> RedisStore<T, C> {
     #[cfg(feature = "test-util")]
     #[inline]
@@ -132,6 +256,7 @@ impl<T, C: GetConnection> RedisStore<T, C> {
         Self {
             client,
             config: Config::default(),
+            codec: MessagePack,
             rng: None,
             _marker: PhantomData,
         }
@@ -143,12 +268,13 @@ impl<T, C: GetConnection> RedisStore<T, C> {
         Self {
             client,
             config: Config::default(),
+            codec: MessagePack,
             _marker: PhantomData,
         }
     }
 }
 
-impl<T, C: GetConnection> RedisStore<T, C> {
+impl<T, C: GetConnection, K: Codec> RedisStore<T, C, K> {
     /// Set the Redis key prefix used to store sessions.
     ///
     /// When a session is stored, the Redis [key] is constructed by appending
@@ -157,14 +283,91 @@ impl<T, C: GetConnection> RedisStore<T, C> {
     ///
     /// Default is `"session:"`.
     ///
+    /// When connected to a cluster, the prefix must not contain a [hash tag]
+    /// (a substring enclosed in `{` and `}`), since every session would then
+    /// be stored in the same slot.
+    ///
     /// [key]: https://redis.io/docs/latest/develop/use/keyspace/
-    pub fn key_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> RedisStore<T, C> {
+    /// [hash tag]: https://redis.io/docs/latest/operate/oss_and_stack/reference/cluster-spec/#hash-tags
+    pub fn key_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> RedisStore<T, C, K> {
         self.config.key_prefix = prefix.into();
         self
     }
+
+    /// Store each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// The Redis key of a session is then constructed by appending the
+    /// Base64-encoded hash to the prefix, so the session keys held by clients
+    /// can't be recovered from the keys in Redis. See [`KeyHasher`] for
+    /// details.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_core::key::KeyHasher;
+    /// use tower_sesh_store_redis::RedisStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// fn secret() -> Vec<u8> {
+    ///     // TODO: Where do you get a secret?
+    /// # vec![0; 32]
+    /// }
+    ///
+    /// # tokio_test::block_on(async {
+    /// let store = RedisStore::<SessionData>::open("redis://127.0.0.1/")
+    ///     .await?
+    ///     .hash_keys(KeyHasher::new(&secret()));
+    /// # Ok::<(), redis::RedisError>(())
+    /// # }).unwrap();
+    /// ```
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> RedisStore<T, C, K> {
+        self.config.hasher = Some(hasher);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data.
+    ///
+    /// This is useful when the sessions in Redis are shared with a service
+    /// that expects a particular format. Note that changing the codec of an
+    /// existing deployment will make previously stored sessions unreadable.
+    ///
+    /// Large payloads can be compressed by wrapping the codec in
+    /// [`Compressed`](codec::Compressed), which requires the `zstd` or `lz4`
+    /// feature.
+    ///
+    /// Default is [`MessagePack`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "json")] {
+    /// use tower_sesh_store_redis::{codec::Json, RedisStore};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = RedisStore::<SessionData>::open("redis://127.0.0.1/")
+    ///     .await?
+    ///     .codec(Json);
+    /// # Ok::<(), redis::RedisError>(())
+    /// # }).unwrap();
+    /// # }
+    /// ```
+    pub fn codec<K2: Codec>(self, codec: K2) -> RedisStore<T, C, K2> {
+        RedisStore {
+            client: self.client,
+            config: self.config,
+            codec,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
 }
 
-impl<T, C: GetConnection> fmt::Debug for RedisStore<T, C>
+impl<T, C: GetConnection, K: Codec> fmt::Debug for RedisStore<T, C, K>
 where
     C: fmt::Debug,
 {
@@ -172,23 +375,207 @@ where
         f.debug_struct("RedisStore")
             .field("client", &self.client)
             .field("config", &self.config)
+            .field("codec", &std::any::type_name::<K>())
             .finish()
     }
 }
 
-impl<T, C: GetConnection> RedisStore<T, C> {
+impl<T, C: GetConnection, K: Codec> RedisStore<T, C, K> {
     fn redis_key(&self, session_key: &SessionKey) -> String {
-        let mut redis_key =
-            String::with_capacity(self.config.key_prefix.len() + SessionKey::ENCODED_LEN);
+        let mut redis_key = String::with_capacity(
+            self.config.key_prefix.len() + HASH_TAG_LEN + SessionKey::ENCODED_LEN,
+        );
         redis_key.push_str(&self.config.key_prefix);
-        redis_key.push_str(&session_key.encode());
+        self.push_member(&mut redis_key, session_key);
         redis_key
     }
 
+    /// Returns the Redis key of a session without the prefix, which is how
+    /// the session is stored in the sets of the session index.
+    fn member(&self, session_key: &SessionKey) -> String {
+        let mut member = String::with_capacity(HASH_TAG_LEN + SessionKey::ENCODED_LEN);
+        self.push_member(&mut member, session_key);
+        member
+    }
+
+    fn push_member(&self, buf: &mut String, session_key: &SessionKey) {
+        if self.config.hash_tags {
+            buf.push_str(&format!("{{{:04x}}}", self.hash_tag(session_key)));
+        }
+        buf.push_str(&self.storage_key(session_key).encode());
+    }
+
+    /// Returns the hash tag of a session's Redis key, which determines the
+    /// cluster slot the key belongs to.
+    ///
+    /// The tag is derived from the low bits of the session key, which are
+    /// carried over when the session key is cycled (see
+    /// [`cycled_session_key`]). If keys are hashed, so are these bits, so the
+    /// tag reveals nothing about the session key.
+    ///
+    /// [`cycled_session_key`]: RedisStore::cycled_session_key
+    fn hash_tag(&self, session_key: &SessionKey) -> u16 {
+        let bits = hash_tag_bits(session_key);
+        match &self.config.hasher {
+            Some(hasher) => {
+                let key = SessionKey::from(NonZeroU128::MIN.saturating_add(u128::from(bits)));
+                hash_tag_bits(&hasher.hash(&key))
+            }
+            None => bits,
+        }
+    }
+
+    /// Returns a random session key to replace `session_key` with.
+    ///
+    /// If Redis keys contain a hash tag, the new session key has the same
+    /// hash tag, so only 112 of its 128 bits are random.
+    fn cycled_session_key(&self, session_key: &SessionKey) -> SessionKey {
+        if !self.config.hash_tags {
+            return self.random();
+        }
+
+        let tag_bits = u128::from(hash_tag_bits(session_key));
+        loop {
+            let bits = self.random::<u128>() & !u128::from(u16::MAX) | tag_bits;
+            if let Ok(new_session_key) = SessionKey::try_from(bits) {
+                return new_session_key;
+            }
+        }
+    }
+
+    /// Returns the key the session identified by `session_key` is stored
+    /// under, which is encoded to construct its Redis key.
+    fn storage_key<'a>(&self, session_key: &'a SessionKey) -> Cow<'a, SessionKey> {
+        match &self.config.hasher {
+            Some(hasher) => Cow::Owned(hasher.hash(session_key)),
+            None => Cow::Borrowed(session_key),
+        }
+    }
+
+    /// Returns the key of the set containing the (encoded) session keys of the
+    /// principal's sessions, e.g. `session:principal:42`.
+    ///
+    /// This can't collide with the key of a session, since `:` is not part of
+    /// the alphabet used to encode session keys.
+    fn index_key(&self, principal: &str) -> String {
+        let mut index_key = String::with_capacity(
+            self.config.key_prefix.len() + INDEX_KEY_INFIX.len() + principal.len(),
+        );
+        index_key.push_str(&self.config.key_prefix);
+        index_key.push_str(INDEX_KEY_INFIX);
+        index_key.push_str(principal);
+        index_key
+    }
+
+    /// Returns the key holding the principal a session is associated with,
+    /// e.g. `session:ym5hy39HMVwYUJpPW6x_sQ:principal`.
+    ///
+    /// The key shares the hash tag of the session's Redis key, so both are in
+    /// the same slot. Since `:` is not part of the alphabet used to encode
+    /// session keys, it can't collide with the key of a session, nor with the
+    /// key of an index set (whose `principal:` infix is shorter than an
+    /// encoded session key).
+    fn principal_key(&self, session_key: &SessionKey) -> String {
+        let mut principal_key = self.redis_key(session_key);
+        principal_key.push_str(PRINCIPAL_KEY_SUFFIX);
+        principal_key
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.codec.decode(bytes)
+    }
+
     async fn connection(&self) -> Result<<C as GetConnection>::Connection> {
         self.client.connection().await.map_err(Error::store)
     }
 
+    async fn replica_connection(&self) -> Result<Option<<C as GetConnection>::Connection>> {
+        self.client.replica_connection().await.map_err(Error::store)
+    }
+
+    /// Loads the session stored under `key` from a replica, if sessions are
+    /// loaded from replicas.
+    ///
+    /// Returns `None` if the session should be loaded from the primary
+    /// instead.
+    async fn load_from_replica(&self, key: &str) -> Result<Option<Record<T>>>
+    where
+        T: DeserializeOwned,
+    {
+        let Some(mut conn) = self.replica_connection().await? else {
+            return Ok(None);
+        };
+
+        let (value, timestamp) = redis::pipe()
+            .atomic()
+            .get(key)
+            .expire_time(key)
+            .query_async::<(Option<Vec<u8>>, i64)>(&mut conn)
+            .await
+            .map_err(Error::store)?;
+
+        // A session that isn't found may not have been replicated yet, and a
+        // session without a timeout must have one set by the primary
+        match (value, timestamp) {
+            (Some(value), 0..) => self.to_loaded_record(&value, timestamp).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Extends the expiry of the index set of the principal a session is
+    /// associated with, so that the set doesn't expire before the session.
+    async fn extend_index(
+        &self,
+        conn: &mut <C as GetConnection>::Connection,
+        principal: Option<String>,
+        timestamp: i64,
+    ) -> Result<()> {
+        let Some(principal) = principal else {
+            return Ok(());
+        };
+
+        let mut pipe = redis::pipe();
+        push_index_expiry(&mut pipe, &self.index_key(&principal), timestamp);
+        pipe.query_async(conn).await.map_err(Error::store)
+    }
+
+    /// Removes a session which no longer exists from the index set of the
+    /// principal it was associated with.
+    async fn unindex(
+        &self,
+        conn: &mut <C as GetConnection>::Connection,
+        session_key: &SessionKey,
+        principal: Option<String>,
+    ) -> Result<()> {
+        let Some(principal) = principal else {
+            return Ok(());
+        };
+
+        redis::Cmd::srem(self.index_key(&principal), self.member(session_key))
+            .query_async(conn)
+            .await
+            .map_err(Error::store)
+    }
+
+    fn to_loaded_record(&self, value: &[u8], timestamp: i64) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.deserialize(value)
+            .and_then(|data| to_record(data, timestamp))
+            .map(|record| record.with_version(version_of(value)))
+    }
+
     #[cfg(feature = "test-util")]
     fn random<U>(&self) -> U
     where
@@ -211,32 +598,14 @@ impl<T, C: GetConnection> RedisStore<T, C> {
     }
 }
 
-macro_rules! ensure_redis_timestamp {
-    ($timestamp:ident) => {
-        if $timestamp < 0 {
-            return Err(err_redis_timestamp($timestamp));
-        }
-    };
-}
-
-impl<T, C: GetConnection> SessionStore<T> for RedisStore<T, C>
+impl<T, C: GetConnection, K: Codec> RedisStore<T, C, K>
 where
-    T: 'static + Send + Sync + Serialize + DeserializeOwned,
     C::Connection: Sync,
 {
-}
-
-#[async_trait]
-impl<T, C: GetConnection> SessionStoreImpl<T> for RedisStore<T, C>
-where
-    T: 'static + Send + Sync + Serialize + DeserializeOwned,
-    C::Connection: Sync,
-{
-    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+    async fn create_serialized(&self, serialized: &[u8], ttl: Ttl) -> Result<SessionKey> {
         let mut conn = self.connection().await?;
 
         let expiry = set_expiry_from_ttl(ttl)?;
-        let serialized = serialize(data)?;
 
         let options = SetOptions::default()
             .conditional_set(ExistenceCheck::NX) // Only set the key if it does not exist
@@ -250,7 +619,7 @@ where
             let key = self.redis_key(&session_key);
 
             let v: redis::Value = conn
-                .set_options(&key, &serialized, options)
+                .set_options(&key, serialized, options)
                 .await
                 .map_err(Error::store)?;
 
@@ -263,8 +632,120 @@ where
         Err(Error::max_iterations_reached())
     }
 
+    async fn update_serialized(
+        &self,
+        session_key: &SessionKey,
+        serialized: &[u8],
+        ttl: Ttl,
+    ) -> Result<()> {
+        let key = self.redis_key(session_key);
+        let mut conn = self.connection().await?;
+
+        let expiry = set_expiry_from_ttl(ttl)?;
+
+        let timestamp = timestamp_from_ttl(ttl)?;
+
+        let options = SetOptions::default().with_expiration(expiry);
+
+        // The principal the session is associated with, if any, expires along
+        // with it
+        let principal_key = self.principal_key(session_key);
+        let (principal,) = redis::pipe()
+            .atomic()
+            .set_options(&key, serialized, options)
+            .ignore()
+            .expire_at(&principal_key, timestamp)
+            .ignore()
+            .get(&principal_key)
+            .query_async::<(Option<String>,)>(&mut conn)
+            .await
+            .map_err(Error::store)?;
+
+        self.extend_index(&mut conn, principal, timestamp).await
+    }
+}
+
+macro_rules! ensure_redis_timestamp {
+    ($timestamp:ident) => {
+        if $timestamp < 0 {
+            return Err(err_redis_timestamp($timestamp));
+        }
+    };
+}
+
+impl<T, C: GetConnection, K: Codec> SessionStore<T> for RedisStore<T, C, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+    C::Connection: Sync,
+{
+}
+
+#[async_trait]
+impl<T, C: GetConnection, K: Codec> SessionStoreImpl<T> for RedisStore<T, C, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+    C::Connection: Sync,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        self.create_serialized(&self.serialize(data)?, ttl).await
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        _version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        // Versions are derived from the stored data, so `version` can't be
+        // recorded
+        let serialized = self.serialize(data)?;
+        let session_key = self.create_serialized(&serialized, ttl).await?;
+        Ok((session_key, Some(version_of(&serialized))))
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        _version: Version,
+    ) -> Result<CreateWithKey> {
+        // Versions are derived from the stored data, so `version` can't be
+        // recorded
+        let serialized = self.serialize(data)?;
+        let key = self.redis_key(session_key);
+        let mut conn = self.connection().await?;
+
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX) // Only set the key if it does not exist
+            .with_expiration(set_expiry_from_ttl(ttl)?);
+
+        let v: redis::Value = conn
+            .set_options(&key, &serialized, options)
+            .await
+            .map_err(Error::store)?;
+
+        match v {
+            redis::Value::Nil => Ok(CreateWithKey::Occupied), // Conflict with NX: key exists
+            _ => Ok(CreateWithKey::Created(Some(version_of(&serialized)))),
+        }
+    }
+
     async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
         let key = self.redis_key(session_key);
+
+        match self.load_from_replica(&key).await {
+            Ok(Some(record)) => return Ok(Some(record)),
+            Ok(None) => {}
+            // The primary may still be reachable
+            Err(_err) => {
+                tower_sesh_core::warn!(
+                    err = %tower_sesh_core::util::Report::new(_err),
+                    "error loading session from replica, loading from primary"
+                );
+            }
+        }
+
         let mut conn = self.connection().await?;
 
         let (value, timestamp) = redis::pipe()
@@ -282,54 +763,368 @@ where
             None => Ok(None),
             Some(value) => {
                 ensure_redis_timestamp!(timestamp);
-                deserialize(&value)
-                    .and_then(|data| to_record(data, timestamp))
-                    .map(Some)
+                self.to_loaded_record(&value, timestamp).map(Some)
             }
         }
     }
 
     async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.update_serialized(session_key, &self.serialize(data)?, ttl)
+            .await
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
         let key = self.redis_key(session_key);
         let mut conn = self.connection().await?;
 
-        let expiry = set_expiry_from_ttl(ttl)?;
-        let serialized = serialize(data)?;
+        let timestamp = timestamp_from_ttl(ttl)?;
 
-        let options = SetOptions::default().with_expiration(expiry);
+        let principal_key = self.principal_key(session_key);
+        let (principal,) = redis::pipe()
+            .atomic()
+            .expire_at(key, timestamp)
+            .ignore()
+            .expire_at(&principal_key, timestamp)
+            .ignore()
+            .get(&principal_key)
+            .query_async::<(Option<String>,)>(&mut conn)
+            .await
+            .map_err(Error::store)?;
 
-        let _: () = conn
-            .set_options(&key, serialized, options)
+        self.extend_index(&mut conn, principal, timestamp).await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        let key = self.redis_key(session_key);
+        let mut conn = self.connection().await?;
+
+        let (principal,) = redis::pipe()
+            .atomic()
+            .del(&key)
+            .ignore()
+            .get_del(self.principal_key(session_key))
+            .query_async::<(Option<String>,)>(&mut conn)
             .await
             .map_err(Error::store)?;
 
-        Ok(())
+        self.unindex(&mut conn, session_key, principal).await
     }
 
-    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let old_key = self.redis_key(session_key);
+        let mut conn = self.connection().await?;
+
+        let timestamp = timestamp_from_ttl(ttl)?;
+        let serialized = self.serialize(data)?;
+
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let new_session_key = self.cycled_session_key(session_key);
+            let new_key = self.redis_key(&new_session_key);
+
+            let (moved, principal) = CYCLE_KEY_SCRIPT
+                .key(&old_key)
+                .key(&new_key)
+                .key(self.principal_key(session_key))
+                .arg(&serialized)
+                .arg(timestamp)
+                .invoke_async::<(bool, Option<String>)>(&mut conn)
+                .await
+                .map_err(Error::store)?;
+
+            if moved {
+                self.unindex(&mut conn, session_key, principal).await?;
+                return Ok(new_session_key);
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
         let key = self.redis_key(session_key);
         let mut conn = self.connection().await?;
 
         let timestamp = timestamp_from_ttl(ttl)?;
+        let serialized = self.serialize(data)?;
+
+        let (updated, current, current_timestamp, principal) = UPDATE_IF_SCRIPT
+            .key(&key)
+            .key(self.principal_key(session_key))
+            .arg(format!("{:016x}", version.as_u64()))
+            .arg(&serialized)
+            .arg(timestamp)
+            .arg(SESSION_EXPIRY_SECONDS_DEFAULT)
+            .invoke_async::<(bool, Option<Vec<u8>>, i64, Option<String>)>(&mut conn)
+            .await
+            .map_err(Error::store)?;
+
+        if updated {
+            self.extend_index(&mut conn, principal, timestamp).await?;
+            return Ok(UpdateIf::Updated(Some(version_of(&serialized))));
+        }
+
+        match current {
+            None => Ok(UpdateIf::Conflict(None)),
+            Some(value) => {
+                ensure_redis_timestamp!(current_timestamp);
+                self.to_loaded_record(&value, current_timestamp)
+                    .map(|record| UpdateIf::Conflict(Some(record)))
+            }
+        }
+    }
 
-        let _: () = conn.expire_at(key, timestamp).await.map_err(Error::store)?;
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        _version: Version,
+    ) -> Result<Option<Version>> {
+        // Versions are derived from the stored data, so `version` can't be
+        // recorded
+        let serialized = self.serialize(data)?;
+        self.update_serialized(session_key, &serialized, ttl)
+            .await?;
+        Ok(Some(version_of(&serialized)))
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl<T, C: GetConnection, K: Codec> SessionIndex for RedisStore<T, C, K>
+where
+    T: 'static,
+    C::Connection: Sync,
+{
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()> {
+        let index_key = self.index_key(principal);
+        let mut conn = self.connection().await?;
+
+        let timestamp: i64 = ASSOCIATE_SCRIPT
+            .key(self.redis_key(session_key))
+            .key(self.principal_key(session_key))
+            .arg(principal)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(Error::store)?;
+        if timestamp == -2 {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .sadd(&index_key, self.member(session_key))
+            .ignore();
+        if timestamp >= 0 {
+            push_index_expiry(&mut pipe, &index_key, timestamp);
+        }
+        let _: () = pipe.query_async(&mut conn).await.map_err(Error::store)?;
 
         Ok(())
     }
 
-    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
-        let key = self.redis_key(session_key);
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>> {
+        let mut conn = self.connection().await?;
+
+        conn.get(self.principal_key(session_key))
+            .await
+            .map_err(Error::store)
+    }
+
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>> {
+        let index_key = self.index_key(principal);
+        let mut conn = self.connection().await?;
+
+        let members: Vec<String> = conn.smembers(&index_key).await.map_err(Error::store)?;
+        if members.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let keys = members
+            .iter()
+            .map(|member| format!("{}{}", self.config.key_prefix, member))
+            .collect::<Vec<_>>();
+        let timestamps: Vec<i64> = if self.config.hash_tags {
+            // The sessions are spread across slots, which a pipeline can't
+            // span in a cluster, so they're requested concurrently instead.
+            // Each request gets its own handle to the multiplexed connection.
+            futures_util::future::try_join_all(keys.iter().map(|key| async move {
+                let mut conn = self.connection().await?;
+                conn.expire_time(key).await.map_err(Error::store)
+            }))
+            .await?
+        } else {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.expire_time(key);
+            }
+            pipe.query_async(&mut conn).await.map_err(Error::store)?
+        };
+
+        let mut sessions = Vec::with_capacity(members.len());
+        let mut stale = Vec::new();
+        for (member, timestamp) in members.into_iter().zip(timestamps) {
+            // -2 means the session no longer exists. (-1 means it has no
+            // expiry, which `load` corrects.)
+            if timestamp == -2 || SessionKey::decode(strip_hash_tag(&member)).is_err() {
+                stale.push(member);
+                continue;
+            }
+            let ttl = match timestamp {
+                -1 => {
+                    tower_sesh_core::time::now()
+                        + std::time::Duration::from_secs(u64::from(SESSION_EXPIRY_SECONDS_DEFAULT))
+                }
+                timestamp => to_ttl(timestamp)?,
+            };
+            sessions.push(IndexedSession::new(StorageId::new(member), ttl));
+        }
+
+        if !stale.is_empty() {
+            let _: () = conn.srem(&index_key, stale).await.map_err(Error::store)?;
+        }
+
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()> {
+        let index_key = self.index_key(principal);
         let mut conn = self.connection().await?;
 
-        let _: () = conn.del(&key).await.map_err(Error::store)?;
+        // The session is removed from the index first, so that it is only
+        // deleted if it belongs to the principal.
+        let removed: u64 = conn
+            .srem(&index_key, id.as_str())
+            .await
+            .map_err(Error::store)?;
+        if removed == 0 {
+            return Ok(());
+        }
+
+        let key = format!("{}{}", self.config.key_prefix, id.as_str());
+        let principal_key = format!("{key}{PRINCIPAL_KEY_SUFFIX}");
+        let _: () = conn
+            .del(&[key, principal_key])
+            .await
+            .map_err(Error::store)?;
 
         Ok(())
     }
+
+    async fn delete_sessions(&self, principal: &str) -> Result<()> {
+        let index_key = self.index_key(principal);
+        let mut conn = self.connection().await?;
+
+        // Members are popped from the set rather than read and then deleted,
+        // so that a session associated concurrently is never dropped from the
+        // index without being deleted.
+        const BATCH_SIZE: usize = 128;
+        loop {
+            let members: Vec<String> = redis::cmd("SPOP")
+                .arg(&index_key)
+                .arg(BATCH_SIZE)
+                .query_async(&mut conn)
+                .await
+                .map_err(Error::store)?;
+            if members.is_empty() {
+                return Ok(());
+            }
+
+            let keys = members
+                .iter()
+                .flat_map(|member| {
+                    let key = format!("{}{}", self.config.key_prefix, member);
+                    let principal_key = format!("{key}{PRINCIPAL_KEY_SUFFIX}");
+                    [key, principal_key]
+                })
+                .collect::<Vec<_>>();
+            let _: () = conn.del(keys).await.map_err(Error::store)?;
+        }
+    }
 }
 
+/// Sets the data only if the version of the current data matches, in a single
+/// atomic step. The version of the data is the first 64 bits of its SHA-1
+/// hash, which is also computed by [`version_of`].
+///
+/// The principal the session is associated with, if any, expires along with
+/// the updated data.
+///
+/// Returns `{1, false, 0, <principal>}` if the data was updated,
+/// `{0, false, 0, false}` if the key does not exist, or
+/// `{0, <current data>, <current expiry>, false}` if the version does not
+/// match.
+static UPDATE_IF_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local current = redis.call('GET', KEYS[1])
+        if not current then
+            return {0, false, 0, false}
+        end
+        if string.sub(redis.sha1hex(current), 1, 16) ~= ARGV[1] then
+            redis.call('EXPIRE', KEYS[1], ARGV[4], 'NX')
+            return {0, current, redis.call('EXPIRETIME', KEYS[1]), false}
+        end
+        redis.call('SET', KEYS[1], ARGV[2], 'EXAT', ARGV[3])
+        redis.call('EXPIREAT', KEYS[2], ARGV[3])
+        return {1, false, 0, redis.call('GET', KEYS[2])}
+        ",
+    )
+});
+
+/// Stores the principal a session is associated with under a key which expires
+/// along with the session, in a single atomic step.
+///
+/// Returns the expiry of the session, or -2 if the session does not exist, in
+/// which case the principal isn't stored.
+static ASSOCIATE_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local timestamp = redis.call('EXPIRETIME', KEYS[1])
+        if timestamp == -1 then
+            redis.call('SET', KEYS[2], ARGV[1])
+        elseif timestamp ~= -2 then
+            redis.call('SET', KEYS[2], ARGV[1], 'EXAT', timestamp)
+        end
+        return timestamp
+        ",
+    )
+});
+
+/// Sets the data under the new key (only if it does not exist) and deletes the
+/// old key, along with the principal it was associated with, in a single
+/// atomic step.
+///
+/// Returns `{1, <principal>}` if the data was moved, or `{0, false}` if the new
+/// key already exists.
+static CYCLE_KEY_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('SET', KEYS[2], ARGV[1], 'NX', 'EXAT', ARGV[2]) then
+            redis.call('DEL', KEYS[1])
+            return {1, redis.call('GETDEL', KEYS[3])}
+        end
+        return {0, false}
+        ",
+    )
+});
+
 #[doc(hidden)]
 #[cfg(feature = "test-util")]
-impl<T, C: GetConnection, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for RedisStore<T, C>
+impl<T, C: GetConnection, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng>
+    for RedisStore<T, C, K>
 where
     Rng: rand::CryptoRng + Send + 'static,
 {
@@ -338,6 +1133,22 @@ where
     }
 }
 
+/// Adds commands to a pipeline which extend the expiry of an index set to
+/// `timestamp`, unless it already expires later.
+///
+/// The set then expires no earlier than the last of its sessions, and is
+/// removed along with them rather than left behind.
+fn push_index_expiry(pipe: &mut redis::Pipeline, index_key: &str, timestamp: i64) {
+    // `GT` treats a key without an expiry as expiring never, so `NX` is needed
+    // to set the first expiry
+    pipe.expire_at(index_key, timestamp)
+        .arg("NX")
+        .ignore()
+        .expire_at(index_key, timestamp)
+        .arg("GT")
+        .ignore();
+}
+
 fn set_expiry_from_ttl(ttl: Ttl) -> Result<SetExpiry> {
     match u64::try_from(ttl.unix_timestamp()) {
         Ok(timestamp) => Ok(SetExpiry::EXAT(timestamp)),
@@ -352,25 +1163,40 @@ fn timestamp_from_ttl(ttl: Ttl) -> Result<i64> {
     }
 }
 
-fn serialize<T>(value: &T) -> Result<Vec<u8>>
-where
-    T: Serialize,
-{
-    rmp_serde::to_vec_named(value).map_err(Error::serde)
+/// Returns the bits of a session key its hash tag is derived from.
+fn hash_tag_bits(session_key: &SessionKey) -> u16 {
+    NonZeroU128::from(session_key.clone()).get() as u16
 }
 
-fn deserialize<T>(s: &[u8]) -> Result<T>
-where
-    T: DeserializeOwned,
-{
-    rmp_serde::from_slice(s).map_err(Error::serde)
+/// Removes the hash tag, if any, from a member of a session index set.
+fn strip_hash_tag(member: &str) -> &str {
+    match member
+        .strip_prefix('{')
+        .and_then(|rest| rest.split_once('}'))
+    {
+        Some((_, rest)) => rest,
+        None => member,
+    }
+}
+
+/// Returns the version of serialized session data.
+///
+/// Versions are derived from the data itself, so the storage format doesn't
+/// need to change to support them.
+fn version_of(serialized: &[u8]) -> Version {
+    let digest = sha1_smol::Sha1::from(serialized).digest().bytes();
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    Version::from_u64(u64::from_be_bytes(prefix))
 }
 
 fn to_record<T>(data: T, timestamp: i64) -> Result<Record<T>> {
-    match Ttl::from_unix_timestamp(timestamp) {
-        Ok(ttl) => Ok(Record::new(data, ttl)),
-        Err(err) => Err(Error::message(format!("invalid timestamp: {}", err))),
-    }
+    to_ttl(timestamp).map(|ttl| Record::new(data, ttl))
+}
+
+fn to_ttl(timestamp: i64) -> Result<Ttl> {
+    Ttl::from_unix_timestamp(timestamp)
+        .map_err(|err| Error::message(format!("invalid timestamp: {}", err)))
 }
 
 #[cold]
@@ -398,5 +1224,16 @@ mod test {
         fn require_traits<T: SessionStore<()> + Send + Sync + 'static>() {}
 
         require_traits::<RedisStore<(), ConnectionManagerWithRetry>>();
+        #[cfg(feature = "cluster")]
+        require_traits::<RedisStore<(), ClusterConnection>>();
+        #[cfg(feature = "sentinel")]
+        require_traits::<RedisStore<(), SentinelConnection>>();
+    }
+
+    #[test]
+    fn hash_tag_is_stripped_from_members() {
+        let encoded = "ym5hy39HMVwYUJpPW6x_sQ";
+        assert_eq!(strip_hash_tag(encoded), encoded);
+        assert_eq!(strip_hash_tag(&format!("{{3f1c}}{encoded}")), encoded);
     }
 }
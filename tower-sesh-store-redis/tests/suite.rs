@@ -16,31 +16,31 @@ mod container {
     use tower_sesh_core::util::Report;
     use xshell::{cmd, Shell};
 
-    pub fn run(image: &str) -> anyhow::Result<ContainerGuard> {
-        #[derive(Clone, Debug)]
-        struct Cleanup<'a> {
-            shell: &'a Shell,
-            id: &'a str,
-        }
-        impl Cleanup<'_> {
-            fn with(self, port: u16) -> ContainerGuard {
-                let guard = ContainerGuard {
-                    shell: self.shell.to_owned(),
-                    id: self.id.to_owned(),
-                    port,
-                };
+    #[derive(Clone, Debug)]
+    struct Cleanup<'a> {
+        shell: &'a Shell,
+        id: &'a str,
+    }
+    impl Cleanup<'_> {
+        fn with(self, port: u16) -> ContainerGuard {
+            let guard = ContainerGuard {
+                shell: self.shell.to_owned(),
+                id: self.id.to_owned(),
+                port,
+            };
 
-                mem::forget(self);
+            mem::forget(self);
 
-                guard
-            }
+            guard
         }
-        impl Drop for Cleanup<'_> {
-            fn drop(&mut self) {
-                stop(self.shell, self.id);
-            }
+    }
+    impl Drop for Cleanup<'_> {
+        fn drop(&mut self) {
+            stop(self.shell, self.id);
         }
+    }
 
+    pub fn run(image: &str) -> anyhow::Result<ContainerGuard> {
         let sh = Shell::new()?;
 
         let run_opts = [
@@ -79,6 +79,162 @@ mod container {
         Ok(guard.with(port))
     }
 
+    /// Runs a single-node Redis Cluster, which owns every slot.
+    ///
+    /// Cross-slot requests are rejected even though there is only one node,
+    /// so this is enough to test that multi-key operations stay in one slot.
+    #[cfg(feature = "cluster")]
+    pub fn run_cluster(image: &str) -> anyhow::Result<ContainerGuard> {
+        use std::net::TcpListener;
+
+        // The node announces its address to clients, so it must listen on the
+        // same port that is published on the host
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let port_arg = port.to_string();
+
+        let sh = Shell::new()?;
+
+        let publish = format!("127.0.0.1:{port}:{port}/tcp");
+        let run_opts = [
+            "--detach",
+            "--publish",
+            &publish,
+            "--rm",
+            "--stop-timeout",
+            "60",
+        ];
+        let server_opts = [
+            "--port",
+            &port_arg,
+            "--cluster-enabled",
+            "yes",
+            "--cluster-announce-ip",
+            "127.0.0.1",
+        ];
+        let id = cmd!(
+            sh,
+            "docker container run {run_opts...} {image} redis-server {server_opts...}"
+        )
+        .read()?;
+
+        // If we return early, this cleans up the running container
+        let guard = Cleanup {
+            shell: &sh,
+            id: &id,
+        };
+
+        // Wait for the server to start, then assign every slot to it
+        wait_until(|| {
+            cmd!(
+                sh,
+                "docker container exec {id} redis-cli -p {port_arg} cluster addslotsrange 0 16383"
+            )
+            .quiet()
+            .read()
+            .is_ok_and(|reply| reply == "OK")
+        })?;
+        wait_until(|| {
+            cmd!(
+                sh,
+                "docker container exec {id} redis-cli -p {port_arg} cluster info"
+            )
+            .quiet()
+            .read()
+            .is_ok_and(|info| info.contains("cluster_state:ok"))
+        })?;
+
+        Ok(guard.with(port))
+    }
+
+    /// Runs a Redis server and a sentinel monitoring it as `mymaster`, and
+    /// returns guards for the server and the sentinel.
+    ///
+    /// Both containers use the host network, since the sentinel tells clients
+    /// the address of the server as it sees it.
+    #[cfg(feature = "sentinel")]
+    pub fn run_sentinel(image: &str) -> anyhow::Result<(ContainerGuard, ContainerGuard)> {
+        use std::net::TcpListener;
+
+        let (server_port, sentinel_port) = {
+            let server = TcpListener::bind("127.0.0.1:0")?;
+            let sentinel = TcpListener::bind("127.0.0.1:0")?;
+            (server.local_addr()?.port(), sentinel.local_addr()?.port())
+        };
+        let server_port_arg = server_port.to_string();
+        let sentinel_port_arg = sentinel_port.to_string();
+
+        let sh = Shell::new()?;
+
+        let run_opts = [
+            "--detach",
+            "--network",
+            "host",
+            "--rm",
+            "--stop-timeout",
+            "60",
+        ];
+
+        let server_id = cmd!(
+            sh,
+            "docker container run {run_opts...} {image} redis-server --bind 127.0.0.1 --port {server_port_arg}"
+        )
+        .read()?;
+        let server = Cleanup {
+            shell: &sh,
+            id: &server_id,
+        };
+
+        // A sentinel requires a writable configuration file
+        let script = format!(
+            "touch /tmp/sentinel.conf && exec redis-server /tmp/sentinel.conf --sentinel --bind 127.0.0.1 --port {sentinel_port}"
+        );
+        let sentinel_id = cmd!(
+            sh,
+            "docker container run {run_opts...} {image} sh -c {script}"
+        )
+        .read()?;
+        let sentinel = Cleanup {
+            shell: &sh,
+            id: &sentinel_id,
+        };
+
+        wait_until(|| {
+            cmd!(
+                sh,
+                "docker container exec {server_id} redis-cli -p {server_port_arg} ping"
+            )
+            .quiet()
+            .read()
+            .is_ok_and(|reply| reply == "PONG")
+        })?;
+        wait_until(|| {
+            cmd!(
+                sh,
+                "docker container exec {sentinel_id} redis-cli -p {sentinel_port_arg} sentinel monitor mymaster 127.0.0.1 {server_port_arg} 1"
+            )
+            .quiet()
+            .read()
+            .is_ok_and(|reply| reply == "OK")
+        })?;
+
+        Ok((server.with(server_port), sentinel.with(sentinel_port)))
+    }
+
+    #[cfg(any(feature = "cluster", feature = "sentinel"))]
+    fn wait_until(mut f: impl FnMut() -> bool) -> anyhow::Result<()> {
+        use std::{thread, time::Duration};
+
+        let mut attempts = 0;
+        while !f() {
+            if attempts >= 100 {
+                anyhow::bail!("timed out waiting for redis to come up");
+            }
+            attempts += 1;
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
     fn stop(sh: &Shell, id: &str) {
         fn _stop(sh: &Shell, id: &str) -> xshell::Result<()> {
             cmd!(sh, "docker container stop --timeout 1 {id}")
@@ -122,6 +278,30 @@ where
         .expect("failed to connect to redis")
 }
 
+#[cfg(feature = "cluster")]
+async fn cluster_store<T>(
+    url: &str,
+) -> RedisStore<T, tower_sesh_store_redis::connection::ClusterConnection>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    RedisStore::open_cluster([url])
+        .await
+        .expect("failed to connect to redis cluster")
+}
+
+#[cfg(feature = "sentinel")]
+async fn sentinel_store<T>(
+    url: &str,
+) -> RedisStore<T, tower_sesh_store_redis::connection::SentinelConnection>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    RedisStore::open_sentinel([url], "mymaster")
+        .await
+        .expect("failed to connect to redis through sentinel")
+}
+
 mod redis_store {
     use tower_sesh_test::test_suite;
 
@@ -173,3 +353,42 @@ mod valkey_caching_store {
         ),
     }
 }
+
+#[cfg(feature = "cluster")]
+mod redis_cluster_store {
+    use tower_sesh_test::test_suite;
+
+    use super::{cluster_store, container, REDIS_IMAGE};
+
+    test_suite! {
+        guard: container = container::run_cluster(REDIS_IMAGE).unwrap(),
+        store: cluster_store(&format!("redis://127.0.0.1:{}", container.port)).await,
+    }
+}
+
+#[cfg(feature = "cluster")]
+mod redis_cluster_store_hashed_keys {
+    use tower_sesh_core::key::KeyHasher;
+    use tower_sesh_test::test_suite;
+
+    use super::{cluster_store, container, REDIS_IMAGE};
+
+    test_suite! {
+        guard: container = container::run_cluster(REDIS_IMAGE).unwrap(),
+        store: cluster_store(&format!("redis://127.0.0.1:{}", container.port))
+            .await
+            .hash_keys(KeyHasher::new(&[1; 32])),
+    }
+}
+
+#[cfg(feature = "sentinel")]
+mod redis_sentinel_store {
+    use tower_sesh_test::test_suite;
+
+    use super::{container, sentinel_store, REDIS_IMAGE};
+
+    test_suite! {
+        guard: containers = container::run_sentinel(REDIS_IMAGE).unwrap(),
+        store: sentinel_store(&format!("redis://127.0.0.1:{}", containers.1.port)).await,
+    }
+}
@@ -0,0 +1,978 @@
+//! The SQLite store for [`tower-sesh`].
+//!
+//! [`tower-sesh`]: https://docs.rs/tower-sesh/latest/tower_sesh/
+//!
+//! # Schema
+//!
+//! [`SqliteStore::migrate`] creates the following tables, named after the
+//! store's [table name](SqliteStore::table_name) (`tower_sesh_sessions` by
+//! default):
+//!
+//! - `tower_sesh_sessions` holds the sessions. Each session is stored under
+//!   its Base64-encoded session key in the `id` column, with its serialized
+//!   data, its version, and its expiry in the `expires_at` column (a Unix
+//!   timestamp in nanoseconds, which is indexed).
+//! - `tower_sesh_sessions_principals` indexes sessions by principal. See
+//!   [`SessionIndex`].
+//! - `tower_sesh_sessions_migrations` records the migrations which have been
+//!   applied, so that future versions of this crate can upgrade the schema
+//!   in place.
+//!
+//! Expired sessions are never loaded, but they aren't removed from the
+//! database until [`purge_expired`] is called. Call it periodically, or let a
+//! [`Reaper`] spawned by [`SqliteStore::spawn_reaper`] do so.
+//!
+//! [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+#![warn(missing_debug_implementations)]
+#![deny(rustdoc::broken_intra_doc_links)]
+#![doc(test(
+    no_crate_inject,
+    attr(
+        deny(warnings, rust_2018_idioms, single_use_lifetimes),
+        allow(dead_code, unused_assignments, unused_variables)
+    )
+))]
+
+use std::{borrow::Cow, fmt, marker::PhantomData, str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use rand::{rngs::ThreadRng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    Row,
+};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{
+        CreateWithKey, Error, IndexedSession, Result, SessionIndex, SessionStoreImpl, StorageId,
+        UpdateIf, Version,
+    },
+    time::{Clock, SystemClock},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+pub use sqlx;
+pub use tower_sesh_core::{codec, reaper::Reaper};
+
+/// A session store backed by an SQLite database.
+///
+/// See the [crate-level documentation](crate#schema) for the tables used by
+/// the store.
+pub struct SqliteStore<T, K: Codec = MessagePack> {
+    pool: SqlitePool,
+    config: Config,
+    codec: K,
+    clock: Arc<dyn Clock>,
+
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    table_name: Cow<'static, str>,
+    hasher: Option<KeyHasher>,
+}
+
+const DEFAULT_TABLE_NAME: &str = "tower_sesh_sessions";
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            table_name: Cow::Borrowed(DEFAULT_TABLE_NAME),
+            hasher: None,
+        }
+    }
+}
+
+/// The schema migrations, in the order they are applied.
+///
+/// `{sessions}`, `{principals}`, and `{index_prefix}` are replaced with the
+/// quoted table names and the prefix of index names. Migrations must never be
+/// changed once released; change the schema by appending a migration instead.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE {sessions} (
+        id TEXT PRIMARY KEY NOT NULL,
+        data BLOB NOT NULL,
+        expires_at INTEGER NOT NULL,
+        version INTEGER NOT NULL
+    ) WITHOUT ROWID;
+    CREATE INDEX "{index_prefix}_expires_at" ON {sessions} (expires_at);
+    CREATE TABLE {principals} (
+        principal TEXT NOT NULL,
+        id TEXT NOT NULL,
+        PRIMARY KEY (principal, id)
+    ) WITHOUT ROWID;
+    CREATE INDEX "{index_prefix}_principals_id" ON {principals} (id);
+"#];
+
+impl<T> SqliteStore<T> {
+    /// Connects to an SQLite database and returns a store with default
+    /// configuration values, creating the database and its tables if they
+    /// don't exist.
+    ///
+    /// `url` should be a string in the following format:
+    ///
+    /// ```not_rust
+    /// sqlite://<path>[?<options>]
+    /// ```
+    ///
+    /// See [`SqliteConnectOptions`] for the supported options.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_store_sqlite::SqliteStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = SqliteStore::<SessionData>::open("sqlite://sessions.db").await?;
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub async fn open(url: &str) -> sqlx::Result<SqliteStore<T>> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+
+        let store = SqliteStore::with_pool(pool);
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    /// Returns a store using an existing connection pool, with default
+    /// configuration values.
+    ///
+    /// Unlike [`open`], this doesn't create the store's tables. Call
+    /// [`migrate`] once the store is configured.
+    ///
+    /// [`open`]: SqliteStore::open
+    /// [`migrate`]: SqliteStore::migrate
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sqlx::SqlitePool;
+    /// use tower_sesh_store_sqlite::SqliteStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let pool = SqlitePool::connect("sqlite://app.db").await?;
+    /// let store = SqliteStore::<SessionData>::with_pool(pool).table_name("sessions");
+    /// store.migrate().await?;
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub fn with_pool(pool: SqlitePool) -> SqliteStore<T> {
+        SqliteStore {
+            pool,
+            config: Config::default(),
+            codec: MessagePack,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "test-util")]
+            rng: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K: Codec> SqliteStore<T, K> {
+    /// Set the name of the table used to store sessions.
+    ///
+    /// The tables used to index sessions and to record migrations are named
+    /// by appending `_principals` and `_migrations` to this name.
+    ///
+    /// Default is `"tower_sesh_sessions"`.
+    pub fn table_name(mut self, table_name: impl Into<Cow<'static, str>>) -> SqliteStore<T, K> {
+        self.config.table_name = table_name.into();
+        self
+    }
+
+    /// Store each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// The `id` of a session is then the Base64-encoded hash, so the session
+    /// keys held by clients can't be recovered from the database. See
+    /// [`KeyHasher`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tower_sesh_core::key::KeyHasher;
+    /// use tower_sesh_store_sqlite::SqliteStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// fn secret() -> Vec<u8> {
+    ///     // TODO: Where do you get a secret?
+    /// # vec![0; 32]
+    /// }
+    ///
+    /// # tokio_test::block_on(async {
+    /// let store = SqliteStore::<SessionData>::open("sqlite://sessions.db")
+    ///     .await?
+    ///     .hash_keys(KeyHasher::new(&secret()));
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> SqliteStore<T, K> {
+        self.config.hasher = Some(hasher);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data.
+    ///
+    /// Note that changing the codec of an existing deployment will make
+    /// previously stored sessions unreadable.
+    ///
+    /// Large payloads can be compressed by wrapping the codec in
+    /// [`Compressed`](codec::Compressed), which requires the `zstd` or `lz4`
+    /// feature.
+    ///
+    /// Default is [`MessagePack`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "json")] {
+    /// use tower_sesh_store_sqlite::{codec::Json, SqliteStore};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = SqliteStore::<SessionData>::open("sqlite://sessions.db")
+    ///     .await?
+    ///     .codec(Json);
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// # }
+    /// ```
+    pub fn codec<K2: Codec>(self, codec: K2) -> SqliteStore<T, K2> {
+        SqliteStore {
+            pool: self.pool,
+            config: self.config,
+            codec,
+            clock: self.clock,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Set the [`Clock`] used to check whether sessions have expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> SqliteStore<T, K> {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the connection pool used by the store.
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Spawns a background task which removes expired sessions from the
+    /// database every `interval`, as [`purge_expired`] does.
+    ///
+    /// The number of sessions removed by each sweep is logged at the debug
+    /// level, and the running total is available from [`Reaper::removed`].
+    ///
+    /// The reaper runs on its own thread, but its queries are driven by the
+    /// Tokio runtime this is called from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, if the OS fails to create a thread, or
+    /// if called outside of a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// use tower_sesh_store_sqlite::SqliteStore;
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// # tokio_test::block_on(async {
+    /// let store = Arc::new(SqliteStore::<SessionData>::open("sqlite://sessions.db").await?);
+    /// let reaper = store.spawn_reaper(Duration::from_secs(60 * 60));
+    ///
+    /// // ...
+    ///
+    /// reaper.shutdown();
+    /// # Ok::<(), sqlx::Error>(())
+    /// # }).unwrap();
+    /// ```
+    ///
+    /// [`purge_expired`]: tower_sesh_core::store::SessionStoreImpl::purge_expired
+    #[track_caller]
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> Reaper
+    where
+        T: 'static,
+    {
+        let handle = tokio::runtime::Handle::current();
+        Reaper::spawn(self, interval, move |store| handle.block_on(store.purge()))
+    }
+
+    /// Creates the store's tables if they don't exist, and upgrades them to
+    /// the latest schema.
+    ///
+    /// This is called by [`open`]. Migrations run in a single transaction, so
+    /// it is safe to call this from multiple processes at once.
+    ///
+    /// [`open`]: SqliteStore::open
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the migrations fail, or if the tables were created
+    /// by a newer version of this crate.
+    pub async fn migrate(&self) -> sqlx::Result<()> {
+        let migrations_table = quote_identifier(&format!("{}_migrations", self.config.table_name));
+
+        // `BEGIN IMMEDIATE` takes the write lock up front, so that concurrent
+        // migrations are serialized rather than failing to upgrade their lock
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        sqlx::raw_sql(&format!(
+            "CREATE TABLE IF NOT EXISTS {migrations_table} (
+                version INTEGER PRIMARY KEY NOT NULL,
+                applied_at INTEGER NOT NULL
+            )"
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        let applied: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {migrations_table}"))
+            .fetch_one(&mut *tx)
+            .await?;
+        let applied = usize::try_from(applied).unwrap_or(usize::MAX);
+        if applied > MIGRATIONS.len() {
+            return Err(sqlx::Error::Configuration(
+                format!(
+                    "the session tables were migrated by a newer version of `{}` \
+                     (schema version {applied}, latest known version {})",
+                    env!("CARGO_PKG_NAME"),
+                    MIGRATIONS.len(),
+                )
+                .into(),
+            ));
+        }
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let migration = migration
+                .replace("{sessions}", &self.sessions_table())
+                .replace("{principals}", &self.principals_table())
+                .replace(
+                    "{index_prefix}",
+                    &self.config.table_name.replace('"', "\"\""),
+                );
+            sqlx::raw_sql(&migration).execute(&mut *tx).await?;
+
+            sqlx::query(&format!(
+                "INSERT INTO {migrations_table} (version, applied_at) VALUES (?, ?)"
+            ))
+            .bind(version as i64 + 1)
+            .bind(self.clock.now().unix_timestamp())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
+
+impl<T, K: Codec> fmt::Debug for SqliteStore<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteStore")
+            .field("pool", &self.pool)
+            .field("config", &self.config)
+            .field("codec", &std::any::type_name::<K>())
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+impl<T, K: Codec> SqliteStore<T, K> {
+    /// Removes every expired session, along with its principal, returning
+    /// the number of sessions removed.
+    async fn purge(&self) -> Result<u64> {
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(Error::store)?;
+
+        let purged = sqlx::query(&format!(
+            "DELETE FROM {} WHERE expires_at < ?",
+            self.sessions_table()
+        ))
+        .bind(self.now()?)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?
+        .rows_affected();
+
+        if purged > 0 {
+            sqlx::query(&format!(
+                "DELETE FROM {principals} WHERE NOT EXISTS (
+                    SELECT 1 FROM {sessions} WHERE {sessions}.id = {principals}.id
+                )",
+                principals = self.principals_table(),
+                sessions = self.sessions_table(),
+            ))
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::store)?;
+        }
+
+        tx.commit().await.map_err(Error::store)?;
+
+        Ok(purged)
+    }
+
+    fn sessions_table(&self) -> String {
+        quote_identifier(&self.config.table_name)
+    }
+
+    fn principals_table(&self) -> String {
+        quote_identifier(&format!("{}_principals", self.config.table_name))
+    }
+
+    /// Returns the `id` the session identified by `session_key` is stored
+    /// under.
+    fn id(&self, session_key: &SessionKey) -> String {
+        match &self.config.hasher {
+            Some(hasher) => hasher.hash(session_key).encode(),
+            None => session_key.encode(),
+        }
+    }
+
+    fn now(&self) -> Result<i64> {
+        timestamp_from_ttl(self.clock.now())
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.codec.decode(bytes)
+    }
+
+    fn to_record(&self, row: &sqlx::sqlite::SqliteRow) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.deserialize(row.try_get("data").map_err(Error::store)?)?;
+        let ttl = to_ttl(row.try_get("expires_at").map_err(Error::store)?)?;
+        let version = row.try_get::<i64, _>("version").map_err(Error::store)?;
+        Ok(Record::new(data, ttl).with_version(Version::from_u64(version as u64)))
+    }
+
+    /// Inserts or replaces the session stored under `id`.
+    async fn upsert(&self, id: &str, data: &T, ttl: Ttl, version: Version) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let serialized = self.serialize(data)?;
+        let expires_at = timestamp_from_ttl(ttl)?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {} (id, data, expires_at, version) VALUES (?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                data = excluded.data,
+                expires_at = excluded.expires_at,
+                version = excluded.version",
+            self.sessions_table()
+        ))
+        .bind(id)
+        .bind(serialized)
+        .bind(expires_at)
+        .bind(version.as_u64() as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+}
+
+impl<T, K: Codec> SessionStore<T> for SqliteStore<T, K> where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned
+{
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionStoreImpl<T> for SqliteStore<T, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            if let CreateWithKey::Created(version) = self
+                .create_with_key(&session_key, data, ttl, version)
+                .await?
+            {
+                return Ok((session_key, version));
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let result = sqlx::query(&format!(
+            "INSERT OR FAIL INTO {} (id, data, expires_at, version) VALUES (?, ?, ?, ?)",
+            self.sessions_table()
+        ))
+        .bind(self.id(session_key))
+        .bind(self.serialize(data)?)
+        .bind(timestamp_from_ttl(ttl)?)
+        .bind(version.as_u64() as i64)
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(CreateWithKey::Created(Some(version))),
+            Err(err) if is_unique_violation(&err) => Ok(CreateWithKey::Occupied),
+            Err(err) => Err(Error::store(err)),
+        }
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let row = sqlx::query(&format!(
+            "SELECT data, expires_at, version FROM {} WHERE id = ? AND expires_at >= ?",
+            self.sessions_table()
+        ))
+        .bind(self.id(session_key))
+        .bind(self.now()?)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        row.map(|row| self.to_record(&row)).transpose()
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.upsert(&self.id(session_key), data, ttl, new_version())
+            .await
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE {} SET expires_at = ? WHERE id = ? AND expires_at >= ?",
+            self.sessions_table()
+        ))
+        .bind(timestamp_from_ttl(ttl)?)
+        .bind(self.id(session_key))
+        .bind(self.now()?)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        let id = self.id(session_key);
+        let mut tx = self.pool.begin().await.map_err(Error::store)?;
+
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE id = ?",
+            self.sessions_table()
+        ))
+        .bind(id.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE id = ?",
+            self.principals_table()
+        ))
+        .bind(id.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+
+        tx.commit().await.map_err(Error::store)
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let old_id = self.id(session_key);
+        let serialized = self.serialize(data)?;
+        let expires_at = timestamp_from_ttl(ttl)?;
+        let insert_sql = format!(
+            "INSERT OR FAIL INTO {} (id, data, expires_at, version) VALUES (?, ?, ?, ?)",
+            self.sessions_table()
+        );
+
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(Error::store)?;
+
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        let mut new_session_key = None;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+
+            let result = sqlx::query(&insert_sql)
+                .bind(self.id(&session_key))
+                .bind(&serialized)
+                .bind(expires_at)
+                .bind(new_version().as_u64() as i64)
+                .execute(&mut *tx)
+                .await;
+
+            match result {
+                Ok(_) => {
+                    new_session_key = Some(session_key);
+                    break;
+                }
+                Err(err) if is_unique_violation(&err) => {}
+                Err(err) => return Err(Error::store(err)),
+            }
+        }
+        let Some(new_session_key) = new_session_key else {
+            return Err(Error::max_iterations_reached());
+        };
+
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE id = ?",
+            self.sessions_table()
+        ))
+        .bind(&old_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE id = ?",
+            self.principals_table()
+        ))
+        .bind(&old_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+
+        tx.commit().await.map_err(Error::store)?;
+
+        Ok(new_session_key)
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let id = self.id(session_key);
+        let serialized = self.serialize(data)?;
+        let expires_at = timestamp_from_ttl(ttl)?;
+        let now = self.now()?;
+        let new_version = new_version();
+
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(Error::store)?;
+
+        let updated = sqlx::query(&format!(
+            "UPDATE {} SET data = ?, expires_at = ?, version = ?
+            WHERE id = ? AND version = ? AND expires_at >= ?",
+            self.sessions_table()
+        ))
+        .bind(serialized)
+        .bind(expires_at)
+        .bind(new_version.as_u64() as i64)
+        .bind(id.as_str())
+        .bind(version.as_u64() as i64)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?
+        .rows_affected()
+            > 0;
+
+        let result = if updated {
+            UpdateIf::Updated(Some(new_version))
+        } else {
+            let current = sqlx::query(&format!(
+                "SELECT data, expires_at, version FROM {} WHERE id = ? AND expires_at >= ?",
+                self.sessions_table()
+            ))
+            .bind(id.as_str())
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(Error::store)?;
+
+            UpdateIf::Conflict(current.map(|row| self.to_record(&row)).transpose()?)
+        };
+
+        tx.commit().await.map_err(Error::store)?;
+
+        Ok(result)
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        self.upsert(&self.id(session_key), data, ttl, version)
+            .await?;
+        Ok(Some(version))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        self.purge().await
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionIndex for SqliteStore<T, K>
+where
+    T: 'static,
+{
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT OR IGNORE INTO {} (principal, id) VALUES (?, ?)",
+            self.principals_table()
+        ))
+        .bind(principal)
+        .bind(self.id(session_key))
+        .execute(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        Ok(())
+    }
+
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>> {
+        sqlx::query_scalar(&format!(
+            "SELECT principal FROM {} WHERE id = ? LIMIT 1",
+            self.principals_table()
+        ))
+        .bind(self.id(session_key))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::store)
+    }
+
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>> {
+        let rows = sqlx::query(&format!(
+            "SELECT s.id, s.expires_at FROM {principals} p
+            JOIN {sessions} s ON s.id = p.id
+            WHERE p.principal = ? AND s.expires_at >= ?",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(principal)
+        .bind(self.now()?)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::store)?;
+
+        rows.iter()
+            .map(|row| {
+                let id: &str = row.try_get("id").map_err(Error::store)?;
+                let ttl = to_ttl(row.try_get("expires_at").map_err(Error::store)?)?;
+                Ok(IndexedSession::new(StorageId::new(id), ttl))
+            })
+            .collect()
+    }
+
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()> {
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(Error::store)?;
+
+        let unindexed = sqlx::query(&format!(
+            "DELETE FROM {} WHERE principal = ? AND id = ?",
+            self.principals_table()
+        ))
+        .bind(principal)
+        .bind(id.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?
+        .rows_affected();
+        if unindexed > 0 {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE id = ?",
+                self.sessions_table()
+            ))
+            .bind(id.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::store)?;
+        }
+
+        tx.commit().await.map_err(Error::store)
+    }
+
+    async fn delete_sessions(&self, principal: &str) -> Result<()> {
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(Error::store)?;
+
+        sqlx::query(&format!(
+            "DELETE FROM {sessions} WHERE id IN (
+                SELECT id FROM {principals} WHERE principal = ?
+            )",
+            principals = self.principals_table(),
+            sessions = self.sessions_table(),
+        ))
+        .bind(principal)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE principal = ?",
+            self.principals_table()
+        ))
+        .bind(principal)
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::store)?;
+
+        tx.commit().await.map_err(Error::store)
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<T, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for SqliteStore<T, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+/// Quotes an SQL identifier, such as a table name.
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Returns a version for newly written session data.
+///
+/// Versions are random rather than sequential, so that a session which is
+/// deleted and then recreated under the same key doesn't reuse a version.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(err) if err.is_unique_violation())
+}
+
+/// Returns the expiry as a Unix timestamp in nanoseconds, which is how it is
+/// stored in the `expires_at` column.
+fn timestamp_from_ttl(ttl: Ttl) -> Result<i64> {
+    i64::try_from(ttl.unix_timestamp_nanos()).map_err(|_| err_ttl_out_of_range(ttl))
+}
+
+fn to_ttl(timestamp: i64) -> Result<Ttl> {
+    Ttl::from_unix_timestamp_nanos(i128::from(timestamp))
+        .map_err(|err| Error::message(format!("invalid timestamp: {}", err)))
+}
+
+#[cold]
+fn err_ttl_out_of_range(ttl: Ttl) -> Error {
+    Error::message(format!(
+        "expiry can't be stored as a Unix timestamp in nanoseconds: {}",
+        ttl
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        fn require_traits<T: SessionStore<()> + Send + Sync + 'static>() {}
+
+        require_traits::<SqliteStore<()>>();
+    }
+
+    #[test]
+    fn quote_identifier_escapes_quotes() {
+        assert_eq!(quote_identifier("sessions"), r#""sessions""#);
+        assert_eq!(quote_identifier(r#"my "sessions""#), r#""my ""sessions""""#);
+    }
+}
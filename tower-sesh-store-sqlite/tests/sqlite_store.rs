@@ -0,0 +1,154 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+use tower_sesh_core::{
+    time::{Clock, MockClock},
+    SessionStore, Ttl,
+};
+use tower_sesh_store_sqlite::SqliteStore;
+
+async fn pool(dir: &TempDir) -> SqlitePool {
+    let url = format!(
+        "sqlite://{}?mode=rwc",
+        dir.path().join("sessions.db").display()
+    );
+    SqlitePool::connect(&url).await.unwrap()
+}
+
+fn ttl(clock: &MockClock) -> Ttl {
+    clock.now() + Duration::from_secs(60)
+}
+
+#[tokio::test]
+async fn migrate_is_idempotent() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SqliteStore::<()>::with_pool(pool(&dir).await);
+
+    store.migrate().await.unwrap();
+    store.migrate().await.unwrap();
+
+    let versions: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM tower_sesh_sessions_migrations")
+            .fetch_all(store.pool())
+            .await
+            .unwrap();
+    assert_eq!(versions, [1]);
+}
+
+#[tokio::test]
+async fn migrate_rejects_newer_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SqliteStore::<()>::with_pool(pool(&dir).await);
+    store.migrate().await.unwrap();
+
+    sqlx::query("INSERT INTO tower_sesh_sessions_migrations (version, applied_at) VALUES (99, 0)")
+        .execute(store.pool())
+        .await
+        .unwrap();
+
+    let err = store.migrate().await.unwrap_err();
+    assert!(
+        matches!(err, sqlx::Error::Configuration(_)),
+        "unexpected error: {err}"
+    );
+}
+
+#[tokio::test]
+async fn table_name_is_configurable() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SqliteStore::<u32>::with_pool(pool(&dir).await).table_name("my \"sessions\"");
+    store.migrate().await.unwrap();
+    let dyn_store: &dyn SessionStore<u32> = &store;
+
+    let session_key = dyn_store
+        .create(&42, Ttl::now_utc() + Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert_eq!(
+        dyn_store.load(&session_key).await.unwrap().unwrap().data,
+        42
+    );
+
+    let count: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "my ""sessions""""#)
+        .fetch_one(store.pool())
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn purge_expired_returns_number_of_sessions_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = SqliteStore::<()>::with_pool(pool(&dir).await).clock(clock.clone());
+    store.migrate().await.unwrap();
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let long_lived = dyn_store
+        .create(&(), clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+
+    clock.advance(Duration::from_secs(2 * 60));
+
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+    assert!(dyn_store.load(&long_lived).await.unwrap().is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reaper_removes_expired_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = Arc::new(SqliteStore::<()>::with_pool(pool(&dir).await).clock(clock.clone()));
+    store.migrate().await.unwrap();
+    let dyn_store: &dyn SessionStore<()> = store.as_ref();
+
+    let reaper = store.spawn_reaper(Duration::from_millis(1));
+
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    clock.advance(Duration::from_secs(2 * 60));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reaper.removed() < 3 {
+        assert!(Instant::now() < deadline, "reaper didn't remove sessions");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(reaper.shutdown(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn purge_expired_removes_sessions_from_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = SqliteStore::<()>::with_pool(pool(&dir).await).clock(clock.clone());
+    store.migrate().await.unwrap();
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let index = dyn_store.as_index().unwrap();
+
+    let session_key = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    index.associate(&session_key, "alice").await.unwrap();
+
+    clock.advance(Duration::from_secs(2 * 60));
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 1);
+
+    // The session is revived under the same key, but no longer belongs to
+    // "alice"
+    dyn_store
+        .update(&session_key, &(), ttl(&clock))
+        .await
+        .unwrap();
+    assert!(index.sessions("alice").await.unwrap().is_empty());
+}
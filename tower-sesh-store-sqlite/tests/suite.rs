@@ -0,0 +1,56 @@
+#![cfg(feature = "test-util")]
+
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::TempDir;
+use tower_sesh_store_sqlite::SqliteStore;
+
+async fn store<T>(dir: &TempDir) -> SqliteStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let url = format!("sqlite://{}", dir.path().join("sessions.db").display());
+    SqliteStore::open(&url)
+        .await
+        .expect("failed to open database")
+}
+
+mod sqlite_store {
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: store(&dir).await.clock(clock.clone()),
+    }
+}
+
+mod sqlite_store_system_clock {
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        store: store(&dir).await,
+    }
+}
+
+mod sqlite_caching_store {
+    use tower_sesh::store::{CachingStore, MemoryStore};
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    use super::store;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: CachingStore::from_cache_and_store(
+            MemoryStore::new().clock(clock.clone()),
+            store(&dir).await.clock(clock.clone()),
+        ),
+    }
+}
@@ -95,6 +95,24 @@
 //!
 //! For a more practical example, see [`tower-sesh-store-redis`'s test suite].
 //!
+//! ### Using a mock clock
+//!
+//! By default, tests which check that sessions expire wait for them to expire
+//! in real time. If the store accepts a [`Clock`][clock], you can instead bind
+//! a [`MockClock`][mock-clock] with `clock: <clock_ident> = <expr>` (following
+//! `guard:`, if any), and pass it to the store. The test suite then advances
+//! the clock instead of sleeping:
+//!
+//! [clock]: tower_sesh_core::time::Clock
+//! [mock-clock]: tower_sesh_core::time::MockClock
+//!
+//! ```ignore
+//! test_suite! {
+//!     clock: clock = MockClock::default(),
+//!     store: MyStore::new().clock(clock.clone()),
+//! }
+//! ```
+//!
 //! ### Note on test determinism
 //!
 //! Ideally, each test should be isolated from every other test so that the
@@ -162,10 +180,20 @@ macro_rules! doc {
 
 #[cfg(doc)]
 doc! {macro_rules! test_suite {
+    (
+        guard: $guard_ident:ident = $guard:expr,
+        clock: $clock_ident:ident = $clock:expr,
+        store: $store:expr $(,)?
+    ) => {
+        unimplemented!()
+    };
     (guard: $guard_ident:ident = $guard:expr, store: $store:expr $(,)?) => {
         unimplemented!()
     };
     (guard: $guard:expr, store: $store:expr $(,)?) => { unimplemented!() };
+    (clock: $clock_ident:ident = $clock:expr, store: $store:expr $(,)?) => {
+        unimplemented!()
+    };
     (store: $store:expr $(,)?) => { unimplemented!() };
 }}
 
@@ -185,6 +213,10 @@ doc! {macro_rules! test_suite {
 //   `.await`ed then discarded; note that an `Err` returned from a function will
 //   cause the test to falsely indicate success.
 //
+// - The test function must compute times with `support::now()` and wait with
+//   `support::sleep()`, rather than using the system clock directly, so that
+//   test suites using a mock clock don't need to wait.
+//
 // - The test function must pass a `TestRng` to `store` with the
 //   `SessionStoreRng::rng()` method before calling any other methods
 //   on `store`. `TestRng` should be instantiated with a unique, fixed seed
@@ -202,9 +234,17 @@ doc! {macro_rules! test_suite {
 // added under the `// store` comment.
 #[cfg(not(doc))]
 doc! {macro_rules! test_suite {
-    (guard: $guard_ident:ident = $guard:expr, store: $store:expr $(,)?) => {
+    (
+        guard: $guard_ident:ident = $guard:expr,
+        clock: $clock_ident:ident = $clock:expr,
+        store: $store:expr $(,)?
+    ) => {
         $crate::test_suite! {
-            @(guard: $guard_ident = $guard, store: $store) => {
+            @(
+                guard: $guard_ident = $guard,
+                clock: $clock_ident = $clock,
+                store: $store
+            ) => {
                 // Test Suite
 
                 smoke
@@ -212,6 +252,7 @@ doc! {macro_rules! test_suite {
                 // store
                 create_does_collision_resolution
                 loading_session_after_create
+                create_with_key_does_not_overwrite_existing_session
                 loading_session_after_update_nonexisting
                 loading_session_after_update_existing
                 loading_session_after_update_ttl
@@ -227,23 +268,48 @@ doc! {macro_rules! test_suite {
                 delete_after_create
                 delete_after_update
                 delete_does_not_error_for_missing_entry
+                load_many_and_delete_many
                 ttl_with_999_999_999_nanoseconds_create
                 ttl_with_999_999_999_nanoseconds_update_nonexisting
                 ttl_with_999_999_999_nanoseconds_update_existing
                 ttl_with_999_999_999_nanoseconds_update_ttl
                 update_ttl_extends_session_that_would_otherwise_expire
-                // FIXME: Remove this `ignore` when `MemoryStore` is fixed
-                #[ignore = "this test fails with `MemoryStore`"]
                 update_ttl_does_not_revive_expired_session
+                loading_session_after_cycle_key
+                cycle_key_does_collision_resolution
+                cycle_key_creates_session_for_missing_entry
+                update_if_with_current_version
+                update_if_after_update
+                writes_with_version_report_loaded_version
+                update_if_with_stale_version
+                update_if_for_missing_session
+                index_lists_sessions_of_principal
+                index_deletes_session_by_id
+                index_deletes_sessions_of_principal
+                purge_expired_keeps_unexpired_sessions
             }
         }
     };
+    (guard: $guard_ident:ident = $guard:expr, store: $store:expr $(,)?) => {
+        $crate::test_suite! {
+            guard: $guard_ident = $guard,
+            clock: __clock = $crate::support::TestClock::System,
+            store: $store,
+        }
+    };
     (guard: $guard:expr, store: $store:expr $(,)?) => {
         $crate::test_suite! {
             guard: __guard = $guard,
             store: $store,
         }
     };
+    (clock: $clock_ident:ident = $clock:expr, store: $store:expr $(,)?) => {
+        $crate::test_suite! {
+            guard: __guard = (),
+            clock: $clock_ident = $clock,
+            store: $store,
+        }
+    };
     (store: $store:expr $(,)?) => {
         $crate::test_suite! {
             guard: (),
@@ -254,6 +320,7 @@ doc! {macro_rules! test_suite {
     (
         @(
             guard: $guard_ident:ident = $guard:expr,
+            clock: $clock_ident:ident = $clock:expr,
             store: $store:expr
         ) => {
             $(
@@ -267,9 +334,11 @@ doc! {macro_rules! test_suite {
             #[$crate::__private::tokio::test]
             async fn $test() {
                 let $guard_ident = $guard;
+                let $clock_ident = $clock;
                 let __store = $store;
+                let __clock = ::core::clone::Clone::clone(&$clock_ident);
                 $crate::__private::paste::paste! {
-                    $crate::[<test_ $test>](__store).await;
+                    $crate::support::with_clock(__clock, $crate::[<test_ $test>](__store)).await;
                 }
             }
         )+
@@ -2,9 +2,14 @@ use std::time::Duration;
 
 use futures_util::{stream, StreamExt, TryStreamExt};
 use rand::{Rng, SeedableRng};
-use tower_sesh_core::{store::SessionStoreRng, SessionKey, SessionStore, Ttl};
+use tower_sesh_core::{
+    store::{CreateWithKey, SessionStoreRng, UpdateIf, Version},
+    SessionKey, SessionStore, Ttl,
+};
 
-use crate::support::{ttl, ttl_expired, ttl_strict, ttl_strict_of, SessionData, TestRng, TtlExt};
+use crate::support::{
+    now, sleep, ttl, ttl_expired, ttl_strict, ttl_strict_of, SessionData, TestRng, TtlExt,
+};
 
 pub async fn test_create_does_collision_resolution(
     mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
@@ -61,6 +66,38 @@ pub async fn test_loading_session_after_create(
     assert_eq!(record.ttl.normalize(), ttl.normalize());
 }
 
+pub async fn test_create_with_key_does_not_overwrite_existing_session(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let mut rng = TestRng::seed_from_u64(3065180291);
+    let session_key = rng.random::<SessionKey>();
+    store.rng(rng);
+
+    let data = SessionData::sample();
+    let result = store
+        .create_with_key(&session_key, &data, ttl(), Version::from_u64(1))
+        .await
+        .unwrap();
+    assert!(
+        matches!(result, CreateWithKey::Created(_)),
+        "expected `Created`, got {result:?}"
+    );
+
+    let result = store
+        .create_with_key(
+            &session_key,
+            &SessionData::sample_with(1),
+            ttl(),
+            Version::from_u64(2),
+        )
+        .await
+        .unwrap();
+    assert_eq!(result, CreateWithKey::Occupied);
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+}
+
 pub async fn test_loading_session_after_update_nonexisting(
     mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
 ) {
@@ -129,13 +166,13 @@ pub async fn test_loading_an_expired_session_returns_none_create(
     let rng = TestRng::seed_from_u64(31348441);
     store.rng(rng);
 
-    let five_microseconds_from_now = Ttl::now_local().unwrap() + Duration::from_millis(50);
+    let five_microseconds_from_now = now() + Duration::from_millis(50);
     let session_key = store
         .create(&SessionData::sample(), five_microseconds_from_now)
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_millis(90)).await;
+    sleep(Duration::from_millis(90)).await;
 
     let record = store.load(&session_key).await.unwrap();
     assert!(record.is_none());
@@ -148,7 +185,7 @@ pub async fn test_loading_an_expired_session_returns_none_update_nonexisting(
     let session_key = rng.random::<SessionKey>();
     store.rng(rng);
 
-    let five_microseconds_from_now = Ttl::now_local().unwrap() + Duration::from_millis(50);
+    let five_microseconds_from_now = now() + Duration::from_millis(50);
     store
         .update(
             &session_key,
@@ -158,7 +195,7 @@ pub async fn test_loading_an_expired_session_returns_none_update_nonexisting(
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_millis(90)).await;
+    sleep(Duration::from_millis(90)).await;
 
     let record = store.load(&session_key).await.unwrap();
     assert!(record.is_none());
@@ -171,7 +208,7 @@ pub async fn test_loading_an_expired_session_returns_none_update_existing(
     store.rng(rng);
     let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
 
-    let five_microseconds_from_now = Ttl::now_local().unwrap() + Duration::from_millis(50);
+    let five_microseconds_from_now = now() + Duration::from_millis(50);
     store
         .update(
             &session_key,
@@ -181,7 +218,7 @@ pub async fn test_loading_an_expired_session_returns_none_update_existing(
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_millis(90)).await;
+    sleep(Duration::from_millis(90)).await;
 
     let record = store.load(&session_key).await.unwrap();
     assert!(record.is_none());
@@ -194,13 +231,13 @@ pub async fn test_loading_an_expired_session_returns_none_update_ttl(
     store.rng(rng);
     let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
 
-    let five_microseconds_from_now = Ttl::now_local().unwrap() + Duration::from_millis(50);
+    let five_microseconds_from_now = now() + Duration::from_millis(50);
     store
         .update_ttl(&session_key, five_microseconds_from_now)
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_millis(90)).await;
+    sleep(Duration::from_millis(90)).await;
 
     let record = store.load(&session_key).await.unwrap();
     assert!(record.is_none());
@@ -309,8 +346,36 @@ pub async fn test_delete_does_not_error_for_missing_entry(
     store.delete(&session_key).await.unwrap();
 }
 
+pub async fn test_load_many_and_delete_many(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let mut rng = TestRng::seed_from_u64(407526183);
+    let missing_key = rng.random::<SessionKey>();
+    store.rng(rng);
+
+    let data = SessionData::sample();
+    let key_1 = store.create(&data, ttl()).await.unwrap();
+    let key_2 = store.create(&data, ttl()).await.unwrap();
+
+    let records = store
+        .load_many(&[key_1.clone(), missing_key.clone(), key_2.clone()])
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].as_ref().map(|record| &record.data), Some(&data));
+    assert!(records[1].is_none());
+    assert_eq!(records[2].as_ref().map(|record| &record.data), Some(&data));
+
+    store
+        .delete_many(&[key_1.clone(), missing_key, key_2.clone()])
+        .await
+        .unwrap();
+    assert!(store.load(&key_1).await.unwrap().is_none());
+    assert!(store.load(&key_2).await.unwrap().is_none());
+}
+
 fn ttl_edge_case() -> Ttl {
-    (Ttl::now_local().unwrap() + Duration::from_secs(10 * 60))
+    (now() + Duration::from_secs(10 * 60))
         .replace_nanosecond(1_000_000_000 - 1)
         .unwrap()
 }
@@ -378,7 +443,7 @@ pub async fn test_update_ttl_extends_session_that_would_otherwise_expire(
     let rng = TestRng::seed_from_u64(1171023902);
     store.rng(rng);
 
-    let before = Ttl::now_local().unwrap();
+    let before = now();
     let strict_ttl = ttl_strict_of(before);
     let data = SessionData::sample_with(1171023902);
     let session_key = store.create(&data, strict_ttl).await.unwrap();
@@ -386,10 +451,10 @@ pub async fn test_update_ttl_extends_session_that_would_otherwise_expire(
     let updated_ttl = ttl();
     store.update_ttl(&session_key, updated_ttl).await.unwrap();
 
-    let sleep_until_duration = strict_ttl - Ttl::now_local().unwrap();
+    let sleep_until_duration = strict_ttl - now();
     if sleep_until_duration.is_positive() {
         let sleep_until_duration = sleep_until_duration.unsigned_abs();
-        tokio::time::sleep(sleep_until_duration + Duration::from_millis(10)).await;
+        sleep(sleep_until_duration + Duration::from_millis(10)).await;
     }
 
     let record = store.load(&session_key).await.unwrap().unwrap();
@@ -403,15 +468,416 @@ pub async fn test_update_ttl_does_not_revive_expired_session(
     let rng = TestRng::seed_from_u64(2495922455);
     store.rng(rng);
 
-    let five_microseconds_from_now = Ttl::now_local().unwrap() + Duration::from_millis(50);
+    let five_microseconds_from_now = now() + Duration::from_millis(50);
     let session_key = store
         .create(&SessionData::sample(), five_microseconds_from_now)
         .await
         .unwrap();
 
-    tokio::time::sleep(Duration::from_millis(90)).await;
+    sleep(Duration::from_millis(90)).await;
 
     store.update_ttl(&session_key, ttl()).await.unwrap();
     let record = store.load(&session_key).await.unwrap();
     assert!(record.is_none());
 }
+
+pub async fn test_loading_session_after_cycle_key(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(1977720594);
+    store.rng(rng);
+
+    let old_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+
+    let data = SessionData::sample_with(1);
+    let ttl = ttl_strict();
+    let new_session_key = store.cycle_key(&old_session_key, &data, ttl).await.unwrap();
+    assert_ne!(new_session_key, old_session_key);
+
+    let record = store.load(&old_session_key).await.unwrap();
+    assert!(record.is_none());
+
+    let record = store.load(&new_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+    assert_eq!(record.ttl.normalize(), ttl.normalize());
+}
+
+pub async fn test_cycle_key_does_collision_resolution(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(2912326040);
+
+    store.rng(rng.clone());
+    let first_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+
+    store.rng(rng.clone());
+    let second_data = SessionData::sample_with(1);
+    let second_session_key = store.create(&second_data, ttl()).await.unwrap();
+
+    store.rng(rng.clone());
+    let new_session_key = store
+        .cycle_key(&first_session_key, &SessionData::sample_with(2), ttl())
+        .await
+        .unwrap();
+    assert_ne!(new_session_key, first_session_key);
+    assert_ne!(new_session_key, second_session_key);
+
+    let record = store.load(&second_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, second_data);
+}
+
+pub async fn test_cycle_key_creates_session_for_missing_entry(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let mut rng = TestRng::seed_from_u64(1538416597);
+    let old_session_key = rng.random::<SessionKey>();
+    store.rng(rng);
+
+    let record = store.load(&old_session_key).await.unwrap();
+    assert!(record.is_none());
+
+    let data = SessionData::sample();
+    let new_session_key = store
+        .cycle_key(&old_session_key, &data, ttl())
+        .await
+        .unwrap();
+
+    let record = store.load(&old_session_key).await.unwrap();
+    assert!(record.is_none());
+
+    let record = store.load(&new_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+}
+
+pub async fn test_update_if_with_current_version(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(3519622871);
+    store.rng(rng);
+    let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    let Some(version) = record.version else {
+        // The store doesn't track versions
+        return;
+    };
+
+    let data = SessionData::sample_with(1);
+    let ttl = ttl_strict();
+    let new_version = match store
+        .update_if(&session_key, &data, ttl, version)
+        .await
+        .unwrap()
+    {
+        UpdateIf::Updated(new_version) => new_version,
+        result => panic!("expected `Updated`, got {result:?}"),
+    };
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+    assert_eq!(record.ttl.normalize(), ttl.normalize());
+    assert_eq!(record.version, new_version);
+    assert_ne!(record.version, Some(version));
+}
+
+pub async fn test_update_if_after_update(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(1275440113);
+    store.rng(rng);
+    let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    store
+        .update(&session_key, &SessionData::sample_with(1), ttl())
+        .await
+        .unwrap();
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    let Some(version) = record.version else {
+        // The store doesn't track versions
+        return;
+    };
+
+    let data = SessionData::sample_with(2);
+    let result = store
+        .update_if(&session_key, &data, ttl(), version)
+        .await
+        .unwrap();
+    assert!(
+        matches!(result, UpdateIf::Updated(_)),
+        "expected `Updated`, got {result:?}"
+    );
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+}
+
+pub async fn test_writes_with_version_report_loaded_version(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(2984310657);
+    store.rng(rng);
+
+    let (session_key, version) = store
+        .create_with_version(&SessionData::sample(), ttl(), Version::from_u64(1))
+        .await
+        .unwrap();
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    if version.is_some() {
+        assert_eq!(record.version, version);
+    }
+
+    let version = store
+        .update_with_version(
+            &session_key,
+            &SessionData::sample_with(1),
+            ttl(),
+            Version::from_u64(2),
+        )
+        .await
+        .unwrap();
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    if version.is_some() {
+        assert_eq!(record.version, version);
+    }
+}
+
+pub async fn test_update_if_with_stale_version(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(1149836513);
+    store.rng(rng);
+    let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    let Some(stale_version) = record.version else {
+        // The store doesn't track versions
+        return;
+    };
+
+    let data = SessionData::sample_with(1);
+    store.update(&session_key, &data, ttl()).await.unwrap();
+
+    let current = match store
+        .update_if(
+            &session_key,
+            &SessionData::sample_with(2),
+            ttl(),
+            stale_version,
+        )
+        .await
+        .unwrap()
+    {
+        UpdateIf::Conflict(Some(current)) => current,
+        result => panic!("expected `Conflict(Some(_))`, got {result:?}"),
+    };
+    assert_eq!(current.data, data);
+    assert_ne!(current.version, Some(stale_version));
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+    assert_eq!(record.version, current.version);
+}
+
+pub async fn test_update_if_for_missing_session(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(2716160358);
+    store.rng(rng);
+    let session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    let Some(version) = record.version else {
+        // The store doesn't track versions
+        return;
+    };
+
+    store.delete(&session_key).await.unwrap();
+
+    let result = store
+        .update_if(&session_key, &SessionData::sample_with(1), ttl(), version)
+        .await
+        .unwrap();
+    assert!(
+        matches!(result, UpdateIf::Conflict(None)),
+        "expected `Conflict(None)`, got {result:?}"
+    );
+
+    let record = store.load(&session_key).await.unwrap();
+    assert!(record.is_none());
+}
+
+pub async fn test_index_lists_sessions_of_principal(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(2361270950);
+    store.rng(rng);
+    let Some(index) = store.as_index() else {
+        // The store doesn't support indexing
+        return;
+    };
+
+    let ttl = ttl_strict();
+    let first_session_key = store.create(&SessionData::sample(), ttl).await.unwrap();
+    let second_session_key = store.create(&SessionData::sample(), ttl).await.unwrap();
+    let other_session_key = store.create(&SessionData::sample(), ttl).await.unwrap();
+    index.associate(&first_session_key, "alice").await.unwrap();
+    index.associate(&second_session_key, "alice").await.unwrap();
+    index.associate(&other_session_key, "bob").await.unwrap();
+
+    let sessions = index.sessions("alice").await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_ne!(sessions[0].id, sessions[1].id);
+    for session in &sessions {
+        assert_eq!(session.ttl.normalize(), ttl.normalize());
+    }
+    assert_eq!(
+        index
+            .principal(&first_session_key)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("alice")
+    );
+    assert_eq!(
+        index
+            .principal(&other_session_key)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("bob")
+    );
+    let unindexed_session_key = store.create(&SessionData::sample(), ttl).await.unwrap();
+    assert_eq!(index.principal(&unindexed_session_key).await.unwrap(), None);
+
+    // Deleted sessions are no longer listed
+    store.delete(&first_session_key).await.unwrap();
+    let remaining = index.sessions("alice").await.unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert!(sessions.iter().any(|session| session.id == remaining[0].id));
+    assert_eq!(index.principal(&first_session_key).await.unwrap(), None);
+
+    // Neither are sessions under their old session key once it's cycled
+    store
+        .cycle_key(&second_session_key, &SessionData::sample(), ttl)
+        .await
+        .unwrap();
+    assert!(index.sessions("alice").await.unwrap().is_empty());
+    assert_eq!(index.principal(&second_session_key).await.unwrap(), None);
+
+    let sessions = index.sessions("carol").await.unwrap();
+    assert!(sessions.is_empty());
+}
+
+pub async fn test_index_deletes_sessions_of_principal(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(409378213);
+    store.rng(rng);
+    let Some(index) = store.as_index() else {
+        // The store doesn't support indexing
+        return;
+    };
+
+    let first_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    let second_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    let other_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    index.associate(&first_session_key, "alice").await.unwrap();
+    index.associate(&second_session_key, "alice").await.unwrap();
+    index.associate(&other_session_key, "bob").await.unwrap();
+
+    // Populate any caches
+    for session_key in [&first_session_key, &second_session_key, &other_session_key] {
+        assert!(store.load(session_key).await.unwrap().is_some());
+    }
+
+    index.delete_sessions("alice").await.unwrap();
+
+    assert!(store.load(&first_session_key).await.unwrap().is_none());
+    assert!(store.load(&second_session_key).await.unwrap().is_none());
+    assert!(index.sessions("alice").await.unwrap().is_empty());
+    assert!(store.load(&other_session_key).await.unwrap().is_some());
+    assert_eq!(index.sessions("bob").await.unwrap().len(), 1);
+
+    // Deleting the sessions of a principal without any is not an error
+    index.delete_sessions("carol").await.unwrap();
+}
+
+pub async fn test_index_deletes_session_by_id(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(1585204417);
+    store.rng(rng);
+    let Some(index) = store.as_index() else {
+        // The store doesn't support indexing
+        return;
+    };
+
+    let first_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    let second_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    let other_session_key = store.create(&SessionData::sample(), ttl()).await.unwrap();
+    index.associate(&first_session_key, "alice").await.unwrap();
+    index.associate(&second_session_key, "alice").await.unwrap();
+    index.associate(&other_session_key, "bob").await.unwrap();
+
+    // Populate any caches
+    for session_key in [&first_session_key, &second_session_key, &other_session_key] {
+        assert!(store.load(session_key).await.unwrap().is_some());
+    }
+
+    let sessions = index.sessions("alice").await.unwrap();
+    let other_sessions = index.sessions("bob").await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(other_sessions.len(), 1);
+
+    // A session isn't deleted on behalf of another principal
+    index
+        .delete_session("alice", &other_sessions[0].id)
+        .await
+        .unwrap();
+    assert!(store.load(&other_session_key).await.unwrap().is_some());
+
+    index
+        .delete_session("alice", &sessions[0].id)
+        .await
+        .unwrap();
+
+    let first = store.load(&first_session_key).await.unwrap();
+    let second = store.load(&second_session_key).await.unwrap();
+    assert!(
+        first.is_none() != second.is_none(),
+        "expected exactly one session to be deleted"
+    );
+    let remaining = index.sessions("alice").await.unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].id, sessions[1].id);
+    assert!(store.load(&other_session_key).await.unwrap().is_some());
+
+    // Deleting a session which no longer exists is not an error
+    index
+        .delete_session("alice", &sessions[0].id)
+        .await
+        .unwrap();
+}
+
+pub async fn test_purge_expired_keeps_unexpired_sessions(
+    mut store: impl SessionStore<SessionData> + SessionStoreRng<TestRng>,
+) {
+    let rng = TestRng::seed_from_u64(3810243607);
+    store.rng(rng);
+
+    let data = SessionData::sample_with(3810243607);
+    let unexpired_session_key = store.create(&data, ttl()).await.unwrap();
+    let expired_session_key = store
+        .create(&SessionData::sample(), ttl_expired())
+        .await
+        .unwrap();
+
+    store.purge_expired().await.unwrap();
+
+    let record = store.load(&unexpired_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, data);
+    assert!(store.load(&expired_session_key).await.unwrap().is_none());
+
+    // Purging a store without expired sessions is not an error
+    store.purge_expired().await.unwrap();
+}
@@ -1,15 +1,81 @@
-use std::time::Duration;
+use std::{future::Future, time::Duration};
 
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use time::{Date, Month, OffsetDateTime, Time, UtcDateTime};
-use tower_sesh_core::Ttl;
+use tower_sesh_core::{
+    time::{Clock, MockClock, SystemClock},
+    Ttl,
+};
 
 /// A [reproducible], cryptographically secure PRNG suitable for use in testing.
 ///
 /// [reproducible]: https://rust-random.github.io/book/crate-reprod.html
 pub type TestRng = rand_chacha::ChaCha20Rng;
 
+/// The clock which the test suite uses to compute expiration times, and to
+/// wait for sessions to expire.
+///
+/// With [`TestClock::System`], tests sleep until sessions expire. With
+/// [`TestClock::Mock`], tests advance the mock clock instead, which is only
+/// correct if the store under test uses the same clock.
+///
+/// See [the top-level documentation][lib] for how to pass a clock to the test
+/// suite.
+///
+/// [lib]: crate#using-a-mock-clock
+#[derive(Clone, Debug, Default)]
+pub enum TestClock {
+    #[default]
+    System,
+    Mock(MockClock),
+}
+
+impl TestClock {
+    fn now(&self) -> Ttl {
+        match self {
+            TestClock::System => SystemClock.now(),
+            TestClock::Mock(clock) => clock.now(),
+        }
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        match self {
+            TestClock::System => tokio::time::sleep(duration).await,
+            TestClock::Mock(clock) => clock.advance(duration),
+        }
+    }
+}
+
+impl From<MockClock> for TestClock {
+    fn from(clock: MockClock) -> Self {
+        TestClock::Mock(clock)
+    }
+}
+
+tokio::task_local! {
+    static CLOCK: TestClock;
+}
+
+/// Runs `f` with `clock` as the test suite's clock.
+#[doc(hidden)]
+pub async fn with_clock<F: Future>(clock: impl Into<TestClock>, f: F) -> F::Output {
+    CLOCK.scope(clock.into(), f).await
+}
+
+/// Returns the current time according to the test suite's clock.
+pub(crate) fn now() -> Ttl {
+    CLOCK
+        .try_with(TestClock::now)
+        .unwrap_or_else(|_| SystemClock.now())
+}
+
+/// Waits for `duration` to pass according to the test suite's clock.
+pub(crate) async fn sleep(duration: Duration) {
+    let clock = CLOCK.try_with(TestClock::clone).unwrap_or_default();
+    clock.sleep(duration).await;
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[non_exhaustive]
 pub struct SessionData {
@@ -137,13 +203,13 @@ impl SessionData {
 /// (Technically, the returned `Ttl` will expire if a test runs for longer than
 /// 10 minutes.)
 pub(crate) fn ttl() -> Ttl {
-    let now = Ttl::now_local().unwrap();
+    let now = now();
     now + Duration::from_secs(10 * 60)
 }
 
 /// Returns a `Ttl` that is very close to expiring.
 pub(crate) fn ttl_strict() -> Ttl {
-    let now = Ttl::now_local().unwrap();
+    let now = now();
     ttl_strict_of(now)
 }
 
@@ -162,7 +228,7 @@ pub(crate) fn ttl_strict_of(ttl: Ttl) -> Ttl {
 
 /// Returns a `Ttl` that has already expired.
 pub(crate) fn ttl_expired() -> Ttl {
-    let now = Ttl::now_local().unwrap();
+    let now = now();
     now - Duration::from_secs(1)
 }
 
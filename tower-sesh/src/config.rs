@@ -5,24 +5,43 @@ use cookie::{Cookie, CookieJar, Key};
 /// Trait used to control how cookies are stored and retrieved.
 #[doc(hidden)]
 pub trait CookieSecurity: Clone + private::Sealed {
+    /// Retrieves a cookie, verifying or decrypting it with the primary key.
     fn get<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>>;
+
+    /// Retrieves a cookie, verifying or decrypting it with each retired key in
+    /// turn. Returns `None` if no retired key can open the cookie.
+    fn get_retired<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>>;
+
+    /// Adds a cookie, signing or encrypting it with the primary key.
     fn add(&self, jar: &mut CookieJar, cookie: Cookie<'static>);
 
     /// Returns a reference to a cryptographic key. This function may panic if
     /// the implementing type does not own a key.
     fn key(&self) -> &Key;
+
+    /// Returns the retired keys, which are only used to open cookies.
+    fn retired_keys(&self) -> &[Key];
+}
+
+/// Cookie security that signs or encrypts cookies with a key, and so can open
+/// cookies with retired keys.
+pub trait KeyedCookieSecurity: CookieSecurity {
+    /// Replaces the retired keys.
+    fn set_retired_keys(&mut self, keys: Vec<Key>);
 }
 
 #[doc(hidden)]
 #[derive(Clone, Debug)]
 pub struct SignedCookie {
     key: Key,
+    retired_keys: Vec<Key>,
 }
 
 #[doc(hidden)]
 #[derive(Clone, Debug)]
 pub struct PrivateCookie {
     key: Key,
+    retired_keys: Vec<Key>,
 }
 
 #[doc(hidden)]
@@ -31,14 +50,14 @@ pub struct PrivateCookie {
 pub struct PlainCookie;
 
 impl SignedCookie {
-    pub(crate) fn new(key: Key) -> Self {
-        Self { key }
+    pub(crate) fn new(key: Key, retired_keys: Vec<Key>) -> Self {
+        Self { key, retired_keys }
     }
 }
 
 impl PrivateCookie {
-    pub(crate) fn new(key: Key) -> Self {
-        Self { key }
+    pub(crate) fn new(key: Key, retired_keys: Vec<Key>) -> Self {
+        Self { key, retired_keys }
     }
 }
 
@@ -48,6 +67,12 @@ impl CookieSecurity for SignedCookie {
         jar.signed(&self.key).get(name)
     }
 
+    fn get_retired<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>> {
+        self.retired_keys
+            .iter()
+            .find_map(|key| jar.signed(key).get(name))
+    }
+
     #[inline]
     fn add(&self, jar: &mut CookieJar, cookie: Cookie<'static>) {
         jar.signed_mut(&self.key).add(cookie)
@@ -57,15 +82,33 @@ impl CookieSecurity for SignedCookie {
     fn key(&self) -> &Key {
         &self.key
     }
+
+    #[inline]
+    fn retired_keys(&self) -> &[Key] {
+        &self.retired_keys
+    }
 }
 impl private::Sealed for SignedCookie {}
 
+impl KeyedCookieSecurity for SignedCookie {
+    #[inline]
+    fn set_retired_keys(&mut self, keys: Vec<Key>) {
+        self.retired_keys = keys;
+    }
+}
+
 impl CookieSecurity for PrivateCookie {
     #[inline]
     fn get<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>> {
         jar.private(&self.key).get(name)
     }
 
+    fn get_retired<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>> {
+        self.retired_keys
+            .iter()
+            .find_map(|key| jar.private(key).get(name))
+    }
+
     #[inline]
     fn add(&self, jar: &mut CookieJar, cookie: Cookie<'static>) {
         jar.private_mut(&self.key).add(cookie)
@@ -75,15 +118,32 @@ impl CookieSecurity for PrivateCookie {
     fn key(&self) -> &Key {
         &self.key
     }
+
+    #[inline]
+    fn retired_keys(&self) -> &[Key] {
+        &self.retired_keys
+    }
 }
 impl private::Sealed for PrivateCookie {}
 
+impl KeyedCookieSecurity for PrivateCookie {
+    #[inline]
+    fn set_retired_keys(&mut self, keys: Vec<Key>) {
+        self.retired_keys = keys;
+    }
+}
+
 impl CookieSecurity for PlainCookie {
     #[inline]
     fn get<'c>(&self, jar: &'c CookieJar, name: &str) -> Option<Cookie<'c>> {
         jar.get(name).cloned()
     }
 
+    #[inline]
+    fn get_retired<'c>(&self, _jar: &'c CookieJar, _name: &str) -> Option<Cookie<'c>> {
+        None
+    }
+
     #[inline]
     fn add(&self, jar: &mut CookieJar, cookie: Cookie<'static>) {
         jar.add(cookie)
@@ -94,6 +154,11 @@ impl CookieSecurity for PlainCookie {
     fn key(&self) -> &Key {
         unimplemented!("use `SessionLayer::new()` to sign or encrypt cookies")
     }
+
+    #[inline]
+    fn retired_keys(&self) -> &[Key] {
+        &[]
+    }
 }
 impl private::Sealed for PlainCookie {}
 
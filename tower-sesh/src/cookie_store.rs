@@ -0,0 +1,510 @@
+//! Client-side sessions, where session data is stored in encrypted cookies.
+//!
+//! [`CookieSessionLayer`] is an alternative to [`SessionLayer`] for
+//! applications with small session payloads. Instead of storing session data
+//! in a [`SessionStore`] and sending the client a session key, the session
+//! data itself is serialized, encrypted, and sent to the client. No session
+//! store is needed, and no store round-trips are performed.
+//!
+//! The handler-facing API is the same: use the [`Session`] extractor.
+//!
+//! # Trade-offs
+//!
+//! - Session data is sent with every request, so it should be kept small.
+//!   Payloads which don't fit in a single cookie are split across multiple
+//!   cookies, up to a configurable [limit][max_cookies]. If a session exceeds
+//!   the limit, the session's cookies are left unchanged and an error is
+//!   logged.
+//! - A session can't be revoked from the server before it expires, since the
+//!   client holds a valid copy of it. [`Session::purge`] only asks the
+//!   browser to remove its cookies.
+//!
+//! [`SessionLayer`]: crate::SessionLayer
+//! [`SessionStore`]: crate::store::SessionStore
+//! [`Session`]: crate::Session
+//! [`Session::purge`]: crate::Session::purge
+//! [max_cookies]: CookieSessionLayer::max_cookies
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    marker::PhantomData,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use cookie::{Cookie, CookieJar};
+use futures_util::{future::BoxFuture, FutureExt};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use serde::{de::DeserializeOwned, Serialize};
+use tower::{Layer, Service};
+use tower_sesh_core::{time::Clock, util::Report, Record, Ttl};
+
+use crate::{
+    config::{CookieSecurity, PrivateCookie},
+    middleware::{
+        append_set_cookie, cookies_from_request, validate_cookie_name, Config, Expiry, Key,
+        SameSite,
+    },
+    session::{self, CookieSyncAction, Session},
+};
+
+/// A layer that provides [`Session`] as an extractor, storing session data in
+/// encrypted cookies.
+///
+/// See the [module-level documentation](self) for more details.
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh::{cookie_store::CookieSessionLayer, middleware::Key};
+///
+/// # type SessionData = ();
+/// #
+/// fn key() -> Key {
+///     // TODO: Where do you get a key?
+/// # Key::from([0; 64])
+/// }
+///
+/// let layer = CookieSessionLayer::<SessionData>::new(key());
+/// ```
+pub struct CookieSessionLayer<T> {
+    config: Arc<Config>, // This is put in an `Arc` to make clones cheap.
+    cookie_controller: Arc<PrivateCookie>, // Ditto.
+    max_cookies: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// A middleware that provides [`Session`] as an extractor, storing session
+/// data in encrypted cookies.
+pub struct CookieSessionManager<S, T> {
+    inner: S,
+    layer: CookieSessionLayer<T>,
+}
+
+impl<T> CookieSessionLayer<T> {
+    /// The maximum length of a cookie's name and value, in bytes, supported by
+    /// all major browsers.
+    const MAX_COOKIE_LEN: usize = 4096;
+
+    /// Allows payloads of up to roughly 16 KiB.
+    const DEFAULT_MAX_COOKIES: usize = 4;
+
+    /// Creates a new `CookieSessionLayer` with default configuration values.
+    ///
+    /// Session data is encrypted with the provided 64-byte `key`.
+    #[track_caller]
+    pub fn new(key: Key) -> CookieSessionLayer<T> {
+        let key = key.into_cookie_key();
+        CookieSessionLayer {
+            config: Arc::new(Config::default()),
+            cookie_controller: Arc::new(PrivateCookie::new(key, Vec::new())),
+            max_cookies: CookieSessionLayer::<T>::DEFAULT_MAX_COOKIES,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum number of cookies a session may be split across.
+    ///
+    /// Each cookie holds up to 4096 bytes. Browsers limit both the size and
+    /// the number of cookies per domain, and servers limit the size of request
+    /// headers, so this should be kept low.
+    ///
+    /// Default is `4`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_cookies` is zero.
+    #[track_caller]
+    pub fn max_cookies(mut self, max_cookies: usize) -> Self {
+        assert!(max_cookies > 0, "`max_cookies` must be non-zero");
+        self.max_cookies = max_cookies;
+        self
+    }
+
+    /// Sets the name of the cookie used to store session data. If session data
+    /// is split across multiple cookies, the additional cookies are suffixed
+    /// with `.1`, `.2`, and so on.
+    ///
+    /// See [`SessionLayer::cookie_name`] for more details.
+    ///
+    /// [`SessionLayer::cookie_name`]: crate::SessionLayer::cookie_name
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains an invalid character.
+    #[track_caller]
+    pub fn cookie_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        let name = name.into();
+        validate_cookie_name(&name);
+        self.config_mut().cookie_name = name;
+        self
+    }
+
+    /// Sets the `Domain` attribute in the `Set-Cookie` response header.
+    ///
+    /// See [`SessionLayer::domain`] for more details.
+    ///
+    /// [`SessionLayer::domain`]: crate::SessionLayer::domain
+    pub fn domain(mut self, domain: impl Into<Cow<'static, str>>) -> Self {
+        self.config_mut().domain = Some(domain.into());
+        self
+    }
+
+    /// Sets whether to add the `HttpOnly` attribute in the `Set-Cookie`
+    /// response header.
+    ///
+    /// See [`SessionLayer::http_only`] for more details.
+    ///
+    /// [`SessionLayer::http_only`]: crate::SessionLayer::http_only
+    pub fn http_only(mut self, enable: bool) -> Self {
+        self.config_mut().http_only = enable;
+        self
+    }
+
+    /// Sets the `Path` attribute in the `Set-Cookie` response header.
+    ///
+    /// See [`SessionLayer::path`] for more details.
+    ///
+    /// [`SessionLayer::path`]: crate::SessionLayer::path
+    pub fn path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.config_mut().path = Some(path.into());
+        self
+    }
+
+    /// Sets the `SameSite` attribute in the `Set-Cookie` response header.
+    ///
+    /// See [`SessionLayer::same_site`] for more details.
+    ///
+    /// [`SessionLayer::same_site`]: crate::SessionLayer::same_site
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.config_mut().same_site = same_site.into_cookie_same_site();
+        self
+    }
+
+    /// Sets whether to add the `Secure` attribute in the `Set-Cookie`
+    /// response header.
+    ///
+    /// See [`SessionLayer::secure`] for more details.
+    ///
+    /// [`SessionLayer::secure`]: crate::SessionLayer::secure
+    pub fn secure(mut self, enable: bool) -> Self {
+        self.config_mut().secure = enable;
+        self
+    }
+
+    /// Sets the policy used to determine when a session expires.
+    ///
+    /// The expiration time is sealed inside the encrypted payload, so a
+    /// client can't extend a session by tampering with its cookies.
+    ///
+    /// See [`SessionLayer::expiry`] for more details.
+    ///
+    /// [`SessionLayer::expiry`]: crate::SessionLayer::expiry
+    pub fn expiry(mut self, expiry: Expiry) -> Self {
+        self.config_mut().expiry = expiry;
+        self
+    }
+
+    /// Sets the clock used to compute and check session expiry.
+    ///
+    /// See [`SessionLayer::clock`] for more details.
+    ///
+    /// [`SessionLayer::clock`]: crate::SessionLayer::clock
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.config_mut().clock = Arc::new(clock);
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+impl<T> CookieSessionLayer<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Reassembles and decrypts the session payload from the request's
+    /// cookies.
+    ///
+    /// Returns `None` if there is no payload, or if it couldn't be decrypted,
+    /// deserialized, or has expired.
+    fn open(&self, chunks: &BTreeMap<usize, String>) -> Option<Record<T>> {
+        let name = &*self.config.cookie_name;
+
+        // Chunks are contiguous; a missing chunk ends the payload
+        let sealed = (0..)
+            .map_while(|index| chunks.get(&index))
+            .map(String::as_str)
+            .collect::<String>();
+        if sealed.is_empty() {
+            return None;
+        }
+
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(name.to_owned(), sealed));
+        let cookie = self.cookie_controller.get(&jar, name)?;
+
+        let (expires_at, data) = match serde_json::from_str::<(i64, T)>(cookie.value()) {
+            Ok(payload) => payload,
+            Err(_err) => {
+                debug!(err = %Report::new(_err), "failed to deserialize session cookie");
+                return None;
+            }
+        };
+        let ttl = Ttl::from_unix_timestamp(expires_at).ok()?;
+        if ttl <= self.config.clock.now() {
+            return None;
+        }
+
+        Some(Record::new(data, ttl))
+    }
+
+    /// Serializes and encrypts the session payload, then splits it into as
+    /// many cookies as needed.
+    fn seal(&self, data: &T, ttl: Ttl) -> Result<Vec<Cookie<'_>>, SealError> {
+        let name = &*self.config.cookie_name;
+
+        let payload =
+            serde_json::to_string(&(ttl.unix_timestamp(), data)).map_err(SealError::Serialize)?;
+        let mut jar = CookieJar::new();
+        self.cookie_controller
+            .add(&mut jar, Cookie::new(name.to_owned(), payload));
+        let sealed = jar.get(name).expect("this cookie should exist").value();
+
+        let mut cookies = Vec::new();
+        let mut rest = sealed;
+        while !rest.is_empty() {
+            if cookies.len() == self.max_cookies {
+                return Err(SealError::TooLarge {
+                    len: sealed.len(),
+                    max_cookies: self.max_cookies,
+                });
+            }
+
+            let chunk_name = chunk_name(name, cookies.len());
+            let (chunk, tail) = split_chunk(
+                rest,
+                CookieSessionLayer::<T>::MAX_COOKIE_LEN - chunk_name.len() - "=".len(),
+            );
+            rest = tail;
+
+            cookies.push(
+                self.config
+                    .named_cookie(chunk_name, chunk.to_owned(), ttl)
+                    .into_owned(),
+            );
+        }
+
+        Ok(cookies)
+    }
+}
+
+/// Returns the name of the cookie containing the chunk at `index`.
+fn chunk_name(name: &str, index: usize) -> Cow<'_, str> {
+    match index {
+        0 => Cow::Borrowed(name),
+        _ => Cow::Owned(format!("{name}.{index}")),
+    }
+}
+
+/// Splits `value` so that the first part is at most `max_len` bytes once
+/// percent-encoded in a `Set-Cookie` header.
+///
+/// `value` is expected to be Base64-encoded, as produced by the cookie
+/// controller.
+fn split_chunk(value: &str, max_len: usize) -> (&str, &str) {
+    let mut len = 0;
+    for (index, byte) in value.bytes().enumerate() {
+        // These are the only Base64 characters which are percent-encoded in
+        // cookie values
+        len += match byte {
+            b'/' | b'=' => 3,
+            _ => 1,
+        };
+        if len > max_len {
+            return value.split_at(index);
+        }
+    }
+
+    (value, "")
+}
+
+/// Returns the session cookie chunks present in the request, keyed by index.
+fn chunks_from_request_headers(headers: &HeaderMap, name: &str) -> BTreeMap<usize, String> {
+    cookies_from_request(headers)
+        .filter_map(|cookie| {
+            let index = match cookie.name().strip_prefix(name)? {
+                "" => 0,
+                suffix => suffix.strip_prefix('.')?.parse().ok().filter(|&i| i > 0)?,
+            };
+            Some((index, cookie.value().to_owned()))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum SealError {
+    Serialize(serde_json::Error),
+    TooLarge { len: usize, max_cookies: usize },
+}
+
+impl StdError for SealError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            SealError::Serialize(err) => Some(err),
+            SealError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SealError::Serialize(_) => f.write_str("failed to serialize session data"),
+            SealError::TooLarge { len, max_cookies } => write!(
+                f,
+                "encrypted session data is {len} bytes, which does not fit in {max_cookies} cookies"
+            ),
+        }
+    }
+}
+
+impl<T> Clone for CookieSessionLayer<T> {
+    fn clone(&self) -> Self {
+        CookieSessionLayer {
+            config: Arc::clone(&self.config),
+            cookie_controller: Arc::clone(&self.cookie_controller),
+            max_cookies: self.max_cookies,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for CookieSessionLayer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieSessionLayer")
+            .field("config", &self.config)
+            .field("cookie_security", &self.cookie_controller)
+            .field("max_cookies", &self.max_cookies)
+            .finish()
+    }
+}
+
+impl<S, T> Layer<S> for CookieSessionLayer<T> {
+    type Service = CookieSessionManager<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CookieSessionManager {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl<S, T> Clone for CookieSessionManager<S, T>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        CookieSessionManager {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, T> fmt::Debug for CookieSessionManager<S, T>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieSessionManager")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<ReqBody, ResBody, S, T> Service<Request<ReqBody>> for CookieSessionManager<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Error: Send,
+    S::Future: Send + 'static,
+    ResBody: Send,
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let chunks = chunks_from_request_headers(req.headers(), &self.layer.config.cookie_name);
+        let session = self.layer.open(&chunks).map(Session::from_cookie);
+        let session_handle = session::lazy::insert_loaded(req.extensions_mut(), session);
+
+        // Every chunk cookie sent by the client, so that stale chunks can be
+        // removed if the payload shrinks
+        let request_chunk_count = chunks.keys().last().map_or(0, |index| index + 1);
+
+        let fut = self.inner.call(req);
+
+        let layer = self.layer.clone();
+
+        async move {
+            let mut response = fut.await?;
+
+            if let Some(session) = session_handle.get() {
+                let session = session.take();
+
+                match session.sync_cookie(&layer.config.expiry, layer.config.clock.now()) {
+                    CookieSyncAction::Set(data, ttl) => match layer.seal(&data, ttl) {
+                        Ok(cookies) => {
+                            for cookie in &cookies {
+                                append_set_cookie(response.headers_mut(), cookie);
+                            }
+                            remove_chunks(
+                                response.headers_mut(),
+                                &layer.config,
+                                cookies.len()..request_chunk_count,
+                            );
+                        }
+                        Err(_err) => {
+                            error!(err = %Report::new(_err), "error when sealing session into cookies");
+                        }
+                    },
+                    CookieSyncAction::Remove => {
+                        remove_chunks(
+                            response.headers_mut(),
+                            &layer.config,
+                            0..request_chunk_count.max(1),
+                        );
+                    }
+                    CookieSyncAction::None => {}
+                }
+            }
+
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+fn remove_chunks(
+    headers: &mut HeaderMap<HeaderValue>,
+    config: &Config,
+    indices: std::ops::Range<usize>,
+) {
+    for index in indices {
+        let cookie = config.named_cookie_removal(chunk_name(&config.cookie_name, index));
+        append_set_cookie(headers, &cookie);
+    }
+}
@@ -31,18 +31,25 @@ pub mod _draft {
     //!
     //! ## Comparison of session stores
     //!
-    //! |                 | Persistent | Horizontally scalable |
-    //! |-----------------|------------|-----------------------|
-    //! | [`MemoryStore`] | no         | no                    |
-    //! | [`RedisStore`]  | yes\*      | yes                   |
-    //! | [`SqlxStore`]   | yes        | yes†                  |
+    //! |                    | Persistent | Horizontally scalable |
+    //! |--------------------|------------|-----------------------|
+    //! | [`MemoryStore`]    | no         | no                    |
+    //! | [`FileStore`]      | yes        | no                    |
+    //! | [`RedisStore`]     | yes\*      | yes                   |
+    //! | [`MemcachedStore`] | no         | yes                   |
+    //! | [`PostgresStore`]  | yes        | yes                   |
+    //! | [`SqliteStore`]    | yes        | no                    |
+    //! | [`RedbStore`]      | yes        | no                    |
     //!
-    //! \* Only if [Redis persistence] is enabled.<br>
-    //! † Depends on the specific database: SQLite is not horizontally scalable.
+    //! \* Only if [Redis persistence] is enabled.
     //!
     //! [`MemoryStore`]: crate::store::MemoryStore
+    //! [`FileStore`]: crate::store::FileStore
     //! [`RedisStore`]: https://docs.rs/tower-sesh-store-redis
-    //! [`SqlxStore`]: https://docs.rs/tower-sesh-store-sqlx
+    //! [`MemcachedStore`]: https://docs.rs/tower-sesh-store-memcached
+    //! [`PostgresStore`]: https://docs.rs/tower-sesh-store-postgres
+    //! [`SqliteStore`]: https://docs.rs/tower-sesh-store-sqlite
+    //! [`RedbStore`]: https://docs.rs/tower-sesh-store-redb
     //! [Redis persistence]: https://redis.io/docs/latest/operate/oss_and_stack/management/persistence/
     //!
     //! # Feature flags
@@ -51,9 +58,17 @@ pub mod _draft {
     //!
     //! - `axum` *(enabled by default)*: Enables the [`Session`] [extractor]
     //!   (for use with [`axum`]).
+    //! - `cookie-store`: Enables [`CookieSessionLayer`], which stores session
+    //!   data in encrypted cookies instead of a session store.
+    //! - `encrypted-store`: Enables [`EncryptedStore`], which encrypts session
+    //!   data before it reaches a session store.
+    //! - `file-store`: Enables [`FileStore`], which keeps each session in its
+    //!   own file.
     //! - `log`: Causes trace instrumentation points to emit [`log`] records
     //!   (for compatibility with the `log` crate).
     //! - `memory-store` *(enabled by default)*: Enables [`MemoryStore`].
+    //! - `memory-store-snapshot`: Enables saving and restoring snapshots of a
+    //!   [`MemoryStore`], so that sessions survive a restart.
     //! - `tracing` *(enabled by default)*: Enables [`tracing`] output. In order
     //!   to record trace events, you must use a [`Subscriber`] implementation,
     //!   such as one provided by the [`tracing-subscriber`] crate.
@@ -61,6 +76,9 @@ pub mod _draft {
     //!   logger compatible with the `log` crate.
     //!
     //! [feature flags]: https://doc.rust-lang.org/cargo/reference/features.html#the-features-section
+    //! [`CookieSessionLayer`]: crate::cookie_store::CookieSessionLayer
+    //! [`EncryptedStore`]: crate::store::EncryptedStore
+    //! [`FileStore`]: crate::store::FileStore
     //! [`Session`]: crate::Session
     //! [extractor]: https://docs.rs/axum/latest/axum/extract/index.html
     //! [`axum`]: https://docs.rs/axum
@@ -82,7 +100,10 @@ mod macros;
 #[macro_use]
 extern crate tower_sesh_core;
 
+#[cfg(feature = "cookie-store")]
+pub mod cookie_store;
 pub mod middleware;
+pub mod migrate;
 pub mod session;
 pub mod store;
 
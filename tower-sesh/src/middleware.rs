@@ -5,16 +5,23 @@ use std::{
     marker::PhantomData,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
-use cookie::{Cookie, CookieJar};
+use cookie::{Cookie, CookieBuilder, CookieJar};
 use futures_util::{future::BoxFuture, FutureExt};
-use http::{header, HeaderMap, HeaderValue, Request, Response};
+use http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
 use tower::{Layer, Service};
-use tower_sesh_core::{util::Report, SessionKey, SessionStore};
+use tower_sesh_core::{
+    codec::Codec,
+    time::{Clock, SystemClock, SESSION_EXPIRY_SECONDS_DEFAULT},
+    util::Report,
+    SessionKey, SessionStore, Ttl,
+};
 
 use crate::{
-    config::{CookieSecurity, PlainCookie, PrivateCookie, SignedCookie},
+    config::{CookieSecurity, KeyedCookieSecurity, PlainCookie, PrivateCookie, SignedCookie},
+    migrate::{Chain, Migrations},
     session::{self, SyncAction},
 };
 
@@ -31,6 +38,8 @@ pub struct SessionLayer<T, Store: SessionStore<T>, C = PrivateCookie> {
     store: Arc<Store>,
     config: Arc<Config>,       // This is put in an `Arc` to make clones cheap.
     cookie_controller: Arc<C>, // Ditto.
+    conflict_policy: ConflictPolicy<T>,
+    migrations: Option<Arc<Chain<T>>>,
     _marker: PhantomData<fn() -> T>,
 }
 
@@ -43,22 +52,68 @@ pub struct SessionManager<S, T, Store: SessionStore<T>, C> {
 }
 
 #[derive(Clone, Debug)]
-struct Config {
-    cookie_name: Cow<'static, str>,
-    domain: Option<Cow<'static, str>>,
-    http_only: bool,
-    path: Option<Cow<'static, str>>,
-    same_site: cookie::SameSite,
-    secure: bool,
+pub(crate) struct Config {
+    pub(crate) cookie_name: Cow<'static, str>,
+    pub(crate) domain: Option<Cow<'static, str>>,
+    pub(crate) http_only: bool,
+    pub(crate) path: Option<Cow<'static, str>>,
+    pub(crate) same_site: cookie::SameSite,
+    pub(crate) secure: bool,
+    pub(crate) expiry: Expiry,
+    pub(crate) clock: Arc<dyn Clock>,
+    transport: Transport,
 }
 
 impl Config {
     /// Chosen to avoid session ID name fingerprinting.
     const DEFAULT_COOKIE_NAME: &str = "id";
 
-    // TODO: Add the `Expires` attribute.
-    fn cookie(&self, session_key: SessionKey) -> Cookie<'_> {
-        let mut cookie = Cookie::build((&*self.cookie_name, session_key.encode()))
+    /// Returns the cookie used to set the session key in the browser.
+    fn cookie(&self, session_key: SessionKey, ttl: Ttl) -> Cookie<'_> {
+        self.named_cookie(Cow::Borrowed(&self.cookie_name), session_key.encode(), ttl)
+    }
+
+    /// Returns a cookie with the given name and value, and with attributes
+    /// taken from this configuration.
+    ///
+    /// If the expiry policy calls for a persistent cookie, the `Expires` and
+    /// `Max-Age` attributes are both derived from `ttl`. (`Max-Age` takes
+    /// precedence in browsers which support it, and is immune to clock skew
+    /// between the server and the client.)
+    pub(crate) fn named_cookie<'c>(
+        &'c self,
+        name: Cow<'c, str>,
+        value: String,
+        ttl: Ttl,
+    ) -> Cookie<'c> {
+        let mut cookie = self.cookie_builder(name, value);
+
+        if self.expiry.is_persistent() {
+            let max_age = (ttl - self.clock.now()).max(cookie::time::Duration::ZERO);
+            cookie = cookie.expires(ttl).max_age(max_age);
+        }
+
+        cookie.build()
+    }
+
+    /// Returns a cookie which removes the session cookie from the browser.
+    fn cookie_removal(&self) -> Cookie<'_> {
+        self.named_cookie_removal(Cow::Borrowed(&self.cookie_name))
+    }
+
+    /// Returns a cookie which removes the cookie with the given name from the
+    /// browser.
+    ///
+    /// A browser only replaces a cookie if the name, `Domain`, and `Path` all
+    /// match, so these attributes are the same as in [`Config::named_cookie`].
+    pub(crate) fn named_cookie_removal<'c>(&'c self, name: Cow<'c, str>) -> Cookie<'c> {
+        let mut cookie = self.cookie_builder(name, String::new()).build();
+        cookie.make_removal();
+        cookie
+    }
+
+    fn cookie_builder<'c>(&'c self, name: Cow<'c, str>, value: String) -> CookieBuilder<'c> {
+        let mut cookie = Cookie::build((name, value))
             .http_only(self.http_only)
             .same_site(self.same_site)
             .secure(self.secure);
@@ -70,13 +125,6 @@ impl Config {
             cookie = cookie.path(&**path);
         }
 
-        cookie.build()
-    }
-
-    #[inline]
-    fn cookie_removal(&self) -> Cookie<'_> {
-        let mut cookie = Cookie::new(&*self.cookie_name, "");
-        cookie.make_removal();
         cookie
     }
 }
@@ -94,6 +142,9 @@ impl Default for Config {
             path: None,
             same_site: cookie::SameSite::Strict,
             secure: true,
+            expiry: Expiry::default(),
+            clock: Arc::new(SystemClock),
+            transport: Transport::default(),
         }
     }
 }
@@ -132,16 +183,18 @@ impl<T, Store: SessionStore<T>> SessionLayer<T, Store> {
     #[track_caller]
     pub fn new(store: Arc<Store>, key: Key) -> SessionLayer<T, Store> {
         let key = key.into_cookie_key();
+        warn_if_unindexed(store.as_ref());
         Self {
             store,
             config: Arc::new(Config::default()),
-            cookie_controller: Arc::new(PrivateCookie::new(key)),
+            cookie_controller: Arc::new(PrivateCookie::new(key, Vec::new())),
+            conflict_policy: ConflictPolicy::default(),
+            migrations: None,
             _marker: PhantomData,
         }
     }
 }
 
-// TODO: Add customization for session expiry
 impl<T, Store: SessionStore<T>, C: CookieSecurity> SessionLayer<T, Store, C> {
     /// Authenticates cookies.
     ///
@@ -167,10 +220,13 @@ impl<T, Store: SessionStore<T>, C: CookieSecurity> SessionLayer<T, Store, C> {
     #[track_caller]
     pub fn signed(self) -> SessionLayer<T, Store, SignedCookie> {
         let key = self.cookie_controller.key().to_owned();
+        let retired_keys = self.cookie_controller.retired_keys().to_vec();
         SessionLayer {
             store: self.store,
             config: self.config,
-            cookie_controller: Arc::new(SignedCookie::new(key)),
+            cookie_controller: Arc::new(SignedCookie::new(key, retired_keys)),
+            conflict_policy: self.conflict_policy,
+            migrations: self.migrations,
             _marker: PhantomData,
         }
     }
@@ -199,10 +255,13 @@ impl<T, Store: SessionStore<T>, C: CookieSecurity> SessionLayer<T, Store, C> {
     #[track_caller]
     pub fn private(self) -> SessionLayer<T, Store, PrivateCookie> {
         let key = self.cookie_controller.key().to_owned();
+        let retired_keys = self.cookie_controller.retired_keys().to_vec();
         SessionLayer {
             store: self.store,
             config: self.config,
-            cookie_controller: Arc::new(PrivateCookie::new(key)),
+            cookie_controller: Arc::new(PrivateCookie::new(key, retired_keys)),
+            conflict_policy: self.conflict_policy,
+            migrations: self.migrations,
             _marker: PhantomData,
         }
     }
@@ -237,11 +296,7 @@ impl<T, Store: SessionStore<T>, C: CookieSecurity> SessionLayer<T, Store, C> {
     #[track_caller]
     pub fn cookie_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
         let name = name.into();
-
-        if let Err(err) = HeaderValue::try_from(format!("{}=value", name)) {
-            panic!("invalid `cookie_name` value: {}", Report::new(err));
-        }
-
+        validate_cookie_name(&name);
         self.config_mut().cookie_name = name;
         self
     }
@@ -356,6 +411,151 @@ impl<T, Store: SessionStore<T>, C: CookieSecurity> SessionLayer<T, Store, C> {
         self
     }
 
+    /// Sets the policy used to determine when a session expires.
+    ///
+    /// The policy controls both the expiration time passed to the session
+    /// store and the [`Expires`] and [`Max-Age`] attributes in the
+    /// `Set-Cookie` response header, so that a session expires at the same
+    /// time on the server and in the browser. See [`Expiry`] for the available
+    /// policies.
+    ///
+    /// Default is [`Expiry::BrowserSession`] with a server-side idle timeout
+    /// of two weeks.
+    ///
+    /// [`Expires`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#expiresdate
+    /// [`Max-Age`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#max-agenumber
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tower_sesh::{middleware::Expiry, SessionLayer};
+    /// # use std::sync::Arc;
+    /// # use tower_sesh::store::MemoryStore;
+    ///
+    /// # let key = tower_sesh::middleware::Key::from([0; 64]);
+    /// # let store = Arc::new(MemoryStore::<()>::new());
+    /// let layer = SessionLayer::new(store, key)
+    ///     .expiry(Expiry::Idle(Duration::from_secs(30 * 60)));
+    /// ```
+    pub fn expiry(mut self, expiry: Expiry) -> Self {
+        self.config_mut().expiry = expiry;
+        self
+    }
+
+    /// Sets the clock used to compute session expiry.
+    ///
+    /// All expiration times are computed in UTC. For the session store to
+    /// agree on when a session expires, it should use the same clock; see
+    /// e.g. [`MemoryStore::clock`].
+    ///
+    /// Default is [`SystemClock`].
+    ///
+    /// [`MemoryStore::clock`]: crate::store::MemoryStore::clock
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{sync::Arc, time::Duration};
+    /// use tower_sesh::{store::MemoryStore, SessionLayer};
+    /// use tower_sesh_core::time::MockClock;
+    ///
+    /// # let key = tower_sesh::middleware::Key::from([0; 64]);
+    /// let clock = MockClock::default();
+    /// let store = Arc::new(MemoryStore::<()>::new().clock(clock.clone()));
+    /// let layer = SessionLayer::new(store, key).clock(clock.clone());
+    ///
+    /// // Sessions expire as if an hour had passed
+    /// clock.advance(Duration::from_secs(60 * 60));
+    /// ```
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.config_mut().clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets how the session key is exchanged with clients.
+    ///
+    /// Clients which can't store cookies, such as mobile apps and command-line
+    /// tools, can instead send the session key in a request header. Header
+    /// values are signed or encrypted in the same way as cookie values, so they
+    /// can't be tampered with either. See [`Transport`] for the available
+    /// transports.
+    ///
+    /// Default is [`Transport::Cookie`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use http::HeaderName;
+    /// use tower_sesh::{middleware::Transport, SessionLayer};
+    /// # use std::sync::Arc;
+    /// # use tower_sesh::store::MemoryStore;
+    ///
+    /// # let key = tower_sesh::middleware::Key::from([0; 64]);
+    /// # let store = Arc::new(MemoryStore::<()>::new());
+    /// let layer = SessionLayer::new(store, key)
+    ///     .transport(Transport::Bearer(HeaderName::from_static("x-session-token")));
+    /// ```
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.config_mut().transport = transport;
+        self
+    }
+
+    /// Sets how to resolve concurrent modifications of the same session.
+    ///
+    /// When two requests load the same session and both modify it, the second
+    /// request to finish would otherwise overwrite the changes made by the
+    /// first. If the session store tracks versions, a modified session is only
+    /// written back if it wasn't written to since it was loaded; otherwise,
+    /// the conflict is resolved according to the policy. See
+    /// [`ConflictPolicy`] for the available policies.
+    ///
+    /// Default is [`ConflictPolicy::LastWriteWins`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use tower_sesh::{middleware::ConflictPolicy, SessionLayer};
+    /// # use std::sync::Arc;
+    /// # use tower_sesh::store::MemoryStore;
+    ///
+    /// type SessionData = BTreeSet<String>;
+    ///
+    /// # let key = tower_sesh::middleware::Key::from([0; 64]);
+    /// # let store = Arc::new(MemoryStore::<SessionData>::new());
+    /// let layer = SessionLayer::new(store, key).conflict_policy(ConflictPolicy::merge(
+    ///     |mut ours: SessionData, theirs: SessionData| {
+    ///         ours.extend(theirs);
+    ///         ours
+    ///     },
+    /// ));
+    /// ```
+    pub fn conflict_policy(mut self, policy: ConflictPolicy<T>) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Sets the migrations which upgrade session data stored with an older
+    /// schema version.
+    ///
+    /// The session store must serialize session data with
+    /// [`migrations.codec()`](Migrations::codec), which records the current
+    /// schema version alongside stored session data. When a session with an
+    /// older schema version is loaded, it is upgraded to the current schema
+    /// and written back to the store. If no migration matches its schema
+    /// version, an empty session is used instead. See the [`migrate`] module
+    /// for details.
+    ///
+    /// When using [`Expiry::Absolute`], the expiry of a migrated session
+    /// starts over.
+    ///
+    /// [`migrate`]: crate::migrate
+    pub fn migrations<K: Codec + Clone>(mut self, migrations: Migrations<T, K>) -> Self {
+        self.migrations = Some(Arc::new(migrations.into_chain()));
+        self
+    }
+
     fn config_mut(&mut self) -> &mut Config {
         Arc::make_mut(&mut self.config)
     }
@@ -379,21 +579,69 @@ impl<T, Store: SessionStore<T>> SessionLayer<T, Store, PlainCookie> {
     /// let layer = SessionLayer::plain(store);
     /// ```
     pub fn plain(store: Arc<Store>) -> SessionLayer<T, Store, PlainCookie> {
+        warn_if_unindexed(store.as_ref());
         SessionLayer {
             store,
             config: Arc::new(Config::default()),
             cookie_controller: Arc::new(PlainCookie),
+            conflict_policy: ConflictPolicy::default(),
+            migrations: None,
             _marker: PhantomData,
         }
     }
 }
 
+impl<T, Store: SessionStore<T>, C: KeyedCookieSecurity> SessionLayer<T, Store, C> {
+    /// Sets keys that were previously used to sign or encrypt cookies.
+    ///
+    /// Cookies are always signed or encrypted with the key passed to
+    /// [`SessionLayer::new`]. When a request's session cookie can't be opened
+    /// with that key, each retired key is tried in turn. If a retired key
+    /// succeeds, the cookie is re-issued under the current key in the
+    /// response, so users are not logged out when the key is rotated.
+    ///
+    /// Retired keys can be removed once every cookie issued under them has
+    /// either been re-issued or expired.
+    ///
+    /// This is only available on layers which sign or encrypt cookies, since
+    /// layers created with [`SessionLayer::plain`] don't use keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use tower_sesh::{middleware::Key, store::MemoryStore, SessionLayer};
+    ///
+    /// # type SessionData = ();
+    /// #
+    /// fn current_key() -> Key {
+    ///     // TODO: Where do you get a key?
+    /// # Key::from([1; 64])
+    /// }
+    ///
+    /// fn previous_key() -> Key {
+    ///     // TODO: Where do you get a key?
+    /// # Key::from([0; 64])
+    /// }
+    ///
+    /// let store = Arc::new(MemoryStore::<SessionData>::new());
+    /// let layer = SessionLayer::new(store, current_key()).retired_keys([previous_key()]);
+    /// ```
+    pub fn retired_keys(mut self, keys: impl IntoIterator<Item = Key>) -> Self {
+        let keys = keys.into_iter().map(Key::into_cookie_key).collect();
+        Arc::make_mut(&mut self.cookie_controller).set_retired_keys(keys);
+        self
+    }
+}
+
 impl<T, Store: SessionStore<T>, C: CookieSecurity> Clone for SessionLayer<T, Store, C> {
     fn clone(&self) -> Self {
         Self {
             store: Arc::clone(&self.store),
             config: self.config.clone(),
             cookie_controller: self.cookie_controller.clone(),
+            conflict_policy: self.conflict_policy.clone(),
+            migrations: self.migrations.clone(),
             _marker: PhantomData,
         }
     }
@@ -456,7 +704,7 @@ where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
     S::Error: Send,
     S::Future: Send + 'static,
-    ResBody: Send,
+    ResBody: Default + Send,
     T: Send + Sync + 'static,
     C: Send + Sync + 'static,
 {
@@ -470,13 +718,23 @@ where
     }
 
     fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
-        let session_handle = {
-            let cookie = session_cookie_from_request_headers(
+        let (session_handle, reissue_session_key) = {
+            let request_cookie = session_cookie_from_request_headers(
                 req.headers(),
-                &self.layer.config.cookie_name,
+                &self.layer.config,
                 self.layer.cookie_controller.as_ref(),
             );
-            session::lazy::insert(req.extensions_mut(), cookie, &self.layer.store)
+            let reissue_session_key = request_cookie
+                .as_ref()
+                .filter(|request_cookie| request_cookie.opened_with_retired_key)
+                .and_then(|request_cookie| SessionKey::decode(request_cookie.cookie.value()).ok());
+            let session_handle = session::lazy::insert(
+                req.extensions_mut(),
+                request_cookie.map(|request_cookie| request_cookie.cookie),
+                &self.layer.store,
+                self.layer.migrations.as_ref(),
+            );
+            (session_handle, reissue_session_key)
         };
 
         let fut = self.inner.call(req);
@@ -484,32 +742,99 @@ where
         let store = Arc::clone(&self.layer.store);
         let config = Arc::clone(&self.layer.config);
         let cookie_controller = Arc::clone(&self.layer.cookie_controller);
+        let conflict_policy = self.layer.conflict_policy.clone();
 
         async move {
             let mut response = fut.await?;
 
+            // Set if the session cookie could only be opened with a retired
+            // key, in which case it must be re-issued under the primary key.
+            let mut reissue_session_key = reissue_session_key;
+
             if let Some(session) = session_handle.get() {
-                let session = session.take();
-                let sync_result = session.sync(store.as_ref()).await;
-
-                match sync_result {
-                    Ok(SyncAction::Set(session_key)) => {
-                        let mut jar = CookieJar::new();
-                        let cookie = config.cookie(session_key);
-                        cookie_controller.add(&mut jar, cookie.into_owned());
-
-                        let cookie = jar
-                            .get(&config.cookie_name)
-                            .expect("this cookie should exist");
-                        append_set_cookie(response.headers_mut(), cookie);
+                let mut session = session.take();
+                let loaded = session.loaded();
+                let principal = match (session.take_principal(), &loaded) {
+                    // The session is moving to a new session key, which takes
+                    // over the old session key's principal
+                    (None, Some((session_key, _))) if session.cycles_key() => {
+                        indexed_principal(store.as_ref(), session_key).await
+                    }
+                    (principal, _) => principal,
+                };
+                let sync_result = session
+                    .sync(
+                        store.as_ref(),
+                        &config.expiry,
+                        config.clock.now(),
+                        &conflict_policy,
+                    )
+                    .await;
+
+                let session_key = match sync_result {
+                    Ok(SyncAction::Set(session_key, ttl)) => {
+                        set_session_key(
+                            response.headers_mut(),
+                            &config,
+                            cookie_controller.as_ref(),
+                            session_key.clone(),
+                            ttl,
+                        );
+                        Some(session_key)
                     }
                     Ok(SyncAction::Remove) => {
-                        let cookie_removal = config.cookie_removal();
-                        append_set_cookie(response.headers_mut(), &cookie_removal);
+                        remove_session_key(response.headers_mut(), &config);
+                        None
+                    }
+                    Ok(SyncAction::None) => {
+                        if let (Some(_), Some((session_key, ttl))) =
+                            (&reissue_session_key, loaded.clone())
+                        {
+                            set_session_key(
+                                response.headers_mut(),
+                                &config,
+                                cookie_controller.as_ref(),
+                                session_key,
+                                ttl,
+                            );
+                        }
+                        loaded.map(|(session_key, _)| session_key)
+                    }
+                    Ok(SyncAction::Rejected) => {
+                        debug!("rejected changes to a session which was modified concurrently");
+                        response = Response::new(ResBody::default());
+                        *response.status_mut() = StatusCode::CONFLICT;
+                        None
                     }
-                    Ok(SyncAction::None) => {}
                     Err(_err) => {
                         error!(err = %Report::new(_err), "error when syncing session to store");
+                        None
+                    }
+                };
+
+                if let (Some(session_key), Some(principal)) = (session_key, principal) {
+                    associate_principal(store.as_ref(), &session_key, &principal).await;
+                }
+
+                reissue_session_key = None;
+            }
+
+            // The session was never loaded, so its expiry has to be fetched
+            // from the store before the cookie can be re-issued.
+            if let Some(session_key) = reissue_session_key {
+                match store.load(&session_key).await {
+                    Ok(Some(record)) => {
+                        set_session_key(
+                            response.headers_mut(),
+                            &config,
+                            cookie_controller.as_ref(),
+                            session_key,
+                            record.ttl,
+                        );
+                    }
+                    Ok(None) => {}
+                    Err(_err) => {
+                        error!(err = %Report::new(_err), "error loading session");
                     }
                 }
             }
@@ -520,31 +845,153 @@ where
     }
 }
 
+/// Logs a warning if the store doesn't support indexing sessions by
+/// principal, since [`Session::set_principal`] then has no effect.
+///
+/// [`Session::set_principal`]: crate::Session::set_principal
+fn warn_if_unindexed<T>(store: &impl SessionStore<T>) {
+    if store.as_index().is_none() {
+        warn!(
+            "session store doesn't support indexing sessions by principal, \
+             so `Session::set_principal` has no effect"
+        );
+    }
+}
+
+/// Adds the session to the store's index of the principal's sessions, if the
+/// store supports indexing.
+async fn associate_principal<T>(
+    store: &impl SessionStore<T>,
+    session_key: &SessionKey,
+    principal: &str,
+) {
+    let Some(index) = store.as_index() else {
+        // Already warned about when the layer was created
+        return;
+    };
+    if let Err(_err) = index.associate(session_key, principal).await {
+        error!(err = %Report::new(_err), "error when associating session with principal");
+    }
+}
+
+/// Returns the principal the session is associated with in the store's index,
+/// if the store supports indexing.
+async fn indexed_principal<T>(
+    store: &impl SessionStore<T>,
+    session_key: &SessionKey,
+) -> Option<String> {
+    match store.as_index()?.principal(session_key).await {
+        Ok(principal) => principal,
+        Err(_err) => {
+            error!(err = %Report::new(_err), "error when looking up principal of session");
+            None
+        }
+    }
+}
+
+/// A session cookie which was successfully opened.
+struct RequestCookie {
+    cookie: Cookie<'static>,
+    /// Whether the cookie was opened with a retired key rather than the
+    /// primary key.
+    opened_with_retired_key: bool,
+}
+
 fn session_cookie_from_request_headers(
     headers: &HeaderMap,
-    name: &str,
+    config: &Config,
     cookie_controller: &impl CookieSecurity,
-) -> Option<Cookie<'static>> {
-    for cookie in cookies_from_request(headers) {
-        if cookie.name() == name {
-            let mut jar = CookieJar::new();
-            jar.add_original(cookie.into_owned());
-
-            // `cookie_controller` handles decryption/authentication if the
-            // user has it enabled
-            if let Some(cookie) = cookie_controller.get(&jar, name) {
-                return Some(cookie.into_owned());
-            } else {
-                // ignore decryption/authentication failure
-                break;
-            }
+) -> Option<RequestCookie> {
+    let name = &*config.cookie_name;
+
+    // Header values are wrapped in a cookie so that they can be opened the
+    // same way as cookie values
+    let cookie = match &config.transport {
+        Transport::Cookie => cookies_from_request(headers).find(|cookie| cookie.name() == name)?,
+        Transport::Header(header_name) => {
+            let value = headers.get(header_name)?.to_str().ok()?;
+            Cookie::new(name, value)
+        }
+        Transport::Bearer(_) => {
+            let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+            Cookie::new(name, bearer_token(value)?)
+        }
+    };
+
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie.into_owned());
+
+    // `cookie_controller` handles decryption/authentication if the user has it
+    // enabled. Decryption/authentication failure is ignored.
+    if let Some(cookie) = cookie_controller.get(&jar, name) {
+        Some(RequestCookie {
+            cookie: cookie.into_owned(),
+            opened_with_retired_key: false,
+        })
+    } else {
+        cookie_controller
+            .get_retired(&jar, name)
+            .map(|cookie| RequestCookie {
+                cookie: cookie.into_owned(),
+                opened_with_retired_key: true,
+            })
+    }
+}
+
+/// Returns the token from an `Authorization` header value using the [`Bearer`]
+/// scheme.
+///
+/// [`Bearer`]: https://datatracker.ietf.org/doc/html/rfc6750#section-2.1
+fn bearer_token(value: &str) -> Option<&str> {
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("Bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+fn set_session_key(
+    headers: &mut HeaderMap<HeaderValue>,
+    config: &Config,
+    cookie_controller: &impl CookieSecurity,
+    session_key: SessionKey,
+    ttl: Ttl,
+) {
+    let mut jar = CookieJar::new();
+    let cookie = config.cookie(session_key, ttl);
+    cookie_controller.add(&mut jar, cookie.into_owned());
+
+    let cookie = jar
+        .get(&config.cookie_name)
+        .expect("this cookie should exist");
+    match &config.transport {
+        Transport::Cookie => append_set_cookie(headers, cookie),
+        Transport::Header(header_name) | Transport::Bearer(header_name) => {
+            insert_header(headers, header_name, cookie.value())
+        }
+    }
+}
+
+fn remove_session_key(headers: &mut HeaderMap<HeaderValue>, config: &Config) {
+    match &config.transport {
+        Transport::Cookie => {
+            let cookie_removal = config.cookie_removal();
+            append_set_cookie(headers, &cookie_removal);
+        }
+        Transport::Header(header_name) | Transport::Bearer(header_name) => {
+            insert_header(headers, header_name, "")
         }
     }
+}
 
-    None
+#[track_caller]
+pub(crate) fn validate_cookie_name(name: &str) {
+    if let Err(err) = HeaderValue::try_from(format!("{}=value", name)) {
+        panic!("invalid `cookie_name` value: {}", Report::new(err));
+    }
 }
 
-fn cookies_from_request(headers: &HeaderMap) -> impl Iterator<Item = Cookie<'_>> {
+pub(crate) fn cookies_from_request(headers: &HeaderMap) -> impl Iterator<Item = Cookie<'_>> {
     headers
         .get_all(header::COOKIE)
         .into_iter()
@@ -554,7 +1001,7 @@ fn cookies_from_request(headers: &HeaderMap) -> impl Iterator<Item = Cookie<'_>>
 }
 
 #[inline]
-fn append_set_cookie(headers: &mut HeaderMap<HeaderValue>, cookie: &Cookie<'_>) {
+pub(crate) fn append_set_cookie(headers: &mut HeaderMap<HeaderValue>, cookie: &Cookie<'_>) {
     match HeaderValue::try_from(cookie.encoded().to_string()) {
         Ok(header_value) => {
             headers.append(header::SET_COOKIE, header_value);
@@ -565,6 +1012,18 @@ fn append_set_cookie(headers: &mut HeaderMap<HeaderValue>, cookie: &Cookie<'_>)
     }
 }
 
+#[inline]
+fn insert_header(headers: &mut HeaderMap<HeaderValue>, name: &HeaderName, value: &str) {
+    match HeaderValue::try_from(value) {
+        Ok(header_value) => {
+            headers.insert(name, header_value);
+        }
+        Err(_err) => {
+            error!(err = %Report::new(_err), header = %name, "this is likely a bug");
+        }
+    }
+}
+
 /// A 64-byte cryptographic key used by [`SessionLayer`] to sign or encrypt
 /// cookies.
 ///
@@ -589,7 +1048,7 @@ impl Key {
     pub const LEN: usize = 64;
 
     #[track_caller]
-    fn into_cookie_key(self) -> cookie::Key {
+    pub(crate) fn into_cookie_key(self) -> cookie::Key {
         match cookie::Key::try_from(self.0.as_slice()) {
             Ok(key) => key,
             Err(err) => panic!("failed to convert key to `cookie::Key`: {err}"),
@@ -666,6 +1125,235 @@ impl fmt::Display for KeyError {
     }
 }
 
+/// Policy that determines when a session expires.
+///
+/// An expiry policy is set with [`SessionLayer::expiry`].
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_sesh::middleware::Expiry;
+///
+/// // Log out users who have been inactive for 30 minutes
+/// let idle = Expiry::Idle(Duration::from_secs(30 * 60));
+///
+/// // Log out users 8 hours after they log in, even if they are active
+/// let absolute = Expiry::Absolute(Duration::from_secs(8 * 60 * 60));
+///
+/// // Log out users when they close their browser, or after a day of
+/// // inactivity
+/// let browser_session = Expiry::BrowserSession(Duration::from_secs(24 * 60 * 60));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Expiry {
+    /// The session expires after it has been inactive for the given duration.
+    ///
+    /// This is a sliding expiration: requests which access the session push
+    /// back its expiration time, both in the session store and in the
+    /// `Expires` and `Max-Age` cookie attributes. To avoid writing to the
+    /// session store on every request, a session which is only read is
+    /// refreshed once less than half of the duration remains.
+    Idle(Duration),
+
+    /// The session expires once the given duration has elapsed since it was
+    /// created, regardless of activity.
+    ///
+    /// Modifying or [renewing] the session does not push back its expiration
+    /// time.
+    ///
+    /// [renewing]: crate::Session::renew
+    Absolute(Duration),
+
+    /// The session cookie expires when the browser session ends, since the
+    /// cookie is sent without the `Expires` and `Max-Age` attributes.
+    ///
+    /// Because browsers may restore session cookies when they restart, the
+    /// session still expires in the session store after it has been inactive
+    /// for the given duration, as with [`Expiry::Idle`].
+    BrowserSession(Duration),
+}
+
+impl Expiry {
+    /// Returns the expiration time of a session, given the current time and
+    /// the session's previous expiration time, if it has one.
+    pub(crate) fn ttl(&self, now: Ttl, expires_at: Option<Ttl>) -> Ttl {
+        match *self {
+            Expiry::Idle(duration) | Expiry::BrowserSession(duration) => now + duration,
+            Expiry::Absolute(duration) => expires_at.unwrap_or(now + duration),
+        }
+    }
+
+    /// Returns `true` if the expiration time of a session is pushed back when
+    /// it is accessed or renewed.
+    #[inline]
+    pub(crate) fn is_sliding(&self) -> bool {
+        matches!(self, Expiry::Idle(_) | Expiry::BrowserSession(_))
+    }
+
+    /// Returns `true` if a session which was accessed without being modified
+    /// should have its expiration time pushed back, given the current time and
+    /// the session's expiration time, if it has one.
+    ///
+    /// This is only the case once less than half of the idle timeout remains.
+    pub(crate) fn needs_refresh(&self, now: Ttl, expires_at: Option<Ttl>) -> bool {
+        match *self {
+            Expiry::Idle(duration) | Expiry::BrowserSession(duration) => {
+                expires_at.map_or(true, |expires_at| expires_at < now + duration / 2)
+            }
+            Expiry::Absolute(_) => false,
+        }
+    }
+
+    /// Returns `true` if the session cookie should outlive the browser
+    /// session.
+    #[inline]
+    pub(crate) fn is_persistent(&self) -> bool {
+        !matches!(self, Expiry::BrowserSession(_))
+    }
+}
+
+impl Default for Expiry {
+    #[inline]
+    fn default() -> Self {
+        Expiry::BrowserSession(Duration::from_secs(u64::from(
+            SESSION_EXPIRY_SECONDS_DEFAULT,
+        )))
+    }
+}
+
+/// Policy that determines how concurrent modifications of the same session are
+/// resolved.
+///
+/// A conflict policy is set with [`SessionLayer::conflict_policy`].
+///
+/// Conflicts can only be detected if the session store tracks versions of
+/// sessions, as [`MemoryStore`] and `RedisStore` do. With other stores,
+/// every policy behaves like [`ConflictPolicy::LastWriteWins`].
+///
+/// [`MemoryStore`]: crate::store::MemoryStore
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh::middleware::ConflictPolicy;
+///
+/// // Discard changes made to a session which was modified concurrently
+/// let reject = ConflictPolicy::<u64>::Reject;
+///
+/// // Combine changes made to a session which was modified concurrently
+/// let merge = ConflictPolicy::merge(|ours: u64, theirs: u64| ours.max(theirs));
+/// ```
+#[non_exhaustive]
+pub enum ConflictPolicy<T> {
+    /// The session is overwritten with the data from the request which
+    /// finished last, discarding the changes made by other requests.
+    ///
+    /// The session is written without checking for conflicts, so a session
+    /// which was removed concurrently, such as by logging out in another
+    /// request, is written back. Use [`Reject`] or [`Merge`] if that must not
+    /// happen.
+    ///
+    /// [`Reject`]: ConflictPolicy::Reject
+    /// [`Merge`]: ConflictPolicy::Merge
+    LastWriteWins,
+
+    /// The changes made by the request which finished last are discarded, and
+    /// its response is replaced with an empty `409 Conflict` response, so that
+    /// the client knows to retry the request. A session which was removed
+    /// concurrently is not restored.
+    Reject,
+
+    /// The data from the request which finished last is combined with the
+    /// session's current data by calling the closure with `(ours, theirs)`,
+    /// and the result is written back. If the session was removed
+    /// concurrently, the closure isn't called and the session is not
+    /// restored.
+    ///
+    /// Use [`ConflictPolicy::merge`] to construct this variant.
+    Merge(Arc<dyn Fn(T, T) -> T + Send + Sync>),
+}
+
+impl<T> ConflictPolicy<T> {
+    /// Creates a [`ConflictPolicy::Merge`] policy from a closure.
+    pub fn merge<F>(f: F) -> ConflictPolicy<T>
+    where
+        F: Fn(T, T) -> T + Send + Sync + 'static,
+    {
+        ConflictPolicy::Merge(Arc::new(f))
+    }
+}
+
+impl<T> Clone for ConflictPolicy<T> {
+    fn clone(&self) -> Self {
+        match self {
+            ConflictPolicy::LastWriteWins => ConflictPolicy::LastWriteWins,
+            ConflictPolicy::Reject => ConflictPolicy::Reject,
+            ConflictPolicy::Merge(f) => ConflictPolicy::Merge(Arc::clone(f)),
+        }
+    }
+}
+
+impl<T> Default for ConflictPolicy<T> {
+    #[inline]
+    fn default() -> Self {
+        ConflictPolicy::LastWriteWins
+    }
+}
+
+impl<T> fmt::Debug for ConflictPolicy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::LastWriteWins => f.write_str("LastWriteWins"),
+            ConflictPolicy::Reject => f.write_str("Reject"),
+            ConflictPolicy::Merge(_) => f.write_str("Merge(..)"),
+        }
+    }
+}
+
+/// How the session key is exchanged between clients and the server.
+///
+/// A transport is set with [`SessionLayer::transport`].
+///
+/// With every transport, the session key is signed or encrypted in the same
+/// way as a cookie value would be (see [`SessionLayer::signed`] and
+/// [`SessionLayer::private`]). For header-based transports, the `Expires` and
+/// `Max-Age` cookie attributes are not sent, and an empty header value tells the
+/// client to discard its session key.
+///
+/// # Examples
+///
+/// ```
+/// use http::HeaderName;
+/// use tower_sesh::middleware::Transport;
+///
+/// // Session key is sent in and returned in the `X-Session` header
+/// let header = Transport::Header(HeaderName::from_static("x-session"));
+///
+/// // Session key is sent in `Authorization: Bearer <token>`, and returned in
+/// // the `X-Session-Token` header
+/// let bearer = Transport::Bearer(HeaderName::from_static("x-session-token"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Transport {
+    /// The session key is read from the `Cookie` request header and written to
+    /// the `Set-Cookie` response header.
+    #[default]
+    Cookie,
+
+    /// The session key is read from the named request header and written to
+    /// the same header in the response.
+    Header(HeaderName),
+
+    /// The session key is read from the `Authorization` request header using
+    /// the [`Bearer`] scheme and written to the named response header.
+    ///
+    /// [`Bearer`]: https://datatracker.ietf.org/doc/html/rfc6750#section-2.1
+    Bearer(HeaderName),
+}
+
 /// The [`SameSite`] cookie attribute, which controls whether or not a cookie is
 /// sent with cross-site requests.
 ///
@@ -702,7 +1390,7 @@ pub enum SameSite {
 }
 
 impl SameSite {
-    fn into_cookie_same_site(self) -> cookie::SameSite {
+    pub(crate) fn into_cookie_same_site(self) -> cookie::SameSite {
         match self {
             SameSite::Strict => cookie::SameSite::Strict,
             SameSite::Lax => cookie::SameSite::Lax,
@@ -0,0 +1,175 @@
+//! Migrating session data stored with an older schema.
+//!
+//! When the session data type changes, sessions stored with the previous type
+//! can no longer be deserialized. Without migrations, such sessions are
+//! treated as corrupted and replaced with empty sessions, logging users out.
+//!
+//! A [`Migrations`] chain describes every schema the session data has had,
+//! along with a function to upgrade each schema to the next one. Its
+//! [`codec`](Migrations::codec) records the current schema version alongside
+//! stored session data. When a session stored with an older schema version is
+//! loaded, [`SessionLayer`] upgrades it to the current schema, and writes it
+//! back to the store at the end of the request. A session is only treated as
+//! corrupted if no migration matches its schema version.
+//!
+//! Migrations only apply to stores which serialize session data with a
+//! [`Codec`], such as `RedisStore` and [`EncryptedStore`].
+//!
+//! [`SessionLayer`]: crate::SessionLayer
+//! [`EncryptedStore`]: crate::store::EncryptedStore
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use serde::{Deserialize, Serialize};
+//! use tower_sesh::{migrate::Migrations, SessionLayer};
+//! use tower_sesh_core::codec::{Codec, MessagePack};
+//!
+//! #[derive(Deserialize)]
+//! struct SessionV1 {
+//!     user_id: u64,
+//! }
+//!
+//! #[derive(Clone, Deserialize, Serialize)]
+//! struct SessionV2 {
+//!     user_id: u64,
+//!     theme: String,
+//! }
+//!
+//! # fn store_with_codec(codec: impl Codec) -> tower_sesh::store::MemoryStore<SessionV2> {
+//! #     tower_sesh::store::MemoryStore::new()
+//! # }
+//! # fn key() -> tower_sesh::middleware::Key { tower_sesh::middleware::Key::from([0; 64]) }
+//! #
+//! let migrations = Migrations::<SessionV1, _>::new(MessagePack).step(|v1| SessionV2 {
+//!     user_id: v1.user_id,
+//!     theme: "light".to_owned(),
+//! });
+//!
+//! let store = store_with_codec(migrations.codec());
+//! let layer = SessionLayer::new(Arc::new(store), key()).migrations(migrations);
+//! ```
+
+use std::{fmt, sync::Arc};
+
+use serde::de::DeserializeOwned;
+use tower_sesh_core::{
+    codec::{Codec, Versioned},
+    store::Result,
+};
+
+type Upgrade<T> = Box<dyn Fn(&[u8]) -> Result<T> + Send + Sync>;
+
+/// A chain of migrations which upgrade session data from every previous
+/// schema to `T`.
+///
+/// The first schema passed to [`new`] has version 1, and each call to
+/// [`step`] adds a schema with the next version. See the [module-level
+/// documentation](self) for details.
+///
+/// [`new`]: Migrations::new
+/// [`step`]: Migrations::step
+pub struct Migrations<T, K> {
+    codec: K,
+    chain: Chain<T>,
+}
+
+/// The type-erased upgrade functions of a [`Migrations`] chain.
+pub(crate) struct Chain<T> {
+    /// The function at index `i` upgrades data with schema version `i + 1`.
+    upgrades: Vec<Upgrade<T>>,
+}
+
+impl<T, K: Codec + Clone> Migrations<T, K> {
+    /// Creates a migration chain whose first schema is `T`, which is
+    /// serialized with `codec`.
+    ///
+    /// Session data stored before schema versions were recorded has schema
+    /// version 1.
+    pub fn new(codec: K) -> Migrations<T, K> {
+        Migrations {
+            codec,
+            chain: Chain {
+                upgrades: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a schema `U`, which data with the current schema `T` is upgraded
+    /// to with `f`.
+    pub fn step<U, F>(self, f: F) -> Migrations<U, K>
+    where
+        T: 'static + DeserializeOwned,
+        U: 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+
+        let mut upgrades = self
+            .chain
+            .upgrades
+            .into_iter()
+            .map(|upgrade| {
+                let f = Arc::clone(&f);
+                Box::new(move |payload: &[u8]| upgrade(payload).map(|data| f(data))) as Upgrade<U>
+            })
+            .collect::<Vec<_>>();
+
+        let codec = self.codec.clone();
+        upgrades.push(Box::new(move |payload: &[u8]| {
+            codec.decode::<T>(payload).map(|data| f(data))
+        }));
+
+        Migrations {
+            codec: self.codec,
+            chain: Chain { upgrades },
+        }
+    }
+
+    /// Returns the schema version of `T`.
+    pub fn version(&self) -> u32 {
+        self.chain.version()
+    }
+
+    /// Returns the codec which must be used by the session store, which
+    /// records the current schema version alongside session data.
+    pub fn codec(&self) -> Versioned<K> {
+        Versioned::new(self.codec.clone(), self.version())
+    }
+
+    pub(crate) fn into_chain(self) -> Chain<T> {
+        self.chain
+    }
+}
+
+impl<T, K: fmt::Debug> fmt::Debug for Migrations<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migrations")
+            .field("codec", &self.codec)
+            .field("version", &self.chain.version())
+            .finish()
+    }
+}
+
+impl<T> Chain<T> {
+    fn version(&self) -> u32 {
+        u32::try_from(self.upgrades.len() + 1).expect("too many schema versions")
+    }
+
+    /// Upgrades `payload` from `schema_version` to the current schema, or
+    /// returns `None` if there is no migration for `schema_version`.
+    pub(crate) fn migrate(&self, schema_version: u32, payload: &[u8]) -> Option<Result<T>> {
+        let index = usize::try_from(schema_version.checked_sub(1)?).ok()?;
+        self.upgrades.get(index).map(|upgrade| upgrade(payload))
+    }
+}
+
+impl<T> fmt::Debug for Chain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("version", &self.version())
+            .finish_non_exhaustive()
+    }
+}
@@ -2,11 +2,15 @@ use std::{
     fmt,
     ops::{Deref, DerefMut},
     sync::Arc,
-    time::Duration,
 };
 
 use parking_lot::{Mutex, MutexGuard};
-use tower_sesh_core::{time::now, Record, SessionKey, SessionStore, Ttl};
+use tower_sesh_core::{
+    store::{self, UpdateIf, Version},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+use crate::middleware::{ConflictPolicy, Expiry};
 
 /// Extractor to read and mutate session data.
 ///
@@ -27,7 +31,10 @@ pub(crate) struct Inner<T> {
     session_key: Option<SessionKey>,
     data: Option<T>,
     expires_at: Option<Ttl>,
+    version: Option<Version>,
     status: Status,
+    cycle_key: bool,
+    principal: Option<String>,
 }
 
 /// The status of a session.
@@ -66,14 +73,36 @@ use Status::*;
 
 /// Which action was performed by `Session::sync`.
 pub(crate) enum SyncAction {
-    /// The session was created, updated, or renewed with the session key.
-    Set(SessionKey),
+    /// The session was created, updated, or renewed with the session key,
+    /// and expires at the given time.
+    Set(SessionKey, Ttl),
 
     /// The session was removed.
     Remove,
 
     /// The session was unmodified. No action was performed.
     None,
+
+    /// The session was modified concurrently, and its changes were discarded
+    /// by [`ConflictPolicy::Reject`].
+    Rejected,
+}
+
+/// Which action should be performed by [`CookieSessionManager`] to write a
+/// session back to the client.
+///
+/// [`CookieSessionManager`]: crate::cookie_store::CookieSessionManager
+#[cfg(feature = "cookie-store")]
+pub(crate) enum CookieSyncAction<T> {
+    /// The session should be sealed into cookies with the given data and
+    /// expiry.
+    Set(T, Ttl),
+
+    /// The session's cookies should be removed.
+    Remove,
+
+    /// The session was unmodified. No action should be performed.
+    None,
 }
 
 impl<T> Session<T> {
@@ -118,7 +147,7 @@ impl<T> Session<T> {
     /// # store.update(
     /// #     &session_key,
     /// #     &SessionData { user_id: 1234 },
-    /// #     Ttl::now_local().unwrap() + Duration::from_secs(10 * 60),
+    /// #     Ttl::now_utc() + Duration::from_secs(10 * 60),
     /// # ).await?;
     /// # let session_layer = SessionLayer::plain(store.into())
     /// #     .cookie_name("id");
@@ -188,7 +217,7 @@ impl<T> Session<T> {
     /// # store.update(
     /// #     &session_key,
     /// #     &SessionData { theme: Theme::Dark },
-    /// #     Ttl::now_local().unwrap() + Duration::from_secs(10 * 60),
+    /// #     Ttl::now_utc() + Duration::from_secs(10 * 60),
     /// # ).await?;
     /// # let session_layer = SessionLayer::plain(store.into())
     /// #     .cookie_name("id");
@@ -278,6 +307,45 @@ impl<T> Session<T> {
         self.lock().purged();
     }
 
+    /// Moves the session to a new session key, invalidating the old one.
+    ///
+    /// The session's data is preserved. When the session is synced to the
+    /// store, the data is stored under a newly generated session key, the old
+    /// session key is deleted, and the new session key is sent to the client.
+    ///
+    /// To prevent [session fixation] attacks, [OWASP recommends] calling this
+    /// whenever a user's privilege level changes, such as when they log in.
+    ///
+    /// [session fixation]: https://owasp.org/www-community/attacks/Session_fixation
+    /// [OWASP recommends]:
+    ///     https://cheatsheetseries.owasp.org/cheatsheets/Session_Management_Cheat_Sheet.html#renew-the-session-id-after-any-privilege-level-change
+    ///
+    /// If the session was associated with a principal, the new session key is
+    /// associated with the same principal.
+    #[inline]
+    pub fn cycle_key(&self) {
+        self.lock().cycle_key = true;
+    }
+
+    /// Associates the session with a principal, such as a user id.
+    ///
+    /// When the session is synced to the store, it is added to the store's
+    /// [`SessionIndex`], so that every session of the principal can later be
+    /// listed or deleted, for example to log a user out everywhere after a
+    /// password reset. If the store doesn't support indexing, this has no
+    /// effect, and a warning is logged when the [`SessionLayer`] is created.
+    ///
+    /// This is typically called when a user logs in, together with
+    /// [`cycle_key`].
+    ///
+    /// [`SessionIndex`]: tower_sesh_core::store::SessionIndex
+    /// [`SessionLayer`]: crate::SessionLayer
+    /// [`cycle_key`]: Session::cycle_key
+    #[inline]
+    pub fn set_principal(&self, principal: impl Into<String>) {
+        self.lock().principal = Some(principal.into());
+    }
+
     #[inline]
     fn lock(&self) -> MutexGuard<'_, Inner<T>> {
         let guard = self.inner.lock();
@@ -308,7 +376,10 @@ impl<T> Session<T> {
             session_key: Some(session_key),
             data: Some(record.data),
             expires_at: Some(record.ttl),
+            version: record.version,
             status: Unchanged,
+            cycle_key: false,
+            principal: None,
         };
         Session::from_inner(inner)
     }
@@ -319,7 +390,10 @@ impl<T> Session<T> {
             session_key: None,
             data: None,
             expires_at: None,
+            version: None,
             status: Unchanged,
+            cycle_key: false,
+            principal: None,
         };
         Session::from_inner(inner)
     }
@@ -329,7 +403,42 @@ impl<T> Session<T> {
             session_key: Some(session_key),
             data: None,
             expires_at: None,
+            version: None,
             status: Unchanged,
+            cycle_key: false,
+            principal: None,
+        };
+        Session::from_inner(inner)
+    }
+
+    /// Creates a session from data which was upgraded from an older schema
+    /// version, so that it is written back to the store.
+    fn migrated(session_key: SessionKey, data: T) -> Session<T> {
+        let inner = Inner {
+            session_key: Some(session_key),
+            data: Some(data),
+            expires_at: None,
+            version: None,
+            status: Changed,
+            cycle_key: false,
+            principal: None,
+        };
+        Session::from_inner(inner)
+    }
+
+    /// Creates a session from data which was stored in a cookie, rather than
+    /// in a session store.
+    #[cfg(feature = "cookie-store")]
+    #[inline]
+    pub(crate) fn from_cookie(record: Record<T>) -> Session<T> {
+        let inner = Inner {
+            session_key: None,
+            data: Some(record.data),
+            expires_at: Some(record.ttl),
+            version: record.version,
+            status: Unchanged,
+            cycle_key: false,
+            principal: None,
         };
         Session::from_inner(inner)
     }
@@ -406,11 +515,30 @@ impl<T> Inner<T> {
                 session_key: None,
                 data: None,
                 expires_at: None,
+                version: None,
                 status: Taken,
+                cycle_key: false,
+                principal: None,
             },
         )
     }
 
+    /// Returns the session key and expiry of the session as it was loaded from
+    /// the store, if it was loaded from the store.
+    pub(crate) fn loaded(&self) -> Option<(SessionKey, Ttl)> {
+        self.session_key.clone().zip(self.expires_at)
+    }
+
+    /// Takes the principal set with [`Session::set_principal`], if any.
+    pub(crate) fn take_principal(&mut self) -> Option<String> {
+        self.principal.take()
+    }
+
+    /// Returns whether the session key is cycled when the session is synced.
+    pub(crate) fn cycles_key(&self) -> bool {
+        self.cycle_key
+    }
+
     /// Sync this session to the passed session store, if it needs syncing.
     ///
     /// This method should be called on the return value of [`Session::take`].
@@ -425,28 +553,56 @@ impl<T> Inner<T> {
     pub(crate) async fn sync(
         self,
         store: &impl SessionStore<T>,
-    ) -> Result<SyncAction, tower_sesh_core::store::Error> {
-        // FIXME: Determine proper `ttl`.
-        let ttl = now() + Duration::from_secs(10 * 60 * 60);
-
+        expiry: &Expiry,
+        now: Ttl,
+        conflict_policy: &ConflictPolicy<T>,
+    ) -> Result<SyncAction, store::Error>
+    where
+        T: Sync,
+    {
         match (self.status, self.session_key, self.data) {
-            (Renewed, Some(session_key), _) => {
+            (Unchanged | Renewed | Changed, Some(session_key), Some(data)) if self.cycle_key => {
+                let ttl = expiry.ttl(now, self.expires_at);
+                let session_key = store.cycle_key(&session_key, &data, ttl).await?;
+                Ok(SyncAction::Set(session_key, ttl))
+            }
+            (Unchanged, Some(session_key), Some(_))
+                if expiry.needs_refresh(now, self.expires_at) =>
+            {
+                let ttl = expiry.ttl(now, self.expires_at);
+                store.update_ttl(&session_key, ttl).await?;
+                Ok(SyncAction::Set(session_key, ttl))
+            }
+            (Renewed, Some(session_key), _) if expiry.is_sliding() => {
+                let ttl = expiry.ttl(now, self.expires_at);
                 store.update_ttl(&session_key, ttl).await?;
-                Ok(SyncAction::Set(session_key))
+                Ok(SyncAction::Set(session_key, ttl))
             }
             (Changed, Some(session_key), Some(data)) => {
-                store.update(&session_key, &data, ttl).await?;
-                Ok(SyncAction::Set(session_key))
+                let ttl = expiry.ttl(now, self.expires_at);
+                match (conflict_policy, self.version) {
+                    // Conflicts would be overwritten anyway, so they aren't
+                    // looked for
+                    (ConflictPolicy::LastWriteWins, _) | (_, None) => {
+                        store.update(&session_key, &data, ttl).await?;
+                        Ok(SyncAction::Set(session_key, ttl))
+                    }
+                    (_, Some(version)) => {
+                        update_versioned(store, session_key, data, ttl, version, conflict_policy)
+                            .await
+                    }
+                }
             }
             (Changed, None, Some(data)) => {
+                let ttl = expiry.ttl(now, None);
                 let session_key = store.create(&data, ttl).await?;
-                Ok(SyncAction::Set(session_key))
+                Ok(SyncAction::Set(session_key, ttl))
             }
             (Changed, Some(session_key), None) | (Purged, Some(session_key), _) => {
                 store.delete(&session_key).await?;
                 Ok(SyncAction::Remove)
             }
-            (Unchanged, _, _) | (Renewed, None, _) | (Changed, None, None) | (Purged, None, _) => {
+            (Unchanged, _, _) | (Renewed, _, _) | (Changed, None, None) | (Purged, None, _) => {
                 Ok(SyncAction::None)
             }
             (Taken, _, _) => {
@@ -456,6 +612,87 @@ impl<T> Inner<T> {
     }
 }
 
+/// Writes back a modified session which was loaded with `version`, resolving
+/// conflicting modifications according to `conflict_policy`.
+async fn update_versioned<T>(
+    store: &impl SessionStore<T>,
+    session_key: SessionKey,
+    mut data: T,
+    ttl: Ttl,
+    mut version: Version,
+    conflict_policy: &ConflictPolicy<T>,
+) -> Result<SyncAction, store::Error>
+where
+    T: Sync,
+{
+    const MAX_ITERATIONS: usize = 8;
+    for _ in 0..MAX_ITERATIONS {
+        let current = match store.update_if(&session_key, &data, ttl, version).await? {
+            UpdateIf::Conflict(current) => current,
+            _ => return Ok(SyncAction::Set(session_key, ttl)),
+        };
+
+        match (conflict_policy, current) {
+            // The session was deleted, such as by logging out, and must not be
+            // restored
+            (_, None) => return Ok(SyncAction::Remove),
+            (ConflictPolicy::LastWriteWins, Some(_)) => {
+                store.update(&session_key, &data, ttl).await?;
+                return Ok(SyncAction::Set(session_key, ttl));
+            }
+            (ConflictPolicy::Reject, Some(_)) => return Ok(SyncAction::Rejected),
+            (ConflictPolicy::Merge(merge), Some(record)) => {
+                data = merge(data, record.data);
+                match record.version {
+                    Some(current_version) => version = current_version,
+                    None => {
+                        store.update(&session_key, &data, ttl).await?;
+                        return Ok(SyncAction::Set(session_key, ttl));
+                    }
+                }
+            }
+        }
+    }
+
+    Err(store::Error::message(
+        "max iterations reached when merging concurrent session modifications",
+    ))
+}
+
+#[cfg(feature = "cookie-store")]
+impl<T> Inner<T> {
+    /// Determines how this session should be written back to the client, for
+    /// a session whose data is stored in cookies rather than in a session
+    /// store.
+    ///
+    /// Like [`Inner::sync`], this method should be called on the return value
+    /// of [`Session::take`].
+    ///
+    /// # Panics
+    ///
+    /// If this function is called when `status` is [`Status::Taken`], it will
+    /// panic.
+    pub(crate) fn sync_cookie(self, expiry: &Expiry, now: Ttl) -> CookieSyncAction<T> {
+        match (self.status, self.data) {
+            (Unchanged | Renewed | Changed, Some(data)) if self.cycle_key => {
+                CookieSyncAction::Set(data, expiry.ttl(now, self.expires_at))
+            }
+            (Unchanged, Some(data)) if expiry.needs_refresh(now, self.expires_at) => {
+                CookieSyncAction::Set(data, expiry.ttl(now, self.expires_at))
+            }
+            (Renewed, Some(data)) if expiry.is_sliding() => {
+                CookieSyncAction::Set(data, expiry.ttl(now, self.expires_at))
+            }
+            (Changed, Some(data)) => CookieSyncAction::Set(data, expiry.ttl(now, self.expires_at)),
+            (Changed, None) | (Purged, _) => CookieSyncAction::Remove,
+            (Unchanged, _) | (Renewed, _) => CookieSyncAction::None,
+            (Taken, _) => {
+                unreachable!("`sync_cookie` called in `Taken` state. This is a bug.")
+            }
+        }
+    }
+}
+
 define_rejection! {
     #[status = INTERNAL_SERVER_ERROR]
     #[body = "Failed to load session"]
@@ -627,12 +864,14 @@ pub(crate) mod lazy {
     use tower_sesh_core::{store::ErrorKind, SessionKey, SessionStore};
 
     use super::Session;
+    use crate::migrate::Chain;
 
     #[track_caller]
     pub(crate) fn insert<T>(
         extensions: &mut Extensions,
         cookie: Option<Cookie<'static>>,
         store: &Arc<impl SessionStore<T>>,
+        migrations: Option<&Arc<Chain<T>>>,
     ) -> LazySessionHandle<T>
     where
         T: 'static + Send,
@@ -643,7 +882,7 @@ pub(crate) mod lazy {
         );
 
         let lazy_session = match cookie {
-            Some(cookie) => LazySession::new(cookie, Arc::clone(store)),
+            Some(cookie) => LazySession::new(cookie, Arc::clone(store), migrations.cloned()),
             None => LazySession::empty(),
         };
         let handle = lazy_session.handle();
@@ -652,6 +891,37 @@ pub(crate) mod lazy {
         handle
     }
 
+    /// Inserts a session which has already been loaded, e.g. from a cookie.
+    /// If `session` is `None`, an empty session is created on first access.
+    #[cfg(feature = "cookie-store")]
+    #[track_caller]
+    pub(crate) fn insert_loaded<T>(
+        extensions: &mut Extensions,
+        session: Option<Session<T>>,
+    ) -> LazySessionHandle<T>
+    where
+        T: 'static + Send,
+    {
+        debug_assert!(
+            extensions.get::<LazySession<T>>().is_none(),
+            "`tower_sesh::session::lazy::insert` was called more than once!"
+        );
+
+        // An `Empty` session whose cell is already initialized never falls back
+        // to `Session::empty()`
+        let session_cell = match session {
+            Some(session) => OnceCell::new_with(session),
+            None => OnceCell::new(),
+        };
+        let lazy_session = LazySession::Empty {
+            session_cell: Arc::new(session_cell),
+        };
+        let handle = lazy_session.handle();
+        extensions.insert::<LazySession<T>>(lazy_session);
+
+        handle
+    }
+
     pub(super) async fn get_or_init<T>(
         extensions: &Extensions,
     ) -> Result<Option<&Session<T>>, Error>
@@ -671,6 +941,7 @@ pub(crate) mod lazy {
         Load {
             cookie: Cookie<'static>,
             store: Arc<dyn SessionStore<T> + 'static>,
+            migrations: Option<Arc<Chain<T>>>,
             session_cell: Arc<OnceCell<Option<Session<T>>>>,
         },
     }
@@ -689,10 +960,12 @@ pub(crate) mod lazy {
                 LazySession::Load {
                     cookie,
                     store,
+                    migrations,
                     session_cell,
                 } => LazySession::Load {
                     cookie: cookie.clone(),
                     store: Arc::clone(store),
+                    migrations: migrations.clone(),
                     session_cell: Arc::clone(session_cell),
                 },
             }
@@ -704,10 +977,15 @@ pub(crate) mod lazy {
         T: 'static,
     {
         #[inline]
-        fn new(cookie: Cookie<'static>, store: Arc<impl SessionStore<T>>) -> LazySession<T> {
+        fn new(
+            cookie: Cookie<'static>,
+            store: Arc<impl SessionStore<T>>,
+            migrations: Option<Arc<Chain<T>>>,
+        ) -> LazySession<T> {
             LazySession::Load {
                 cookie,
                 store,
+                migrations,
                 session_cell: Arc::new(OnceCell::new()),
             }
         }
@@ -729,9 +1007,10 @@ pub(crate) mod lazy {
                 LazySession::Load {
                     cookie,
                     store,
+                    migrations,
                     session_cell,
                 } => session_cell
-                    .get_or_init(init_session(cookie, store.as_ref()))
+                    .get_or_init(init_session(cookie, store.as_ref(), migrations.as_deref()))
                     .await
                     .as_ref(),
             }
@@ -752,6 +1031,7 @@ pub(crate) mod lazy {
     async fn init_session<T>(
         cookie: &Cookie<'static>,
         store: &dyn SessionStore<T>,
+        migrations: Option<&Chain<T>>,
     ) -> Option<Session<T>>
     where
         T: 'static,
@@ -766,6 +1046,22 @@ pub(crate) mod lazy {
             Ok(None) => Some(Session::empty()),
             Err(err) => match err.kind() {
                 ErrorKind::Serde(_) => Some(Session::corrupted(session_key)),
+                ErrorKind::Outdated(outdated) => {
+                    let migrated = migrations.and_then(|migrations| {
+                        migrations.migrate(outdated.schema_version(), outdated.payload())
+                    });
+                    match migrated {
+                        Some(Ok(data)) => Some(Session::migrated(session_key, data)),
+                        Some(Err(_err)) => {
+                            warn!(
+                                err = %tower_sesh_core::util::Report::new(_err),
+                                "error migrating session"
+                            );
+                            Some(Session::corrupted(session_key))
+                        }
+                        None => Some(Session::corrupted(session_key)),
+                    }
+                }
                 _ => {
                     error!(
                         err = %tower_sesh_core::util::Report::new(err),
@@ -1,53 +1,506 @@
+#[cfg(feature = "memory-store")]
+use std::borrow::Cow;
+#[cfg(feature = "memory-store")]
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 use std::{fmt, marker::PhantomData};
 
 use async_trait::async_trait;
 #[cfg(feature = "memory-store")]
 use dashmap::DashMap;
 #[cfg(feature = "memory-store")]
+use moka::{
+    notification::RemovalCause,
+    ops::compute::{CompResult, Op},
+};
 use rand::{rngs::ThreadRng, Rng};
+#[cfg(feature = "memory-store")]
+use tower_sesh_core::{
+    key::KeyHasher,
+    time::{Clock, SystemClock},
+};
 use tower_sesh_core::{
-    store::{Result, SessionStoreImpl},
+    store::{
+        CreateWithKey, IndexedSession, Result, SessionIndex, SessionStoreImpl, StorageId, UpdateIf,
+        Version,
+    },
     Record, SessionKey, Ttl,
 };
 
 #[doc(inline)]
 pub use tower_sesh_core::SessionStore;
 
-// TODO: Implement `MemoryStore` with `moka` instead of `dashmap`.
-// It supports per-entry expiration policy, which makes it more suitable
-// for use as an in-memory store.
-// See https://docs.rs/moka/0.12.10/moka/sync/struct.Cache.html#per-entry-expiration-policy
+#[cfg(feature = "encrypted-store")]
+pub use encrypted::{EncryptedStore, EncryptionKey, Sealed};
+#[cfg(feature = "file-store")]
+pub use file::FileStore;
+
+#[cfg(feature = "memory-store-snapshot")]
+pub use snapshot::Snapshots;
+#[cfg(any(feature = "memory-store", feature = "file-store"))]
+pub use tower_sesh_core::reaper::Reaper;
+
+#[cfg(feature = "memory-store-snapshot")]
+mod background;
+#[cfg(feature = "encrypted-store")]
+mod encrypted;
+#[cfg(feature = "file-store")]
+mod file;
+#[cfg(feature = "memory-store")]
+mod reaper;
+#[cfg(feature = "memory-store-snapshot")]
+mod snapshot;
+
+/// A store which keeps sessions in memory.
+///
+/// Sessions are lost when the process exits, and aren't shared between
+/// processes, so `MemoryStore` is best suited to development, testing, and
+/// single-instance deployments. It can also serve as the cache of a
+/// [`CachingStore`].
+///
+/// # Capacity
+///
+/// By default, a `MemoryStore` grows without bound. A maximum capacity can be
+/// set with [`max_capacity`], after which sessions are evicted according to
+/// the store's [`EvictionPolicy`]. Capacity is measured in sessions, unless a
+/// [`weigher`] is set.
+///
+/// [`max_capacity`]: MemoryStore::max_capacity
+/// [`weigher`]: MemoryStore::weigher
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh::store::{EvictionPolicy, MemoryStore};
+///
+/// # type SessionData = ();
+/// let store = MemoryStore::<SessionData>::new()
+///     .max_capacity(10_000)
+///     .eviction_policy(EvictionPolicy::Lru);
+/// # let _: &dyn tower_sesh::store::SessionStore<SessionData> = &store;
+/// ```
 #[cfg(feature = "memory-store")]
 pub struct MemoryStore<T> {
-    map: DashMap<SessionKey, Record<T>>,
+    /// Built on first use, since moka's cache requires bounds on `T` which
+    /// configuring the store doesn't.
+    map: OnceLock<Map<T>>,
+    /// The cache replaced by reconfiguring the store after it was built. Its
+    /// sessions are moved to the new cache when that is built.
+    retired: parking_lot::Mutex<Option<Map<T>>>,
+    index: Arc<Index>,
+    next_version: AtomicU64,
+    hasher: Option<KeyHasher>,
+    clock: Arc<dyn Clock>,
+    capacity: Capacity<T>,
+    counters: Arc<Counters>,
     #[cfg(feature = "test-util")]
     rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
 }
 
+/// The policy used by a [`MemoryStore`] with a maximum capacity to choose
+/// which sessions to evict.
+#[cfg(feature = "memory-store")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvictionPolicy {
+    /// Admits and evicts sessions based on how often and how recently they
+    /// have been used, so that a burst of sessions used only once doesn't
+    /// evict frequently used ones.
+    ///
+    /// See [TinyLFU](https://arxiv.org/abs/1512.00727) for details.
+    #[default]
+    TinyLfu,
+
+    /// Evicts the least recently used session.
+    Lru,
+}
+
+#[cfg(feature = "memory-store")]
+struct Capacity<T> {
+    max: Option<u64>,
+    weigher: Option<Weigher<T>>,
+    policy: EvictionPolicy,
+}
+
+#[cfg(feature = "memory-store")]
+type Weigher<T> = Arc<dyn Fn(&T) -> u32 + Send + Sync>;
+
+#[cfg(feature = "memory-store")]
+type Map<T> = moka::sync::Cache<SessionKey, Record<T>>;
+
+#[cfg(feature = "memory-store")]
+impl<T> Default for Capacity<T> {
+    fn default() -> Self {
+        Capacity {
+            max: None,
+            weigher: None,
+            policy: EvictionPolicy::default(),
+        }
+    }
+}
+
+#[cfg(feature = "memory-store")]
+#[derive(Debug, Default)]
+struct Counters {
+    evicted: AtomicU64,
+    /// Sessions which expired since they were last counted by `purge`.
+    expired: AtomicU64,
+}
+
+/// The index of a `MemoryStore`'s sessions by principal, keyed by storage key.
+#[cfg(feature = "memory-store")]
+#[derive(Debug, Default)]
+struct Index {
+    sessions: DashMap<String, HashSet<SessionKey>>,
+    /// The principal each indexed session is associated with, so that the
+    /// session can be removed from the index when it's deleted.
+    principals: DashMap<SessionKey, String>,
+}
+
+#[cfg(feature = "memory-store")]
+impl Index {
+    fn insert(&self, storage_key: SessionKey, principal: &str) {
+        if let Some(previous) = self
+            .principals
+            .insert(storage_key.clone(), principal.to_owned())
+        {
+            self.remove_from(&previous, &storage_key);
+        }
+        self.sessions
+            .entry(principal.to_owned())
+            .or_default()
+            .insert(storage_key);
+    }
+
+    fn principal(&self, storage_key: &SessionKey) -> Option<String> {
+        self.principals
+            .get(storage_key)
+            .map(|principal| principal.clone())
+    }
+
+    fn remove(&self, storage_key: &SessionKey) {
+        if let Some((_, principal)) = self.principals.remove(storage_key) {
+            self.remove_from(&principal, storage_key);
+        }
+    }
+
+    fn remove_from(&self, principal: &str, storage_key: &SessionKey) {
+        if let Some(mut session_keys) = self.sessions.get_mut(principal) {
+            session_keys.remove(storage_key);
+        }
+        self.sessions
+            .remove_if(principal, |_, session_keys| session_keys.is_empty());
+    }
+
+    /// Removes the principal and all of its sessions from the index, returning
+    /// the storage keys of the sessions.
+    fn remove_principal(&self, principal: &str) -> HashSet<SessionKey> {
+        let Some((_, session_keys)) = self.sessions.remove(principal) else {
+            return HashSet::new();
+        };
+        for storage_key in &session_keys {
+            self.principals.remove(storage_key);
+        }
+        session_keys
+    }
+
+    /// Removes the sessions for which `f` returns `false` from the index.
+    fn retain(&self, mut f: impl FnMut(&SessionKey) -> bool) {
+        self.principals.retain(|storage_key, _| f(storage_key));
+        self.sessions.retain(|_, session_keys| {
+            session_keys.retain(|storage_key| self.principals.contains_key(storage_key));
+            !session_keys.is_empty()
+        });
+    }
+}
+
+/// Expires each entry of a `MemoryStore`'s cache when its session expires.
+#[cfg(feature = "memory-store")]
+struct RecordExpiry {
+    clock: Arc<dyn Clock>,
+}
+
+#[cfg(feature = "memory-store")]
+impl RecordExpiry {
+    fn until<T>(&self, record: &Record<T>) -> Duration {
+        // moka can't schedule an expiration more than about 584 years away,
+        // the range of its nanosecond timer, so cap it at 100 years
+        const MAX: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+        Duration::try_from(record.ttl - self.clock.now())
+            .unwrap_or(Duration::ZERO)
+            .min(MAX)
+    }
+}
+
+#[cfg(feature = "memory-store")]
+impl<T> moka::Expiry<SessionKey, Record<T>> for RecordExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &SessionKey,
+        record: &Record<T>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.until(record))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &SessionKey,
+        record: &Record<T>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(self.until(record))
+    }
+}
+
 #[cfg(feature = "memory-store")]
 impl<T> Default for MemoryStore<T> {
     #[cfg(not(feature = "test-util"))]
     fn default() -> Self {
         MemoryStore {
-            map: DashMap::new(),
+            map: OnceLock::new(),
+            retired: parking_lot::Mutex::new(None),
+            index: Arc::default(),
+            next_version: AtomicU64::new(0),
+            hasher: None,
+            clock: Arc::new(SystemClock),
+            capacity: Capacity::default(),
+            counters: Arc::default(),
         }
     }
 
     #[cfg(feature = "test-util")]
     fn default() -> Self {
         MemoryStore {
-            map: DashMap::new(),
+            map: OnceLock::new(),
+            retired: parking_lot::Mutex::new(None),
+            index: Arc::default(),
+            next_version: AtomicU64::new(0),
+            hasher: None,
+            clock: Arc::new(SystemClock),
+            capacity: Capacity::default(),
+            counters: Arc::default(),
             rng: None,
         }
     }
 }
 
+#[cfg(feature = "memory-store")]
+fn build_map<T>(
+    clock: &Arc<dyn Clock>,
+    capacity: &Capacity<T>,
+    counters: &Arc<Counters>,
+    index: &Arc<Index>,
+) -> Map<T>
+where
+    T: 'static + Send + Sync + Clone,
+{
+    let mut builder = moka::sync::Cache::builder()
+        .expire_after(RecordExpiry {
+            clock: Arc::clone(clock),
+        })
+        .eviction_policy(match capacity.policy {
+            EvictionPolicy::TinyLfu => moka::policy::EvictionPolicy::tiny_lfu(),
+            EvictionPolicy::Lru => moka::policy::EvictionPolicy::lru(),
+        })
+        .eviction_listener({
+            let counters = Arc::clone(counters);
+            let index = Arc::clone(index);
+            move |storage_key, _record, cause| match cause {
+                RemovalCause::Size => {
+                    counters.evicted.fetch_add(1, atomic::Ordering::Relaxed);
+                    // Unlike expired sessions, these aren't found by `purge`
+                    index.remove(&storage_key);
+                }
+                RemovalCause::Expired => {
+                    counters.expired.fetch_add(1, atomic::Ordering::Relaxed);
+                }
+                RemovalCause::Explicit | RemovalCause::Replaced => {}
+            }
+        });
+    if let Some(max) = capacity.max {
+        builder = builder.max_capacity(max);
+    }
+    if let Some(weigher) = &capacity.weigher {
+        let weigher = Arc::clone(weigher);
+        builder = builder.weigher(move |_key, record: &Record<T>| weigher(&record.data));
+    }
+    builder.build()
+}
+
 #[cfg(feature = "memory-store")]
 impl<T> MemoryStore<T> {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Stores each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// See [`KeyHasher`] for details.
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Sets the clock used to check whether sessions have expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self.retire_map();
+        self
+    }
+
+    /// Sets the maximum capacity of the store.
+    ///
+    /// Once the store is full, sessions are evicted according to the store's
+    /// [`EvictionPolicy`] to make room for new ones. If a [`weigher`] is set,
+    /// this is the maximum total weight of all sessions; otherwise, it is the
+    /// maximum number of sessions.
+    ///
+    /// The store may briefly exceed its capacity, since sessions are evicted
+    /// in batches.
+    ///
+    /// Default is unbounded.
+    ///
+    /// [`weigher`]: MemoryStore::weigher
+    pub fn max_capacity(mut self, max_capacity: u64) -> Self {
+        self.capacity.max = Some(max_capacity);
+        self.retire_map();
+        self
+    }
+
+    /// Sets a function which returns the weight of each session, such as the
+    /// approximate size of its data in bytes.
+    ///
+    /// The weight of a session is computed when it is written. It only has an
+    /// effect if [`max_capacity`] is set.
+    ///
+    /// [`max_capacity`]: MemoryStore::max_capacity
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tower_sesh::store::MemoryStore;
+    ///
+    /// // Store up to 64 MiB of session data
+    /// let store = MemoryStore::<String>::new()
+    ///     .max_capacity(64 * 1024 * 1024)
+    ///     .weigher(|data| data.len().try_into().unwrap_or(u32::MAX));
+    /// # let _: &dyn tower_sesh::store::SessionStore<String> = &store;
+    /// ```
+    pub fn weigher(mut self, weigher: impl Fn(&T) -> u32 + Send + Sync + 'static) -> Self {
+        self.capacity.weigher = Some(Arc::new(weigher));
+        self.retire_map();
+        self
+    }
+
+    /// Sets the policy used to choose which sessions to evict once the store
+    /// reaches its [`max_capacity`].
+    ///
+    /// Default is [`EvictionPolicy::TinyLfu`].
+    ///
+    /// [`max_capacity`]: MemoryStore::max_capacity
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.capacity.policy = policy;
+        self.retire_map();
+        self
+    }
+
+    /// Retires the cache built from the previous configuration, if any, so
+    /// that a new one is built from the current configuration on next use.
+    fn retire_map(&mut self) {
+        if let Some(map) = self.map.take() {
+            *self.retired.get_mut() = Some(map);
+        }
+    }
+}
+
+#[cfg(feature = "memory-store")]
+impl<T> MemoryStore<T>
+where
+    T: 'static + Send + Sync + Clone,
+{
+    /// Returns the number of sessions which have been evicted to keep the
+    /// store within its [`max_capacity`].
+    ///
+    /// Sessions which are removed because they expired or were deleted aren't
+    /// counted.
+    ///
+    /// [`max_capacity`]: MemoryStore::max_capacity
+    pub fn eviction_count(&self) -> u64 {
+        self.map().run_pending_tasks();
+        self.counters.evicted.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns the cache, building it on first use.
+    fn map(&self) -> &Map<T> {
+        self.map.get_or_init(|| {
+            let map = build_map(&self.clock, &self.capacity, &self.counters, &self.index);
+            if let Some(retired) = self.retired.lock().take() {
+                for (storage_key, record) in retired.iter() {
+                    map.insert(Arc::unwrap_or_clone(storage_key), record);
+                }
+            }
+            map
+        })
+    }
+
+    /// Returns the key the session identified by `session_key` is stored
+    /// under.
+    fn storage_key<'a>(&self, session_key: &'a SessionKey) -> Cow<'a, SessionKey> {
+        match &self.hasher {
+            Some(hasher) => Cow::Owned(hasher.hash(session_key)),
+            None => Cow::Borrowed(session_key),
+        }
+    }
+
+    /// Removes every expired session, returning the number of sessions
+    /// removed.
+    fn purge(&self) -> u64 {
+        let now = self.clock.now();
+        let mut removed = 0;
+        for (storage_key, record) in self.map().iter() {
+            if record.ttl >= now {
+                continue;
+            }
+
+            // The session may have been updated since it was iterated over
+            let result = self
+                .map()
+                .entry(Arc::unwrap_or_clone(storage_key))
+                .and_compute_with(|entry| match entry {
+                    Some(entry) if entry.value().ttl < now => Op::Remove,
+                    _ => Op::Nop,
+                });
+            removed += u64::from(matches!(result, CompResult::Removed(_)));
+        }
+
+        // Count sessions which the cache expired on its own, too
+        self.map().run_pending_tasks();
+        removed += self.counters.expired.swap(0, atomic::Ordering::Relaxed);
+
+        if removed > 0 {
+            self.index
+                .retain(|storage_key| self.map().contains_key(storage_key));
+        }
+
+        removed
+    }
+
+    /// Returns a version which hasn't been assigned to any record yet.
+    fn next_version(&self) -> Version {
+        Version::from_u64(self.next_version.fetch_add(1, atomic::Ordering::Relaxed))
+    }
+
     #[cfg(not(feature = "test-util"))]
     #[inline]
     fn random<U>(&self) -> U
@@ -87,50 +540,200 @@ where
     T: 'static + Send + Sync + Clone,
 {
     async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
-        let record = Record::new(data.clone(), ttl);
+        let (session_key, _) = self
+            .create_with_version(data, ttl, self.next_version())
+            .await?;
+        Ok(session_key)
+    }
 
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
         // Collision resolution
         // (This is statistically improbable for a sufficiently large session key)
         const MAX_ITERATIONS: usize = 8;
         for _ in 0..MAX_ITERATIONS {
             let session_key = self.random::<SessionKey>();
-            match self.map.entry(session_key.clone()) {
-                dashmap::Entry::Vacant(entry) => {
-                    entry.insert(record);
-                    return Ok(session_key);
-                }
-                dashmap::Entry::Occupied(_) => continue,
+            if let CreateWithKey::Created(version) = self
+                .create_with_key(&session_key, data, ttl, version)
+                .await?
+            {
+                return Ok((session_key, version));
             }
         }
 
         Err(tower_sesh_core::store::Error::max_iterations_reached())
     }
 
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let record = Record::new(data.clone(), ttl).with_version(version);
+        let result = self
+            .map()
+            .entry(self.storage_key(session_key).into_owned())
+            .and_compute_with(|entry| match entry {
+                None => Op::Put(record),
+                Some(_) => Op::Nop,
+            });
+        match result {
+            CompResult::Inserted(_) => Ok(CreateWithKey::Created(Some(version))),
+            _ => Ok(CreateWithKey::Occupied),
+        }
+    }
+
     async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
         let record = self
-            .map
-            .get(session_key)
-            .as_deref()
-            .cloned()
-            .filter(|record| record.ttl >= tower_sesh_core::time::now());
+            .map()
+            .get(&*self.storage_key(session_key))
+            .filter(|record| record.ttl >= self.clock.now());
         Ok(record)
     }
 
     async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
-        let record = Record::new(data.clone(), ttl);
-        self.map.insert(session_key.clone(), record);
+        let record = Record::new(data.clone(), ttl).with_version(self.next_version());
+        self.map()
+            .insert(self.storage_key(session_key).into_owned(), record);
         Ok(())
     }
 
     async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
-        if let Some(mut record) = self.map.get_mut(session_key) {
-            record.ttl = ttl;
-        }
+        let now = self.clock.now();
+        self.map()
+            .entry(self.storage_key(session_key).into_owned())
+            .and_compute_with(|entry| match entry {
+                Some(entry) if entry.value().ttl >= now => {
+                    let mut record = entry.into_value();
+                    record.ttl = ttl;
+                    Op::Put(record)
+                }
+                _ => Op::Nop,
+            });
         Ok(())
     }
 
     async fn delete(&self, session_key: &SessionKey) -> Result<()> {
-        self.map.remove(session_key);
+        let storage_key = self.storage_key(session_key);
+        self.map().invalidate(&*storage_key);
+        self.index.remove(&storage_key);
+        Ok(())
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let now = self.clock.now();
+        let mut result = UpdateIf::Conflict(None);
+        self.map()
+            .entry(self.storage_key(session_key).into_owned())
+            .and_compute_with(|entry| match entry {
+                Some(entry) if entry.value().ttl >= now => {
+                    if entry.value().version != Some(version) {
+                        result = UpdateIf::Conflict(Some(entry.into_value()));
+                        return Op::Nop;
+                    }
+
+                    let new_version = self.next_version();
+                    result = UpdateIf::Updated(Some(new_version));
+                    Op::Put(Record::new(data.clone(), ttl).with_version(new_version))
+                }
+                _ => Op::Nop,
+            });
+        Ok(result)
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let record = Record::new(data.clone(), ttl).with_version(version);
+        self.map()
+            .insert(self.storage_key(session_key).into_owned(), record);
+        Ok(Some(version))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        Ok(self.purge())
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "memory-store")]
+#[async_trait]
+impl<T> SessionIndex for MemoryStore<T>
+where
+    T: 'static + Send + Sync + Clone,
+{
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()> {
+        self.index
+            .insert(self.storage_key(session_key).into_owned(), principal);
+        Ok(())
+    }
+
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>> {
+        Ok(self.index.principal(&self.storage_key(session_key)))
+    }
+
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>> {
+        let Some(session_keys) = self
+            .index
+            .sessions
+            .get(principal)
+            .map(|session_keys| session_keys.clone())
+        else {
+            return Ok(Vec::new());
+        };
+
+        let now = self.clock.now();
+        let mut sessions = Vec::with_capacity(session_keys.len());
+        for session_key in session_keys {
+            match self.map().get(&session_key) {
+                Some(record) if record.ttl >= now => {
+                    sessions.push(IndexedSession::new(
+                        StorageId::new(session_key.encode()),
+                        record.ttl,
+                    ));
+                }
+                _ => self.index.remove(&session_key),
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()> {
+        let Ok(storage_key) = SessionKey::decode(id.as_str()) else {
+            // Not the ID of a session in this store
+            return Ok(());
+        };
+        if self.index.principal(&storage_key).as_deref() == Some(principal) {
+            self.map().invalidate(&storage_key);
+            self.index.remove(&storage_key);
+        }
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, principal: &str) -> Result<()> {
+        for session_key in self.index.remove_principal(principal) {
+            self.map().invalidate(&session_key);
+        }
         Ok(())
     }
 }
@@ -146,6 +749,16 @@ where
     }
 }
 
+/// A store that caches sessions from a slower store in a faster one.
+///
+/// A [`MemoryStore`] with a [`max_capacity`] makes a good cache, since it
+/// evicts sessions instead of growing without bound.
+///
+/// To store sessions under a keyed hash of their session key, configure both
+/// `cache` and `store` with the same [`KeyHasher`].
+///
+/// [`max_capacity`]: MemoryStore::max_capacity
+/// [`KeyHasher`]: tower_sesh_core::key::KeyHasher
 pub struct CachingStore<T, Cache: SessionStore<T>, Store: SessionStore<T>> {
     cache: Cache,
     store: Store,
@@ -189,12 +802,40 @@ where
     T: 'static + Send + Sync,
 {
     async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
-        let session_key = self.store.create(data, ttl).await?;
-        self.cache.update(&session_key, data, ttl).await?;
-
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
         Ok(session_key)
     }
 
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        let (session_key, version) = self.store.create_with_version(data, ttl, version).await?;
+        self.write_through(&session_key, data, ttl, version).await?;
+
+        Ok((session_key, version))
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let result = self
+            .store
+            .create_with_key(session_key, data, ttl, version)
+            .await?;
+        if let CreateWithKey::Created(version) = result {
+            self.write_through(session_key, data, ttl, version).await?;
+        }
+
+        Ok(result)
+    }
+
     async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
         match self.cache.load(session_key).await {
             Ok(Some(record)) => Ok(Some(record)),
@@ -202,10 +843,7 @@ where
                 let record = self.store.load(session_key).await?;
 
                 if let Some(record) = &record {
-                    let _ = self
-                        .cache
-                        .update(session_key, &record.data, record.ttl)
-                        .await;
+                    let _ = self.cache_record(session_key, record).await;
                 }
 
                 Ok(record)
@@ -214,10 +852,8 @@ where
     }
 
     async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
-        let store_fut = self.store.update(session_key, data, ttl);
-        let cache_fut = self.cache.update(session_key, data, ttl);
-
-        futures_util::try_join!(store_fut, cache_fut)?;
+        self.update_with_version(session_key, data, ttl, new_version())
+            .await?;
 
         Ok(())
     }
@@ -239,6 +875,217 @@ where
 
         Ok(())
     }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let new_session_key = self.store.cycle_key(session_key, data, ttl).await?;
+        self.cache.delete(session_key).await?;
+
+        Ok(new_session_key)
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let result = self
+            .store
+            .update_if(session_key, data, ttl, version)
+            .await?;
+
+        match &result {
+            UpdateIf::Updated(Some(new_version)) => {
+                self.cache
+                    .update_with_version(session_key, data, ttl, *new_version)
+                    .await?;
+            }
+            UpdateIf::Conflict(Some(record)) => {
+                self.cache_record(session_key, record).await?;
+            }
+            _ => {
+                self.cache.delete(session_key).await?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let version = self
+            .store
+            .update_with_version(session_key, data, ttl, version)
+            .await?;
+        self.write_through(session_key, data, ttl, version).await?;
+
+        Ok(version)
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let store_fut = self.store.purge_expired();
+        let cache_fut = self.cache.purge_expired();
+
+        // Sessions purged from `cache` are duplicates of those in `store`, so
+        // only the latter are counted.
+        let (removed, _) = futures_util::try_join!(store_fut, cache_fut)?;
+
+        Ok(removed)
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        // Sessions deleted through the index must be evicted from `cache` too,
+        // so `store`'s index can't be returned directly.
+        self.store.as_index().map(|_| self as &dyn SessionIndex)
+    }
+}
+
+/// Indexing is supported if `Store` supports it.
+#[async_trait]
+impl<T, Cache: SessionStore<T>, Store: SessionStore<T>> SessionIndex
+    for CachingStore<T, Cache, Store>
+where
+    T: 'static + Send + Sync,
+{
+    async fn associate(&self, session_key: &SessionKey, principal: &str) -> Result<()> {
+        self.store_index()?
+            .associate(session_key, principal)
+            .await?;
+        if let Some(cache_index) = self.cache.as_index() {
+            cache_index.associate(session_key, principal).await?;
+        }
+        Ok(())
+    }
+
+    async fn principal(&self, session_key: &SessionKey) -> Result<Option<String>> {
+        self.store_index()?.principal(session_key).await
+    }
+
+    async fn sessions(&self, principal: &str) -> Result<Vec<IndexedSession>> {
+        self.store_index()?.sessions(principal).await
+    }
+
+    async fn delete_session(&self, principal: &str, id: &StorageId) -> Result<()> {
+        let index = self.store_index()?;
+
+        // If `cache` has its own index, it can delete the session by ID,
+        // since it is configured with the same hasher as `store`. Otherwise
+        // the ID is only a session key if `store` doesn't hash session keys.
+        match self.cache.as_index() {
+            Some(cache_index) => {
+                let store_fut = index.delete_session(principal, id);
+                let cache_fut = cache_index.delete_session(principal, id);
+
+                futures_util::try_join!(store_fut, cache_fut)?;
+            }
+            None => {
+                index.delete_session(principal, id).await?;
+                if let Ok(session_key) = SessionKey::decode(id.as_str()) {
+                    self.cache.delete(&session_key).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, principal: &str) -> Result<()> {
+        let index = self.store_index()?;
+
+        // If `cache` has its own index, it can evict the principal's sessions
+        // itself. This is necessary if `store` hashes session keys, since the
+        // session keys listed by its index can't be used to address sessions.
+        if let Some(cache_index) = self.cache.as_index() {
+            let store_fut = index.delete_sessions(principal);
+            let cache_fut = cache_index.delete_sessions(principal);
+
+            futures_util::try_join!(store_fut, cache_fut)?;
+
+            return Ok(());
+        }
+
+        let sessions = index.sessions(principal).await?;
+        index.delete_sessions(principal).await?;
+
+        let session_keys = sessions
+            .iter()
+            .filter_map(|session| SessionKey::decode(session.id.as_str()).ok())
+            .collect::<Vec<_>>();
+        futures_util::future::try_join_all(
+            session_keys
+                .iter()
+                .map(|session_key| self.cache.delete(session_key)),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl<T, Cache: SessionStore<T>, Store: SessionStore<T>> CachingStore<T, Cache, Store>
+where
+    T: 'static + Send + Sync,
+{
+    fn store_index(&self) -> Result<&dyn SessionIndex> {
+        self.store.as_index().ok_or_else(|| {
+            tower_sesh_core::store::Error::message(
+                "session store doesn't support indexing sessions by principal",
+            )
+        })
+    }
+
+    /// Copies a record loaded from `store` into `cache`, keeping its version
+    /// so that conditional updates made through the cache are checked against
+    /// the version in `store`.
+    async fn cache_record(&self, session_key: &SessionKey, record: &Record<T>) -> Result<()> {
+        match record.version {
+            Some(version) => {
+                self.cache
+                    .update_with_version(session_key, &record.data, record.ttl, version)
+                    .await?;
+                Ok(())
+            }
+            None => {
+                self.cache
+                    .update(session_key, &record.data, record.ttl)
+                    .await
+            }
+        }
+    }
+
+    /// Writes session data just written to `store` through to `cache`, with
+    /// the version `store` stored it with.
+    async fn write_through(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Option<Version>,
+    ) -> Result<()> {
+        match version {
+            Some(version) => {
+                self.cache
+                    .update_with_version(session_key, data, ttl, version)
+                    .await?;
+                Ok(())
+            }
+            // Without the version assigned by `store`, conditional updates of
+            // a cached copy would conflict, so the session is cached the next
+            // time it's loaded instead
+            None => self.cache.delete(session_key).await,
+        }
+    }
+}
+
+/// Returns a version for session data written through a `CachingStore`.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
 }
 
 #[doc(hidden)]
@@ -254,3 +1101,24 @@ where
         self.store.rng(rng);
     }
 }
+
+/// Flushes the directory containing `path` to disk, so that a rename into it
+/// survives a crash.
+#[cfg(all(unix, any(feature = "file-store", feature = "memory-store-snapshot")))]
+fn sync_parent_dir(path: &std::path::Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms; the rename is
+/// left to the OS to persist.
+#[cfg(all(
+    not(unix),
+    any(feature = "file-store", feature = "memory-store-snapshot")
+))]
+fn sync_parent_dir(_path: &std::path::Path) -> std::io::Result<()> {
+    Ok(())
+}
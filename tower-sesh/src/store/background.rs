@@ -0,0 +1,88 @@
+use std::{
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::MemoryStore;
+
+/// A thread which periodically runs a task against a [`MemoryStore`], until
+/// it is stopped or the store is dropped.
+pub(super) struct Background {
+    shared: Arc<Shared>,
+    thread: thread::JoinHandle<()>,
+}
+
+struct Shared {
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Background {
+    /// Spawns a thread which calls `task` with the store every `interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the OS fails to create a thread.
+    pub(super) fn spawn<T>(
+        name: &str,
+        store: &Arc<MemoryStore<T>>,
+        interval: Duration,
+        mut task: impl FnMut(&MemoryStore<T>) + Send + 'static,
+    ) -> Background
+    where
+        T: 'static + Send + Sync,
+    {
+        let shared = Arc::new(Shared {
+            stopped: Mutex::new(false),
+            condvar: Condvar::new(),
+        });
+        let store = Arc::downgrade(store);
+
+        let thread = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn({
+                let shared = Arc::clone(&shared);
+                move || run(&store, &shared, interval, &mut task)
+            })
+            .unwrap_or_else(|err| panic!("failed to spawn {name} thread: {err}"));
+
+        Background { shared, thread }
+    }
+
+    /// Stops the thread, waiting for a task in progress to finish.
+    pub(super) fn stop(self) {
+        *self.shared.stopped.lock() = true;
+        self.shared.condvar.notify_one();
+
+        // The thread doesn't panic unless the task does, in which case there's
+        // nothing left to stop.
+        let _ = self.thread.join();
+    }
+}
+
+fn run<T>(
+    store: &Weak<MemoryStore<T>>,
+    shared: &Shared,
+    interval: Duration,
+    task: &mut impl FnMut(&MemoryStore<T>),
+) {
+    loop {
+        {
+            let mut stopped = shared.stopped.lock();
+            if !*stopped {
+                shared.condvar.wait_for(&mut stopped, interval);
+            }
+            if *stopped {
+                return;
+            }
+        }
+
+        let Some(store) = store.upgrade() else {
+            return;
+        };
+        task(&store);
+    }
+}
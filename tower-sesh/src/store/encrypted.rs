@@ -0,0 +1,574 @@
+use std::{error::Error as StdError, fmt};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use async_trait::async_trait;
+use rand::{rngs::ThreadRng, Rng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    store::{CreateWithKey, Error, Result, SessionIndex, SessionStoreImpl, UpdateIf, Version},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+/// A store that encrypts session data before passing it to another store.
+///
+/// Session data is serialized with a [`Codec`] ([`MessagePack`] by default),
+/// then encrypted with AES-256-GCM. The wrapped store only ever sees the
+/// resulting [`Sealed`] envelope, so session data can't be read by anyone with
+/// access to the underlying storage.
+///
+/// Each envelope is bound to the session key it was sealed for, so an envelope
+/// copied to another session can't be opened. To seal session data before it
+/// is first written, this store generates session keys itself, and creates
+/// sessions in the wrapped store with [`create_with_key`]. Cycling a session's
+/// key creates the session under the new key before deleting the old one.
+///
+/// # Key rotation
+///
+/// Each envelope records the id of the [`EncryptionKey`] used to seal it. New
+/// data is always sealed with the key passed to [`new`], while keys added with
+/// [`decryption_key`] are only used to open existing envelopes. A session
+/// sealed with an old key is re-encrypted with the current key the next time
+/// it is updated, so an old key can be removed once every session sealed with
+/// it has been updated or has expired.
+///
+/// # Errors
+///
+/// An envelope which can't be opened, because it has been tampered with or was
+/// sealed with an unknown key, results in an error of kind
+/// [`ErrorKind::Serde`]. The middleware treats such a session the same as one
+/// which can't be deserialized.
+///
+/// [`create_with_key`]: tower_sesh_core::store::SessionStoreImpl::create_with_key
+/// [`new`]: EncryptedStore::new
+/// [`decryption_key`]: EncryptedStore::decryption_key
+/// [`ErrorKind::Serde`]: tower_sesh_core::store::ErrorKind::Serde
+///
+/// # Examples
+///
+/// ```
+/// use tower_sesh::store::{EncryptedStore, EncryptionKey, MemoryStore};
+///
+/// # type SessionData = ();
+/// #
+/// fn key_bytes(id: u32) -> [u8; 32] {
+///     // TODO: Where do you get a key?
+/// # [id as u8; 32]
+/// }
+///
+/// let store = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(2, key_bytes(2)))
+///     .decryption_key(EncryptionKey::new(1, key_bytes(1)));
+/// # let _: &dyn tower_sesh::store::SessionStore<SessionData> = &store;
+/// ```
+pub struct EncryptedStore<S, K: Codec = MessagePack> {
+    store: S,
+    keys: Vec<EncryptionKey>,
+    codec: K,
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+}
+
+/// A key used by [`EncryptedStore`] to encrypt session data.
+///
+/// Each key has an id, which is recorded alongside the data it encrypts so
+/// that the key can be found again after keys are rotated.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u32,
+    cipher: Aes256Gcm,
+}
+
+/// Encrypted session data, as stored by the store wrapped by
+/// [`EncryptedStore`].
+///
+/// This serializes as a byte string.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Sealed {
+    bytes: Vec<u8>,
+}
+
+/// Version of the envelope layout:
+///
+/// ```text
+/// +---------+--------+-------+-------------------+
+/// | version | key id | nonce | ciphertext || tag |
+/// +---------+--------+-------+-------------------+
+///      1         4       12
+/// ```
+///
+/// The header (version and key id) and the session key are authenticated as
+/// associated data, so an envelope can't be moved to another session.
+const ENVELOPE_VERSION: u8 = 1;
+const HEADER_LEN: usize = 1 + 4;
+const NONCE_LEN: usize = 12;
+
+impl<S> EncryptedStore<S> {
+    /// Wraps `store`, encrypting session data with `key`.
+    pub fn new(store: S, key: EncryptionKey) -> EncryptedStore<S> {
+        EncryptedStore {
+            store,
+            keys: vec![key],
+            codec: MessagePack,
+            #[cfg(feature = "test-util")]
+            rng: None,
+        }
+    }
+}
+
+impl<S, K: Codec> EncryptedStore<S, K> {
+    /// Adds a key which is only used to decrypt session data, such as a key
+    /// which is being rotated out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key with the same id has already been added.
+    #[track_caller]
+    pub fn decryption_key(mut self, key: EncryptionKey) -> EncryptedStore<S, K> {
+        assert!(
+            self.keys.iter().all(|k| k.id != key.id),
+            "duplicate encryption key id {}",
+            key.id
+        );
+        self.keys.push(key);
+        self
+    }
+
+    /// Set the [`Codec`] used to serialize session data before it is
+    /// encrypted.
+    ///
+    /// Default is [`MessagePack`].
+    pub fn codec<K2: Codec>(self, codec: K2) -> EncryptedStore<S, K2> {
+        EncryptedStore {
+            store: self.store,
+            keys: self.keys,
+            codec,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+        }
+    }
+
+    /// Returns a reference to the wrapped store.
+    pub fn get_ref(&self) -> &S {
+        &self.store
+    }
+
+    /// Consumes `self`, returning the wrapped store.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn seal(&self, plaintext: &[u8], session_key: &SessionKey) -> Result<Sealed> {
+        let key = &self.keys[0];
+
+        let mut nonce = [0; NONCE_LEN];
+        ThreadRng::default().fill_bytes(&mut nonce);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + NONCE_LEN + plaintext.len() + 16);
+        bytes.push(ENVELOPE_VERSION);
+        bytes.extend_from_slice(&key.id.to_be_bytes());
+        let ciphertext = key
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad(&bytes, session_key),
+                },
+            )
+            .map_err(|_| Error::serde(SealError::Encrypt))?;
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+
+        Ok(Sealed { bytes })
+    }
+
+    fn open<T>(&self, sealed: &Sealed, session_key: &SessionKey) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let bytes = &sealed.bytes;
+        if bytes.len() < HEADER_LEN + NONCE_LEN || bytes[0] != ENVELOPE_VERSION {
+            return Err(Error::serde(SealError::Malformed));
+        }
+        let (header, rest) = bytes.split_at(HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let key_id = sealed.key_id().expect("header was checked");
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id == key_id)
+            .ok_or_else(|| Error::serde(SealError::UnknownKey(key_id)))?;
+        let plaintext = key
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad(header, session_key),
+                },
+            )
+            .map_err(|_| Error::serde(SealError::Decrypt))?;
+
+        self.codec.decode(&plaintext)
+    }
+
+    fn open_record<T>(&self, record: Record<Sealed>, session_key: &SessionKey) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.open(&record.data, session_key)?;
+        let opened = Record::new(data, record.ttl);
+        Ok(match record.version {
+            Some(version) => opened.with_version(version),
+            None => opened,
+        })
+    }
+}
+
+impl<S, K: Codec> EncryptedStore<S, K> {
+    /// Seals the session data under a newly generated session key, and
+    /// creates the session in the wrapped store, retrying if the key is taken.
+    /// Returns the session key and the version reported by the wrapped store.
+    async fn create_sealed(
+        &self,
+        plaintext: &[u8],
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)>
+    where
+        S: SessionStore<Sealed>,
+    {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            let sealed = self.seal(plaintext, &session_key)?;
+            if let CreateWithKey::Created(version) = self
+                .store
+                .create_with_key(&session_key, &sealed, ttl, version)
+                .await?
+            {
+                return Ok((session_key, version));
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+}
+
+impl<S, K: Codec> fmt::Debug for EncryptedStore<S, K>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("store", &self.store)
+            .field("keys", &self.keys)
+            .field("codec", &std::any::type_name::<K>())
+            .finish()
+    }
+}
+
+impl<T, S, K: Codec> SessionStore<T> for EncryptedStore<S, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+    S: SessionStore<Sealed>,
+{
+}
+
+#[async_trait]
+impl<T, S, K: Codec> SessionStoreImpl<T> for EncryptedStore<S, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+    S: SessionStore<Sealed>,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let plaintext = self.codec.encode(data)?;
+        let (session_key, _) = self.create_sealed(&plaintext, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        let plaintext = self.codec.encode(data)?;
+        self.create_sealed(&plaintext, ttl, version).await
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let sealed = self.seal(&self.codec.encode(data)?, session_key)?;
+        self.store
+            .create_with_key(session_key, &sealed, ttl, version)
+            .await
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        match self.store.load(session_key).await? {
+            Some(record) => self.open_record(record, session_key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        let sealed = self.seal(&self.codec.encode(data)?, session_key)?;
+        self.store.update(session_key, &sealed, ttl).await
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        self.store.update_ttl(session_key, ttl).await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        self.store.delete(session_key).await
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let plaintext = self.codec.encode(data)?;
+        let (new_session_key, _) = self.create_sealed(&plaintext, ttl, new_version()).await?;
+        self.store.delete(session_key).await?;
+        Ok(new_session_key)
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let sealed = self.seal(&self.codec.encode(data)?, session_key)?;
+        match self
+            .store
+            .update_if(session_key, &sealed, ttl, version)
+            .await?
+        {
+            UpdateIf::Updated(version) => Ok(UpdateIf::Updated(version)),
+            UpdateIf::Conflict(Some(record)) => self
+                .open_record(record, session_key)
+                .map(|r| UpdateIf::Conflict(Some(r))),
+            _ => Ok(UpdateIf::Conflict(None)),
+        }
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let sealed = self.seal(&self.codec.encode(data)?, session_key)?;
+        self.store
+            .update_with_version(session_key, &sealed, ttl, version)
+            .await
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        self.store.purge_expired().await
+    }
+
+    fn as_index(&self) -> Option<&dyn SessionIndex> {
+        self.store.as_index()
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<S, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for EncryptedStore<S, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+/// Returns a version for newly created session data.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+impl EncryptionKey {
+    /// Creates a key with the given id from 256 bits of key material.
+    ///
+    /// The key material should be generated by a cryptographically secure
+    /// random number generator.
+    pub fn new(id: u32, key: [u8; 32]) -> EncryptionKey {
+        EncryptionKey {
+            id,
+            cipher: Aes256Gcm::new(&key.into()),
+        }
+    }
+
+    /// Generates a random key with the given id.
+    pub fn generate(id: u32) -> EncryptionKey {
+        let mut key = [0; 32];
+        ThreadRng::default().fill_bytes(&mut key);
+        EncryptionKey::new(id, key)
+    }
+
+    /// Returns the id of this key.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sealed {
+    /// Creates an envelope from bytes previously returned by
+    /// [`as_bytes`](Sealed::as_bytes) or [`into_bytes`](Sealed::into_bytes).
+    pub fn from_bytes(bytes: Vec<u8>) -> Sealed {
+        Sealed { bytes }
+    }
+
+    /// Returns the bytes of the envelope.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consumes `self`, returning the bytes of the envelope.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the id of the key this envelope was sealed with, or `None` if
+    /// the envelope is malformed.
+    pub fn key_id(&self) -> Option<u32> {
+        match self.bytes.get(..HEADER_LEN)? {
+            &[ENVELOPE_VERSION, a, b, c, d] => Some(u32::from_be_bytes([a, b, c, d])),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Sealed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sealed")
+            .field("key_id", &self.key_id())
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl Serialize for Sealed {
+    fn serialize<Ser>(&self, serializer: Ser) -> std::result::Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sealed {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Sealed;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a byte string")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Sealed, E> {
+                Ok(Sealed::from_bytes(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Sealed, E> {
+                Ok(Sealed::from_bytes(v))
+            }
+
+            // Formats without a byte string type, such as JSON, serialize
+            // bytes as a sequence.
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Sealed, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(Sealed::from_bytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+/// Returns the associated data authenticated along with an envelope: its
+/// header, followed by the session key it was sealed for.
+fn aad(header: &[u8], session_key: &SessionKey) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(HEADER_LEN + SessionKey::ENCODED_LEN);
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(session_key.encode().as_bytes());
+    aad
+}
+
+#[derive(Debug)]
+enum SealError {
+    Encrypt,
+    Malformed,
+    UnknownKey(u32),
+    Decrypt,
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SealError::Encrypt => f.write_str("failed to encrypt session data"),
+            SealError::Malformed => f.write_str("malformed encrypted session data"),
+            SealError::UnknownKey(id) => {
+                write!(f, "session data was encrypted with unknown key {id}")
+            }
+            SealError::Decrypt => f.write_str("failed to decrypt session data"),
+        }
+    }
+}
+
+impl StdError for SealError {}
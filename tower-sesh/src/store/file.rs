@@ -0,0 +1,735 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use fs4::fs_std::FileExt;
+use rand::{rngs::ThreadRng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    key::KeyHasher,
+    store::{CreateWithKey, Error, Result, SessionStoreImpl, UpdateIf, Version},
+    time::{Clock, SystemClock},
+    Record, SessionKey, SessionStore, Ttl,
+};
+
+use super::{sync_parent_dir, Reaper};
+
+/// A store which keeps each session in its own file.
+///
+/// Sessions are stored in a directory, in files named after their
+/// Base64-encoded session key (or its keyed hash, see [`hash_keys`]). Each
+/// file starts with a header recording the session's expiry and version,
+/// followed by the session data serialized with a [`Codec`] ([`MessagePack`]
+/// by default).
+///
+/// `FileStore` is suited to small, self-hosted deployments which need
+/// sessions to survive a restart without running a database.
+///
+/// [`hash_keys`]: FileStore::hash_keys
+///
+/// # Sharing a directory
+///
+/// Several processes may use the same directory at once, as long as they are
+/// configured with the same codec and [`KeyHasher`]. Files are replaced
+/// atomically, by writing to a temporary file which is then renamed, so
+/// sessions can be loaded without locking. Changes to sessions are serialized
+/// by an advisory lock on the `.lock` file in the directory.
+///
+/// Advisory locks are unreliable on some network file systems, such as NFS,
+/// so the directory should be on a local file system. Session keys are
+/// case-sensitive, so the file system must be case-sensitive as well.
+///
+/// # Expired sessions
+///
+/// Expired sessions are never loaded, but their files aren't removed until
+/// [`purge_expired`] is called. Call it periodically, or let a [`Reaper`]
+/// spawned by [`spawn_reaper`] do so.
+///
+/// [`purge_expired`]: SessionStoreImpl::purge_expired
+/// [`spawn_reaper`]: FileStore::spawn_reaper
+///
+/// # Examples
+///
+/// ```no_run
+/// use tower_sesh::store::FileStore;
+///
+/// # type SessionData = ();
+/// # fn main() -> std::io::Result<()> {
+/// let store = FileStore::<SessionData>::open("/var/lib/my-app/sessions")?;
+/// # let _: &dyn tower_sesh::store::SessionStore<SessionData> = &store;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FileStore<T, K: Codec = MessagePack> {
+    dir: Arc<Path>,
+    hasher: Option<KeyHasher>,
+    codec: K,
+    clock: Arc<dyn Clock>,
+    #[cfg(feature = "test-util")]
+    rng: Option<Box<parking_lot::Mutex<dyn rand::CryptoRng + Send + 'static>>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// Layout of a session file:
+///
+/// ```text
+/// +-------+----------------+----------------+------------------+---------+------+
+/// | magic | format version | expiry seconds | expiry nanosecs  | version | data |
+/// +-------+----------------+----------------+------------------+---------+------+
+///     4           1                8                 4              8
+/// ```
+///
+/// The expiry is a Unix timestamp. All integers are big-endian.
+const MAGIC: &[u8; 4] = b"TSSF";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 8 + 4 + 8;
+
+/// The name of the file locked while sessions are changed.
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// How old a temporary file must be before [`purge_expired`] assumes it was
+/// left behind by an interrupted write, and removes it.
+///
+/// [`purge_expired`]: SessionStoreImpl::purge_expired
+const STALE_TMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    ttl: Ttl,
+    version: Version,
+}
+
+impl<T> FileStore<T> {
+    /// Returns a store which keeps sessions in the directory at `dir`,
+    /// creating it if it doesn't exist.
+    ///
+    /// On Unix, a directory created by this function is only accessible by
+    /// its owner.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory can't be created.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<FileStore<T>> {
+        let dir = dir.into();
+
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+
+        Ok(FileStore {
+            dir: dir.into(),
+            hasher: None,
+            codec: MessagePack,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "test-util")]
+            rng: None,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T, K: Codec> FileStore<T, K> {
+    /// Stores each session under a keyed hash of its session key, rather than
+    /// the session key itself.
+    ///
+    /// Files are then named after the Base64-encoded hash, so the session keys
+    /// held by clients can't be recovered from the directory. See
+    /// [`KeyHasher`] for details.
+    pub fn hash_keys(mut self, hasher: KeyHasher) -> FileStore<T, K> {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Sets the [`Codec`] used to serialize session data.
+    ///
+    /// Note that changing the codec of an existing directory will make
+    /// previously stored sessions unreadable.
+    ///
+    /// Default is [`MessagePack`].
+    pub fn codec<K2: Codec>(self, codec: K2) -> FileStore<T, K2> {
+        FileStore {
+            dir: self.dir,
+            hasher: self.hasher,
+            codec,
+            clock: self.clock,
+            #[cfg(feature = "test-util")]
+            rng: self.rng,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the clock used to check whether sessions have expired.
+    ///
+    /// Default is [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock) -> FileStore<T, K> {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the directory sessions are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Spawns a background task which removes expired session files from the
+    /// directory every `interval`, as [`purge_expired`] does.
+    ///
+    /// The number of sessions removed by each sweep is logged at the debug
+    /// level, and the running total is available from [`Reaper::removed`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, or if the OS fails to create a thread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    /// use tower_sesh::store::FileStore;
+    ///
+    /// # type SessionData = ();
+    /// # fn main() -> std::io::Result<()> {
+    /// let store = Arc::new(FileStore::<SessionData>::open("/var/lib/my-app/sessions")?);
+    /// let reaper = store.spawn_reaper(Duration::from_secs(60 * 60));
+    ///
+    /// // ...
+    ///
+    /// reaper.shutdown();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`purge_expired`]: SessionStoreImpl::purge_expired
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> Reaper
+    where
+        T: 'static,
+    {
+        Reaper::spawn(self, interval, |store| {
+            purge(&store.dir, store.clock.now()).map_err(Error::store)
+        })
+    }
+
+    /// Returns the path of the file the session identified by `session_key`
+    /// is stored in.
+    fn path(&self, session_key: &SessionKey) -> PathBuf {
+        let file_name = match &self.hasher {
+            Some(hasher) => hasher.hash(session_key).encode(),
+            None => session_key.encode(),
+        };
+        self.dir.join(file_name)
+    }
+
+    fn serialize(&self, data: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        self.codec.encode(data)
+    }
+
+    fn to_record(&self, header: Header, payload: &[u8]) -> Result<Record<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let data = self.codec.decode(payload)?;
+        Ok(Record::new(data, header.ttl).with_version(header.version))
+    }
+
+    /// Runs `f` on a thread pool with exclusive access to the directory.
+    async fn locked<U, F>(&self, f: F) -> Result<U>
+    where
+        U: Send + 'static,
+        F: FnOnce() -> io::Result<U> + Send + 'static,
+    {
+        let dir = Arc::clone(&self.dir);
+        blocking::unblock(move || {
+            let _lock = lock(&dir)?;
+            f()
+        })
+        .await
+        .map_err(Error::store)
+    }
+
+    /// Writes a session to a new file under a randomly generated session key,
+    /// retrying if the key is taken, and returns the session key.
+    ///
+    /// `f` is called with exclusive access to the directory once the file is
+    /// written.
+    async fn create_file<F>(&self, header: Header, payload: Arc<[u8]>, f: F) -> Result<SessionKey>
+    where
+        F: Fn() -> io::Result<()> + Clone + Send + 'static,
+    {
+        // Collision resolution
+        // (This is statistically improbable for a sufficiently large session key)
+        const MAX_RETRIES: usize = 8;
+        for _ in 0..MAX_RETRIES {
+            let session_key = self.random::<SessionKey>();
+            let created = self
+                .create_file_at(&session_key, header, Arc::clone(&payload), f.clone())
+                .await?;
+
+            if created {
+                return Ok(session_key);
+            }
+        }
+
+        Err(Error::max_iterations_reached())
+    }
+
+    /// Writes a session to a new file under `session_key`, returning `false`
+    /// without writing anything if the file already exists.
+    ///
+    /// `f` is called with exclusive access to the directory once the file is
+    /// written.
+    async fn create_file_at<F>(
+        &self,
+        session_key: &SessionKey,
+        header: Header,
+        payload: Arc<[u8]>,
+        f: F,
+    ) -> Result<bool>
+    where
+        F: FnOnce() -> io::Result<()> + Send + 'static,
+    {
+        let path = self.path(session_key);
+        self.locked(move || {
+            if path.symlink_metadata().is_ok() {
+                return Ok(false);
+            }
+            write_atomically(&path, header, &payload)?;
+            f()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    #[inline]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        ThreadRng::default().random()
+    }
+
+    #[cfg(feature = "test-util")]
+    fn random<U>(&self) -> U
+    where
+        rand::distr::StandardUniform: rand::distr::Distribution<U>,
+    {
+        if let Some(rng) = &self.rng {
+            rng.lock().random()
+        } else {
+            ThreadRng::default().random()
+        }
+    }
+}
+
+impl<T, K: Codec> fmt::Debug for FileStore<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileStore")
+            .field("dir", &self.dir)
+            .field("hasher", &self.hasher)
+            .field("codec", &std::any::type_name::<K>())
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+impl<T, K: Codec> SessionStore<T> for FileStore<T, K> where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned
+{
+}
+
+#[async_trait]
+impl<T, K: Codec> SessionStoreImpl<T> for FileStore<T, K>
+where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let (session_key, _) = self.create_with_version(data, ttl, new_version()).await?;
+        Ok(session_key)
+    }
+
+    async fn create_with_version(
+        &self,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<(SessionKey, Option<Version>)> {
+        let header = Header { ttl, version };
+        let payload = self.serialize(data)?.into();
+
+        let session_key = self.create_file(header, payload, || Ok(())).await?;
+        Ok((session_key, Some(version)))
+    }
+
+    async fn create_with_key(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<CreateWithKey> {
+        let header = Header { ttl, version };
+        let payload = self.serialize(data)?.into();
+
+        if self
+            .create_file_at(session_key, header, payload, || Ok(()))
+            .await?
+        {
+            Ok(CreateWithKey::Created(Some(version)))
+        } else {
+            Ok(CreateWithKey::Occupied)
+        }
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let path = self.path(session_key);
+        let now = self.clock.now();
+
+        let file = blocking::unblock(move || read_file(&path))
+            .await
+            .map_err(Error::store)?;
+
+        match file {
+            Some((header, payload)) if header.ttl >= now => {
+                self.to_record(header, &payload).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.update_with_version(session_key, data, ttl, new_version())
+            .await?;
+        Ok(())
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: Ttl) -> Result<()> {
+        let path = self.path(session_key);
+        let now = self.clock.now();
+
+        self.locked(move || match read_file(&path)? {
+            Some((header, payload)) if header.ttl >= now => {
+                write_atomically(&path, Header { ttl, ..header }, &payload)
+            }
+            _ => Ok(()),
+        })
+        .await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        let path = self.path(session_key);
+
+        self.locked(move || remove_file(&path)).await
+    }
+
+    async fn cycle_key(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let header = Header {
+            ttl,
+            version: new_version(),
+        };
+        let payload = self.serialize(data)?.into();
+        let old_path = self.path(session_key);
+
+        self.create_file(header, payload, move || remove_file(&old_path))
+            .await
+    }
+
+    async fn update_if(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<UpdateIf<T>> {
+        let path = self.path(session_key);
+        let now = self.clock.now();
+        let new_header = Header {
+            ttl,
+            version: new_version(),
+        };
+        let payload = self.serialize(data)?;
+
+        let current = self
+            .locked(move || match read_file(&path)? {
+                Some((header, _)) if header.ttl >= now && header.version == version => {
+                    write_atomically(&path, new_header, &payload).map(|()| None)
+                }
+                Some((header, current)) if header.ttl >= now => Ok(Some(Some((header, current)))),
+                _ => Ok(Some(None)),
+            })
+            .await?;
+
+        match current {
+            None => Ok(UpdateIf::Updated(Some(new_header.version))),
+            Some(None) => Ok(UpdateIf::Conflict(None)),
+            Some(Some((header, current))) => self
+                .to_record(header, &current)
+                .map(|record| UpdateIf::Conflict(Some(record))),
+        }
+    }
+
+    async fn update_with_version(
+        &self,
+        session_key: &SessionKey,
+        data: &T,
+        ttl: Ttl,
+        version: Version,
+    ) -> Result<Option<Version>> {
+        let path = self.path(session_key);
+        let payload = self.serialize(data)?;
+
+        self.locked(move || write_atomically(&path, Header { ttl, version }, &payload))
+            .await?;
+        Ok(Some(version))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let dir = Arc::clone(&self.dir);
+        let now = self.clock.now();
+
+        blocking::unblock(move || purge(&dir, now))
+            .await
+            .map_err(Error::store)
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "test-util")]
+impl<T, K: Codec, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for FileStore<T, K>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.rng = Some(Box::new(parking_lot::Mutex::new(rng)));
+    }
+}
+
+/// Returns a version for newly written session data.
+///
+/// Versions are random rather than sequential, since they must be unique
+/// across every process sharing the directory.
+fn new_version() -> Version {
+    Version::from_u64(ThreadRng::default().random())
+}
+
+/// Takes the advisory lock on the directory, which is held until the returned
+/// file is dropped.
+fn lock(dir: &Path) -> io::Result<File> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE_NAME))?;
+    FileExt::lock_exclusive(&file)?;
+    Ok(file)
+}
+
+/// Removes every expired session file in `dir`, along with temporary files
+/// left behind by interrupted writes. Returns the number of sessions removed.
+fn purge(dir: &Path, now: Ttl) -> io::Result<u64> {
+    let mut purged = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        if file_name.starts_with('.') {
+            if file_name.ends_with(".tmp") && is_stale(&entry)? {
+                remove_file(&entry.path())?;
+            }
+            continue;
+        }
+        if SessionKey::decode(file_name).is_err() {
+            continue;
+        }
+
+        let path = entry.path();
+        if !matches!(read_header(&path), Ok(Some(header)) if header.ttl < now) {
+            continue;
+        }
+
+        // Check again while holding the lock, in case the session was updated
+        // in the meantime
+        let _lock = lock(dir)?;
+        if let Ok(Some(header)) = read_header(&path) {
+            if header.ttl < now {
+                remove_file(&path)?;
+                purged += 1;
+            }
+        }
+    }
+
+    Ok(purged)
+}
+
+fn is_stale(entry: &fs::DirEntry) -> io::Result<bool> {
+    let modified = entry.metadata()?.modified()?;
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age >= STALE_TMP_FILE_AGE))
+}
+
+/// Reads the header of the session file at `path`, if it exists.
+fn read_header(path: &Path) -> io::Result<Option<Header>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut bytes = [0; HEADER_LEN];
+    file.read_exact(&mut bytes)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => invalid_data("session file is truncated"),
+            _ => err,
+        })?;
+    decode_header(&bytes).map(Some)
+}
+
+/// Reads the header and payload of the session file at `path`, if it exists.
+fn read_file(path: &Path) -> io::Result<Option<(Header, Vec<u8>)>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if bytes.len() < HEADER_LEN {
+        return Err(invalid_data("session file is truncated"));
+    }
+    let header = decode_header(&bytes[..HEADER_LEN])?;
+    Ok(Some((header, bytes[HEADER_LEN..].to_vec())))
+}
+
+fn decode_header(bytes: &[u8]) -> io::Result<Header> {
+    let (magic, rest) = bytes.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(invalid_data("not a session file"));
+    }
+    let (&format_version, rest) = rest.split_first().expect("header is long enough");
+    if format_version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported session file format version {format_version}"
+        )));
+    }
+
+    let (seconds, rest) = rest.split_at(8);
+    let (nanoseconds, version) = rest.split_at(4);
+    let seconds = i64::from_be_bytes(seconds.try_into().unwrap());
+    let nanoseconds = u32::from_be_bytes(nanoseconds.try_into().unwrap());
+    let version = u64::from_be_bytes(version.try_into().unwrap());
+
+    let ttl = Ttl::from_unix_timestamp(seconds)
+        .and_then(|ttl| ttl.replace_nanosecond(nanoseconds))
+        .map_err(invalid_data)?;
+
+    Ok(Header {
+        ttl,
+        version: Version::from_u64(version),
+    })
+}
+
+fn encode_header(header: Header) -> [u8; HEADER_LEN] {
+    let mut bytes = [0; HEADER_LEN];
+    let mut rest = &mut bytes[..];
+    rest.write_all(MAGIC).unwrap();
+    rest.write_all(&[FORMAT_VERSION]).unwrap();
+    rest.write_all(&header.ttl.unix_timestamp().to_be_bytes())
+        .unwrap();
+    rest.write_all(&header.ttl.nanosecond().to_be_bytes())
+        .unwrap();
+    rest.write_all(&header.version.as_u64().to_be_bytes())
+        .unwrap();
+    bytes
+}
+
+/// Writes a session file to a temporary file next to `path`, then renames it
+/// to `path` and flushes the directory, so the new file survives a crash.
+fn write_atomically(path: &Path, header: Header, payload: &[u8]) -> io::Result<()> {
+    let mut tmp_file_name = std::ffi::OsString::from(".");
+    tmp_file_name.push(path.file_name().expect("session file has a name"));
+    tmp_file_name.push(format!(
+        ".{:016x}.tmp",
+        ThreadRng::default().random::<u64>()
+    ));
+    let tmp_path = path.with_file_name(tmp_file_name);
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(&encode_header(header))?;
+        file.write_all(payload)?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    sync_parent_dir(path)
+}
+
+/// Removes the file at `path`, if it exists.
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header {
+            ttl: Ttl::from_unix_timestamp(1_700_000_000)
+                .unwrap()
+                .replace_nanosecond(999_999_999)
+                .unwrap(),
+            version: Version::from_u64(u64::MAX),
+        };
+
+        let decoded = decode_header(&encode_header(header)).unwrap();
+        assert_eq!(decoded.ttl, header.ttl);
+        assert_eq!(decoded.version, header.version);
+    }
+
+    #[test]
+    fn header_rejects_unknown_format() {
+        let mut bytes = encode_header(Header {
+            ttl: Ttl::UNIX_EPOCH,
+            version: Version::from_u64(0),
+        });
+        bytes[MAGIC.len()] = FORMAT_VERSION + 1;
+
+        let err = decode_header(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
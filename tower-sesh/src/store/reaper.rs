@@ -0,0 +1,37 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{MemoryStore, Reaper};
+
+impl<T> MemoryStore<T>
+where
+    T: 'static + Send + Sync + Clone,
+{
+    /// Spawns a background task which removes expired sessions from the store
+    /// every `interval`.
+    ///
+    /// The number of sessions removed by each sweep is logged at the debug
+    /// level, and the running total is available from [`Reaper::removed`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, or if the OS fails to create a thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{sync::Arc, time::Duration};
+    /// use tower_sesh::store::MemoryStore;
+    ///
+    /// # type SessionData = ();
+    /// let store = Arc::new(MemoryStore::<SessionData>::new());
+    /// let reaper = store.spawn_reaper(Duration::from_secs(60));
+    ///
+    /// // ...
+    ///
+    /// let removed = reaper.shutdown();
+    /// # assert_eq!(removed, 0);
+    /// ```
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> Reaper {
+        Reaper::spawn(self, interval, |store| Ok(store.purge()))
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    error::Error as StdError,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use rand::{rngs::ThreadRng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sesh_core::{
+    codec::{Codec, MessagePack},
+    util::Report,
+    Record, SessionKey, Ttl,
+};
+
+use super::{background::Background, sync_parent_dir, MemoryStore};
+
+/// The version of the snapshot format, which is bumped whenever the format
+/// changes incompatibly.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// A session in a snapshot: its storage key, data, and expiry as a Unix
+/// timestamp and nanoseconds.
+type SnapshotRecord<D> = (String, D, i64, u32);
+
+/// A principal in a snapshot, with the storage keys of its sessions.
+type SnapshotPrincipal = (String, Vec<String>);
+
+/// A handle to a background task which periodically saves a snapshot of a
+/// [`MemoryStore`] to disk.
+///
+/// Like [`Reaper`], the task runs on its own thread until [`shutdown`] is
+/// called, or until every `Arc` referencing the store has been dropped.
+///
+/// Created by [`MemoryStore::spawn_snapshots`].
+///
+/// [`Reaper`]: super::Reaper
+/// [`shutdown`]: Snapshots::shutdown
+pub struct Snapshots {
+    background: Background,
+    save: Arc<dyn Fn() -> io::Result<Option<u64>> + Send + Sync>,
+    path: PathBuf,
+}
+
+impl<T> MemoryStore<T>
+where
+    T: 'static + Send + Sync + Clone + Serialize,
+{
+    /// Saves every unexpired session in the store to a file at `path`,
+    /// returning the number of sessions saved.
+    ///
+    /// The snapshot records each session's key, data, and expiry, along with
+    /// the principals set with [`Session::set_principal`]. If the store hashes
+    /// session keys, only the hashed keys are saved. Session data is encoded
+    /// with [`MessagePack`].
+    ///
+    /// The snapshot is written to a temporary file which then replaces the
+    /// file at `path`, so that a crash while saving never leaves a partially
+    /// written snapshot behind. On Unix, the file is only readable by its
+    /// owner.
+    ///
+    /// Call this after the server shuts down gracefully, and restore the
+    /// snapshot at startup with [`load_snapshot`]. To also save snapshots
+    /// periodically, use [`spawn_snapshots`].
+    ///
+    /// [`Session::set_principal`]: crate::Session::set_principal
+    /// [`load_snapshot`]: MemoryStore::load_snapshot
+    /// [`spawn_snapshots`]: MemoryStore::spawn_snapshots
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if session data can't be serialized, or if the file
+    /// can't be written.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<u64> {
+        let now = self.clock.now();
+        let records = self
+            .map()
+            .iter()
+            .filter(|(_, record)| record.ttl >= now)
+            .map(|(storage_key, record)| {
+                (
+                    storage_key.encode(),
+                    record.data,
+                    record.ttl.unix_timestamp(),
+                    record.ttl.nanosecond(),
+                )
+            })
+            .collect::<Vec<SnapshotRecord<T>>>();
+        let principals = self
+            .index
+            .sessions
+            .iter()
+            .map(|entry| {
+                let storage_keys = entry.value().iter().map(SessionKey::encode).collect();
+                (entry.key().clone(), storage_keys)
+            })
+            .collect::<Vec<SnapshotPrincipal>>();
+
+        let bytes = MessagePack
+            .encode(&(SNAPSHOT_VERSION, &records, &principals))
+            .map_err(invalid_data)?;
+        write_atomically(path.as_ref(), &bytes)?;
+
+        Ok(records.len() as u64)
+    }
+
+    /// Spawns a background task which saves a snapshot of the store to a file
+    /// at `path` every `interval`, in addition to a final snapshot when the
+    /// task is shut down.
+    ///
+    /// Errors encountered while saving periodic snapshots are logged at the
+    /// error level. See [`save_snapshot`] for details on the snapshot.
+    ///
+    /// [`save_snapshot`]: MemoryStore::save_snapshot
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero, or if the OS fails to create a thread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    /// use tower_sesh::store::MemoryStore;
+    ///
+    /// # type SessionData = ();
+    /// # fn main() -> std::io::Result<()> {
+    /// let store = Arc::new(MemoryStore::<SessionData>::new());
+    /// store.load_snapshot("sessions.snapshot")?;
+    /// let snapshots = store.spawn_snapshots("sessions.snapshot", Duration::from_secs(5 * 60));
+    ///
+    /// // Run the server until it shuts down...
+    ///
+    /// snapshots.shutdown()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn_snapshots(
+        self: &Arc<Self>,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> Snapshots {
+        assert!(!interval.is_zero(), "snapshot interval must be non-zero");
+
+        let path = path.into();
+        let save = Arc::new({
+            let store = Arc::downgrade(self);
+            let path = path.clone();
+            move || match store.upgrade() {
+                Some(store) => store.save_snapshot(&path).map(Some),
+                None => Ok(None),
+            }
+        });
+
+        let background = Background::spawn("tower-sesh-snapshots", self, interval, {
+            let path = path.clone();
+            move |store| match store.save_snapshot(&path) {
+                Ok(_saved) => {
+                    debug!(saved = _saved, path = %path.display(), "saved session snapshot");
+                }
+                Err(_err) => {
+                    error!(
+                        err = %Report::new(_err),
+                        path = %path.display(),
+                        "failed to save session snapshot"
+                    );
+                }
+            }
+        });
+
+        Snapshots {
+            background,
+            save,
+            path,
+        }
+    }
+}
+
+impl<T> MemoryStore<T>
+where
+    T: 'static + Send + Sync + Clone + DeserializeOwned,
+{
+    /// Restores sessions from a snapshot saved with [`save_snapshot`],
+    /// returning the number of sessions restored.
+    ///
+    /// Sessions which have expired since the snapshot was saved are skipped,
+    /// as are sessions with a key already in the store. If no file exists at
+    /// `path`, no sessions are restored.
+    ///
+    /// If the store hashes session keys, it must be configured with the same
+    /// [`KeyHasher`] as the store which saved the snapshot.
+    ///
+    /// [`save_snapshot`]: MemoryStore::save_snapshot
+    /// [`KeyHasher`]: tower_sesh_core::key::KeyHasher
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read, or if it isn't a valid
+    /// snapshot. Sessions are only restored if the whole snapshot is valid.
+    pub fn load_snapshot(&self, path: impl AsRef<Path>) -> io::Result<u64> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+
+        let (version, records, principals): (u32, Vec<SnapshotRecord<T>>, Vec<SnapshotPrincipal>) =
+            MessagePack.decode(&bytes).map_err(invalid_data)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {version}"
+            )));
+        }
+
+        let records = records
+            .into_iter()
+            .map(|(storage_key, data, timestamp, nanosecond)| {
+                let storage_key = SessionKey::decode(storage_key).map_err(invalid_data)?;
+                let ttl = Ttl::from_unix_timestamp(timestamp)
+                    .and_then(|ttl| ttl.replace_nanosecond(nanosecond))
+                    .map_err(invalid_data)?;
+                Ok((storage_key, data, ttl))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let now = self.clock.now();
+        let mut restored = 0;
+        for (storage_key, data, ttl) in records {
+            if ttl < now {
+                continue;
+            }
+
+            let record = Record::new(data, ttl).with_version(self.next_version());
+            if self.map().entry(storage_key).or_insert(record).is_fresh() {
+                restored += 1;
+            }
+        }
+
+        for (principal, storage_keys) in principals {
+            let storage_keys = storage_keys
+                .iter()
+                .filter_map(|storage_key| SessionKey::decode(storage_key).ok())
+                .filter(|storage_key| self.map().contains_key(storage_key));
+            for storage_key in storage_keys {
+                self.index.insert(storage_key, &principal);
+            }
+        }
+
+        Ok(restored)
+    }
+}
+
+impl Snapshots {
+    /// Stops saving periodic snapshots, then saves a final snapshot.
+    ///
+    /// If every `Arc` referencing the store has already been dropped, no
+    /// snapshot is saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the final snapshot can't be saved.
+    pub fn shutdown(self) -> io::Result<()> {
+        self.background.stop();
+        (self.save)().map(|_| ())
+    }
+}
+
+impl fmt::Debug for Snapshots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshots")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writes `bytes` to a temporary file next to `path`, then renames it to
+/// `path`.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let Some(file_name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "snapshot path has no file name",
+        ));
+    };
+
+    // Unique, so that snapshots saved concurrently don't clobber each other's
+    // temporary file
+    let mut tmp_file_name = file_name.to_owned();
+    tmp_file_name.push(format!(
+        ".{:016x}.tmp",
+        ThreadRng::default().random::<u64>()
+    ));
+    let tmp_path = path.with_file_name(tmp_file_name);
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    sync_parent_dir(path)
+}
+
+fn invalid_data(err: impl Into<Box<dyn StdError + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,192 @@
+#![cfg(feature = "cookie-store")]
+
+use std::time::Duration;
+
+use axum::{body::Body, routing, Router};
+use cookie::{Cookie, CookieJar};
+use http::{header, Method, Request, Response, StatusCode};
+use tower::ServiceExt;
+use tower_sesh::{
+    cookie_store::CookieSessionLayer,
+    middleware::{Expiry, Key},
+    Session,
+};
+
+fn jar_from_response<B>(res: &Response<B>) -> CookieJar {
+    res.headers().get_all(header::SET_COOKIE).into_iter().fold(
+        CookieJar::new(),
+        |mut jar, header_value| {
+            let cookie = Cookie::parse_encoded(header_value.to_str().unwrap()).unwrap();
+            jar.add(cookie.into_owned());
+            jar
+        },
+    )
+}
+
+fn cookie_header(jar: &CookieJar) -> String {
+    jar.iter()
+        .filter(|cookie| !cookie.value().is_empty())
+        .map(|cookie| cookie.stripped().encoded().to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn app(layer: CookieSessionLayer<String>) -> Router {
+    async fn insert(session: Session<String>, body: String) {
+        session.insert(body);
+    }
+
+    async fn load(session: Session<String>) -> Result<String, StatusCode> {
+        session
+            .get()
+            .as_ref()
+            .cloned()
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+
+    async fn purge(session: Session<String>) {
+        session.purge();
+    }
+
+    Router::new()
+        .route("/insert", routing::post(insert))
+        .route("/load", routing::get(load))
+        .route("/purge", routing::post(purge))
+        .layer(layer.cookie_name("id"))
+}
+
+async fn insert(app: &Router, cookies: Option<&CookieJar>, data: String) -> Response<Body> {
+    let mut req = Request::builder().uri("/insert").method(Method::POST);
+    if let Some(jar) = cookies {
+        req = req.header(header::COOKIE, cookie_header(jar));
+    }
+    app.clone()
+        .oneshot(req.body(Body::from(data)).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn load(app: &Router, cookies: &CookieJar) -> Response<Body> {
+    let req = Request::builder()
+        .uri("/load")
+        .header(header::COOKIE, cookie_header(cookies))
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(req).await.unwrap()
+}
+
+async fn body_string(res: Response<Body>) -> String {
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn create_and_load() {
+    let app = app(CookieSessionLayer::new(Key::from([0; 64])));
+
+    let res = insert(&app, None, "hello".to_owned()).await;
+    let jar = jar_from_response(&res);
+    let cookie = jar.get("id").unwrap();
+    assert!(
+        !cookie.value().contains("hello"),
+        "cookie must be encrypted"
+    );
+
+    let res = load(&app, &jar).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_string(res).await, "hello");
+}
+
+#[tokio::test]
+async fn tampered_cookie_is_rejected() {
+    let app = app(CookieSessionLayer::new(Key::from([0; 64])));
+
+    let res = insert(&app, None, "hello".to_owned()).await;
+    let mut jar = jar_from_response(&res);
+    let mut value = jar.get("id").unwrap().value().to_owned().into_bytes();
+    value[0] = if value[0] == b'A' { b'B' } else { b'A' };
+    jar.add(Cookie::new("id", String::from_utf8(value).unwrap()));
+
+    let res = load(&app, &jar).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn expired_payload_is_rejected() {
+    let app =
+        app(CookieSessionLayer::new(Key::from([0; 64])).expiry(Expiry::Absolute(Duration::ZERO)));
+
+    let res = insert(&app, None, "hello".to_owned()).await;
+    let jar = jar_from_response(&res);
+    assert!(jar.get("id").is_some());
+
+    let res = load(&app, &jar).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn large_payload_is_split_across_cookies() {
+    let app = app(CookieSessionLayer::new(Key::from([0; 64])));
+    let data = "a".repeat(10_000);
+
+    let res = insert(&app, None, data.clone()).await;
+    let jar = jar_from_response(&res);
+    assert!(jar.get("id").is_some());
+    assert!(jar.get("id.1").is_some());
+    assert!(jar.get("id.2").is_some());
+    for cookie in jar.iter() {
+        let encoded = cookie.stripped().encoded().to_string();
+        assert!(encoded.len() <= 4096, "cookie is {} bytes", encoded.len());
+    }
+
+    let res = load(&app, &jar).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_string(res).await, data);
+
+    // Shrinking the payload removes the chunks which are no longer needed
+    let res = insert(&app, Some(&jar), "small".to_owned()).await;
+    let shrunk_jar = jar_from_response(&res);
+    assert!(!shrunk_jar.get("id").unwrap().value().is_empty());
+    assert_eq!(shrunk_jar.get("id.1").unwrap().value(), "");
+    assert_eq!(shrunk_jar.get("id.2").unwrap().value(), "");
+
+    let res = load(&app, &shrunk_jar).await;
+    assert_eq!(body_string(res).await, "small");
+}
+
+#[tokio::test]
+async fn payload_exceeding_limit_is_not_set() {
+    let app = app(CookieSessionLayer::new(Key::from([0; 64])).max_cookies(1));
+
+    let res = insert(&app, None, "a".repeat(10_000)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+}
+
+#[tokio::test]
+async fn purge_removes_all_cookies() {
+    let app = app(CookieSessionLayer::new(Key::from([0; 64])));
+
+    let res = insert(&app, None, "a".repeat(5_000)).await;
+    let jar = jar_from_response(&res);
+    assert!(jar.get("id.1").is_some());
+
+    let req = Request::builder()
+        .uri("/purge")
+        .method(Method::POST)
+        .header(header::COOKIE, cookie_header(&jar))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res);
+    assert_eq!(jar.get("id").unwrap().value(), "");
+    assert_eq!(jar.get("id.1").unwrap().value(), "");
+}
+
+#[test]
+#[should_panic = "`max_cookies` must be non-zero"]
+fn zero_max_cookies() {
+    let _ = CookieSessionLayer::<()>::new(Key::from([0; 64])).max_cookies(0);
+}
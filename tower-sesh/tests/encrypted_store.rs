@@ -0,0 +1,209 @@
+#![cfg(feature = "encrypted-store")]
+
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tower_sesh::store::{EncryptedStore, EncryptionKey, MemoryStore, Sealed, SessionStore};
+use tower_sesh_core::{
+    backend::{BackendStore, SessionBackend},
+    store::{ErrorKind, Result},
+    time::now,
+    Record, SessionKey, Ttl,
+};
+
+fn ttl() -> Ttl {
+    now() + Duration::from_secs(60)
+}
+
+async fn sealed(store: &MemoryStore<Sealed>, session_key: &SessionKey) -> Sealed {
+    let store: &dyn SessionStore<Sealed> = store;
+    store.load(session_key).await.unwrap().unwrap().data
+}
+
+#[tokio::test]
+async fn data_is_encrypted() {
+    let store = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let session_key = dyn_store.create(&"hello".to_owned(), ttl()).await.unwrap();
+    let sealed = sealed(store.get_ref(), &session_key).await;
+    assert_eq!(sealed.key_id(), Some(1));
+    assert!(!sealed
+        .as_bytes()
+        .windows("hello".len())
+        .any(|w| w == b"hello"));
+
+    let record = dyn_store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "hello");
+}
+
+#[tokio::test]
+async fn tampered_ciphertext_is_serde_error() {
+    let store = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let session_key = dyn_store.create(&"hello".to_owned(), ttl()).await.unwrap();
+    let mut bytes = sealed(store.get_ref(), &session_key).await.into_bytes();
+    *bytes.last_mut().unwrap() ^= 1;
+    let inner: &dyn SessionStore<Sealed> = store.get_ref();
+    inner
+        .update(&session_key, &Sealed::from_bytes(bytes), ttl())
+        .await
+        .unwrap();
+
+    let err = dyn_store.load(&session_key).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Serde(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn unknown_key_is_serde_error() {
+    let old = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let session_key = (&old as &dyn SessionStore<String>)
+        .create(&"hello".to_owned(), ttl())
+        .await
+        .unwrap();
+
+    let store = EncryptedStore::new(old.into_inner(), EncryptionKey::new(2, [2; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+    let err = dyn_store.load(&session_key).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Serde(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn envelope_swapped_between_sessions_is_serde_error() {
+    let store = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let alice = dyn_store.create(&"alice".to_owned(), ttl()).await.unwrap();
+    let mallory = dyn_store
+        .create(&"mallory".to_owned(), ttl())
+        .await
+        .unwrap();
+    let sealed = sealed(store.get_ref(), &alice).await;
+    let inner: &dyn SessionStore<Sealed> = store.get_ref();
+    inner.update(&mallory, &sealed, ttl()).await.unwrap();
+
+    let err = dyn_store.load(&mallory).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Serde(_)), "{err:?}");
+    let record = dyn_store.load(&alice).await.unwrap().unwrap();
+    assert_eq!(record.data, "alice");
+}
+
+#[tokio::test]
+async fn cycle_key_seals_for_new_key() {
+    let store = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let session_key = dyn_store.create(&"hello".to_owned(), ttl()).await.unwrap();
+    let new_session_key = dyn_store
+        .cycle_key(&session_key, &"world".to_owned(), ttl())
+        .await
+        .unwrap();
+
+    assert!(dyn_store.load(&session_key).await.unwrap().is_none());
+    let record = dyn_store.load(&new_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "world");
+}
+
+/// A backend which records every envelope written to it.
+#[derive(Default)]
+struct RecordingBackend {
+    map: Mutex<HashMap<SessionKey, Record<Sealed>>>,
+    writes: Mutex<Vec<Sealed>>,
+}
+
+#[async_trait]
+impl SessionBackend<Sealed> for RecordingBackend {
+    async fn create(&self, _data: &Sealed, _ttl: Ttl) -> Result<SessionKey> {
+        unreachable!("session keys are generated by `EncryptedStore`")
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<Sealed>>> {
+        Ok(self.map.lock().get(session_key).cloned())
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &Sealed, ttl: Ttl) -> Result<()> {
+        self.writes.lock().push(data.clone());
+        self.map
+            .lock()
+            .insert(session_key.clone(), Record::new(data.clone(), ttl));
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        self.map.lock().remove(session_key);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn create_and_cycle_key_only_write_sealed_data() {
+    let store = EncryptedStore::new(
+        BackendStore::new(RecordingBackend::default()),
+        EncryptionKey::new(1, [1; 32]),
+    );
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let session_key = dyn_store.create(&"hello".to_owned(), ttl()).await.unwrap();
+    let new_session_key = dyn_store
+        .cycle_key(&session_key, &"world".to_owned(), ttl())
+        .await
+        .unwrap();
+    let record = dyn_store.load(&new_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "world");
+
+    let writes = store.get_ref().get_ref().writes.lock();
+    assert_eq!(writes.len(), 2, "each operation should write once");
+    assert!(writes.iter().all(|sealed| sealed.key_id() == Some(1)));
+}
+
+#[tokio::test]
+async fn rotated_key_decrypts_and_update_reencrypts() {
+    let old = EncryptedStore::new(MemoryStore::new(), EncryptionKey::new(1, [1; 32]));
+    let session_key = (&old as &dyn SessionStore<String>)
+        .create(&"hello".to_owned(), ttl())
+        .await
+        .unwrap();
+
+    let store = EncryptedStore::new(old.into_inner(), EncryptionKey::new(2, [2; 32]))
+        .decryption_key(EncryptionKey::new(1, [1; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+    let record = dyn_store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "hello");
+    assert_eq!(
+        sealed(store.get_ref(), &session_key).await.key_id(),
+        Some(1)
+    );
+
+    dyn_store
+        .update(&session_key, &"world".to_owned(), ttl())
+        .await
+        .unwrap();
+    assert_eq!(
+        sealed(store.get_ref(), &session_key).await.key_id(),
+        Some(2)
+    );
+
+    // The old key is no longer needed
+    let store = EncryptedStore::new(store.into_inner(), EncryptionKey::new(2, [2; 32]));
+    let dyn_store: &dyn SessionStore<String> = &store;
+    let record = dyn_store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "world");
+}
+
+#[test]
+fn sealed_round_trips_through_json() {
+    let sealed = Sealed::from_bytes(vec![1, 0, 0, 0, 7, 42]);
+    let json = serde_json::to_vec(&sealed).unwrap();
+    assert_eq!(serde_json::from_slice::<Sealed>(&json).unwrap(), sealed);
+    let msgpack = rmp_serde::to_vec(&sealed).unwrap();
+    assert_eq!(rmp_serde::from_slice::<Sealed>(&msgpack).unwrap(), sealed);
+}
+
+#[test]
+#[should_panic = "duplicate encryption key id 1"]
+fn duplicate_key_id() {
+    let _ = EncryptedStore::new(MemoryStore::<Sealed>::new(), EncryptionKey::new(1, [1; 32]))
+        .decryption_key(EncryptionKey::new(1, [2; 32]));
+}
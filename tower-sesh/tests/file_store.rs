@@ -0,0 +1,180 @@
+#![cfg(feature = "file-store")]
+
+use std::{
+    fs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tower_sesh::store::{FileStore, SessionStore};
+use tower_sesh_core::{
+    key::KeyHasher,
+    time::{Clock, MockClock},
+    Ttl,
+};
+
+fn ttl(clock: &MockClock) -> Ttl {
+    clock.now() + Duration::from_secs(60)
+}
+
+fn session_files(dir: &tempfile::TempDir) -> Vec<String> {
+    let mut names = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn open_creates_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a/b/sessions");
+
+    let store = FileStore::<()>::open(&path).unwrap();
+    assert!(path.is_dir());
+    assert_eq!(store.dir(), path);
+}
+
+#[tokio::test]
+async fn files_are_named_after_session_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = FileStore::<String>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    let session_key = dyn_store
+        .create(&"hello".to_owned(), ttl(&clock))
+        .await
+        .unwrap();
+    assert_eq!(session_files(&dir), [session_key.encode()]);
+
+    let hasher = KeyHasher::new(&[1; 32]);
+    let hashed = FileStore::<String>::open(dir.path())
+        .unwrap()
+        .hash_keys(hasher.clone())
+        .clock(clock.clone());
+    let dyn_hashed: &dyn SessionStore<String> = &hashed;
+    dyn_hashed
+        .update(&session_key, &"hello".to_owned(), ttl(&clock))
+        .await
+        .unwrap();
+
+    let mut expected = [session_key.encode(), hasher.hash(&session_key).encode()];
+    expected.sort();
+    assert_eq!(session_files(&dir), expected);
+}
+
+#[tokio::test]
+async fn stores_can_share_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let first = FileStore::<String>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let second = FileStore::<String>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_first: &dyn SessionStore<String> = &first;
+    let dyn_second: &dyn SessionStore<String> = &second;
+
+    let session_key = dyn_first
+        .create(&"hello".to_owned(), ttl(&clock))
+        .await
+        .unwrap();
+    let record = dyn_second.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "hello");
+    assert_eq!(record.ttl, ttl(&clock));
+
+    dyn_second.delete(&session_key).await.unwrap();
+    assert!(dyn_first.load(&session_key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn purge_expired_returns_number_of_sessions_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = FileStore::<()>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let long_lived = dyn_store
+        .create(&(), clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+
+    clock.advance(Duration::from_secs(2 * 60));
+
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+    assert_eq!(session_files(&dir), [long_lived.encode()]);
+}
+
+#[tokio::test]
+async fn reaper_removes_expired_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = Arc::new(
+        FileStore::<()>::open(dir.path())
+            .unwrap()
+            .clock(clock.clone()),
+    );
+    let dyn_store: &dyn SessionStore<()> = store.as_ref();
+
+    let reaper = store.spawn_reaper(Duration::from_millis(1));
+
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    clock.advance(Duration::from_secs(2 * 60));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reaper.removed() < 3 {
+        assert!(Instant::now() < deadline, "reaper didn't remove sessions");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(reaper.shutdown(), 3);
+    assert!(session_files(&dir).is_empty());
+}
+
+#[tokio::test]
+async fn purge_expired_ignores_unrelated_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = FileStore::<()>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    fs::write(dir.path().join("README"), "not a session").unwrap();
+    // A fresh temporary file may belong to a write in progress
+    fs::write(dir.path().join(".session.0000000000000000.tmp"), "").unwrap();
+
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+    assert!(dir.path().join("README").exists());
+    assert!(dir.path().join(".session.0000000000000000.tmp").exists());
+}
+
+#[tokio::test]
+async fn load_fails_on_corrupt_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let clock = MockClock::default();
+    let store = FileStore::<()>::open(dir.path())
+        .unwrap()
+        .clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let session_key = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    fs::write(dir.path().join(session_key.encode()), "garbage").unwrap();
+
+    assert!(dyn_store.load(&session_key).await.is_err());
+}
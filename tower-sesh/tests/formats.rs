@@ -8,6 +8,10 @@ use serde_json::{
     value::{from_value, to_value},
     Value,
 };
+use tower_sesh_core::{
+    codec::{Algorithm, Cbor, Codec, Compressed, Json, MessagePack, Versioned},
+    store::ErrorKind,
+};
 
 macro_rules! treemap {
     () => {
@@ -84,9 +88,36 @@ where
             rmp_serde::to_vec_named,
             f!(rmp_serde::from_slice),
         );
+        check_codec(*data, expected, Json);
+        check_codec(*data, expected, MessagePack);
+        check_codec(*data, expected, Cbor);
+        check_codec(*data, expected, Compressed::zstd(MessagePack).threshold(0));
+        check_codec(*data, expected, Compressed::lz4(Json).threshold(0));
+        check_codec(*data, expected, Compressed::zstd(Cbor));
+        check_codec(*data, expected, Versioned::new(MessagePack, 3));
+        check_codec(
+            *data,
+            expected,
+            Versioned::new(Compressed::lz4(Json).threshold(0), 1),
+        );
     }
 }
 
+#[track_caller]
+fn check_codec<T, TOwned, C>(data: &T, expected: &Value, codec: C)
+where
+    T: PartialEq + PartialEq<TOwned> + ToOwned<Owned = TOwned> + Serialize + Debug + ?Sized,
+    TOwned: DeserializeOwned + Debug,
+    C: Codec,
+{
+    check(
+        data,
+        expected,
+        |value| codec.encode(value),
+        f!(|bytes| codec.decode(bytes)),
+    );
+}
+
 #[test]
 fn test_write_null() {
     check_all(&[(&(), Value::Null)]);
@@ -477,3 +508,89 @@ fn test_field_reordering() {
         ]),
     );
 }
+
+#[test]
+fn test_compressed_is_self_describing() {
+    let data = "a".repeat(2048);
+
+    // Payloads which were stored uncompressed still decode
+    let uncompressed = MessagePack.encode(&data).unwrap();
+    let codec = Compressed::zstd(MessagePack);
+    assert_eq!(codec.decode::<String>(&uncompressed).unwrap(), data);
+
+    // Payloads above the threshold are compressed
+    let compressed = codec.encode(&data).unwrap();
+    assert!(compressed.len() < uncompressed.len());
+    assert_eq!(codec.decode::<String>(&compressed).unwrap(), data);
+
+    // Payloads compressed with another algorithm still decode
+    let codec = Compressed::new(MessagePack, Algorithm::Lz4);
+    assert_eq!(codec.decode::<String>(&compressed).unwrap(), data);
+
+    // Payloads below the threshold are left as is
+    let codec = Compressed::zstd(MessagePack).threshold(4096);
+    assert_eq!(codec.encode(&data).unwrap(), uncompressed);
+}
+
+#[test]
+fn test_compressed_limits_decompressed_size() {
+    let data = "a".repeat(4096);
+
+    for codec in [
+        Compressed::zstd(MessagePack),
+        Compressed::new(MessagePack, Algorithm::Lz4),
+    ] {
+        let compressed = codec.encode(&data).unwrap();
+        assert_eq!(codec.decode::<String>(&compressed).unwrap(), data);
+
+        let codec = codec.max_decompressed_size(1024);
+        let err = codec.decode::<String>(&compressed).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Serde(_)), "{err:?}");
+
+        // Payloads above the limit are left uncompressed, so they still decode
+        let uncompressed = codec.encode(&data).unwrap();
+        assert_eq!(uncompressed, MessagePack.encode(&data).unwrap());
+        assert_eq!(codec.decode::<String>(&uncompressed).unwrap(), data);
+    }
+
+    // The decompressed size prepended to LZ4 payloads isn't trusted
+    let mut forged = Compressed::lz4(MessagePack).encode(&data).unwrap();
+    forged[2..6].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = Compressed::lz4(MessagePack)
+        .decode::<String>(&forged)
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Serde(_)), "{err:?}");
+}
+
+#[test]
+fn test_versioned_reports_outdated_schema() {
+    let data = "hello".to_owned();
+    let codec = Versioned::new(MessagePack, 2);
+
+    // Payloads stored before schema versions were recorded have version 1
+    let unversioned = MessagePack.encode(&data).unwrap();
+    let err = codec.decode::<String>(&unversioned).unwrap_err();
+    match err.kind() {
+        ErrorKind::Outdated(outdated) => {
+            assert_eq!(outdated.schema_version(), 1);
+            assert_eq!(outdated.payload(), unversioned);
+        }
+        _ => panic!("expected outdated error, got {err:?}"),
+    }
+    assert_eq!(
+        Versioned::new(MessagePack, 1)
+            .decode::<String>(&unversioned)
+            .unwrap(),
+        data
+    );
+
+    let newer = Versioned::new(MessagePack, 3).encode(&data).unwrap();
+    let err = codec.decode::<String>(&newer).unwrap_err();
+    match err.kind() {
+        ErrorKind::Outdated(outdated) => {
+            assert_eq!(outdated.schema_version(), 3);
+            assert_eq!(outdated.payload(), unversioned);
+        }
+        _ => panic!("expected outdated error, got {err:?}"),
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tower_sesh::store::{EvictionPolicy, MemoryStore, SessionStore};
+use tower_sesh_core::{
+    time::{Clock, MockClock},
+    Ttl,
+};
+
+fn ttl(clock: &MockClock) -> Ttl {
+    clock.now() + Duration::from_secs(60)
+}
+
+#[tokio::test]
+async fn purge_expired_returns_number_of_sessions_removed() {
+    let clock = MockClock::default();
+    let store = MemoryStore::<()>::new().clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+
+    let long_lived = dyn_store
+        .create(&(), clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+
+    clock.advance(Duration::from_secs(2 * 60));
+
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+    assert!(dyn_store.load(&long_lived).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn purge_expired_removes_sessions_from_index() {
+    let clock = MockClock::default();
+    let store = MemoryStore::<()>::new().clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let index = dyn_store.as_index().unwrap();
+
+    let session_key = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    index.associate(&session_key, "alice").await.unwrap();
+
+    clock.advance(Duration::from_secs(2 * 60));
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 1);
+
+    // The session is revived under the same key, but no longer belongs to
+    // "alice"
+    dyn_store
+        .update(&session_key, &(), ttl(&clock))
+        .await
+        .unwrap();
+    assert!(index.sessions("alice").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reaper_removes_expired_sessions() {
+    let clock = MockClock::default();
+    let store = Arc::new(MemoryStore::<()>::new().clock(clock.clone()));
+    let dyn_store: &dyn SessionStore<()> = store.as_ref();
+
+    let reaper = store.spawn_reaper(Duration::from_millis(1));
+
+    for _ in 0..3 {
+        dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    }
+    clock.advance(Duration::from_secs(2 * 60));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reaper.removed() < 3 {
+        assert!(Instant::now() < deadline, "reaper didn't remove sessions");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(reaper.shutdown(), 3);
+    assert_eq!(dyn_store.purge_expired().await.unwrap(), 0);
+}
+
+#[test]
+#[should_panic = "reaper interval must be non-zero"]
+fn reaper_panics_on_zero_interval() {
+    let store = Arc::new(MemoryStore::<()>::new());
+    let _ = store.spawn_reaper(Duration::ZERO);
+}
+
+#[tokio::test]
+async fn evicted_sessions_are_removed_from_index() {
+    let store = MemoryStore::<u64>::new().max_capacity(10);
+    let dyn_store: &dyn SessionStore<u64> = &store;
+    let index = dyn_store.as_index().unwrap();
+
+    let mut session_keys = Vec::new();
+    for n in 0..100 {
+        let session_key = dyn_store
+            .create(&n, Ttl::now_utc() + Duration::from_secs(60))
+            .await
+            .unwrap();
+        index.associate(&session_key, "alice").await.unwrap();
+        session_keys.push(session_key);
+    }
+
+    assert!(store.eviction_count() > 0);
+    for session_key in &session_keys {
+        let loaded = dyn_store.load(session_key).await.unwrap();
+        let principal = index.principal(session_key).await.unwrap();
+        assert_eq!(principal.is_some(), loaded.is_some());
+    }
+}
+
+#[test]
+fn configuring_does_not_require_session_data_bounds() {
+    // Neither `Send` nor `Sync`
+    struct SessionData(#[allow(dead_code)] std::rc::Rc<()>);
+
+    let _store = MemoryStore::<SessionData>::new()
+        .clock(MockClock::default())
+        .max_capacity(10)
+        .weigher(|_| 1)
+        .eviction_policy(EvictionPolicy::Lru);
+}
+
+#[tokio::test]
+async fn max_capacity_evicts_sessions() {
+    let store = MemoryStore::<u64>::new().max_capacity(10);
+    let dyn_store: &dyn SessionStore<u64> = &store;
+
+    let mut session_keys = Vec::new();
+    for n in 0..100 {
+        let session_key = dyn_store
+            .create(&n, Ttl::now_utc() + Duration::from_secs(60))
+            .await
+            .unwrap();
+        session_keys.push(session_key);
+    }
+
+    let evicted = store.eviction_count();
+    let mut remaining = 0;
+    for session_key in &session_keys {
+        if dyn_store.load(session_key).await.unwrap().is_some() {
+            remaining += 1;
+        }
+    }
+    assert!(remaining <= 10, "{remaining} sessions remaining");
+    assert_eq!(evicted + remaining, 100);
+}
+
+#[tokio::test]
+async fn weigher_limits_total_weight() {
+    let store = MemoryStore::<String>::new()
+        .max_capacity(100)
+        .weigher(|data| data.len() as u32);
+    let dyn_store: &dyn SessionStore<String> = &store;
+
+    for _ in 0..20 {
+        dyn_store
+            .create(
+                &"0123456789".to_owned(),
+                Ttl::now_utc() + Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+    }
+
+    assert!(store.eviction_count() >= 10);
+}
+
+#[tokio::test]
+async fn lru_evicts_least_recently_used_session() {
+    let store = MemoryStore::<()>::new()
+        .max_capacity(2)
+        .eviction_policy(EvictionPolicy::Lru);
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let ttl = Ttl::now_utc() + Duration::from_secs(60);
+
+    // `eviction_count` also makes the store process the accesses so far, so
+    // they're applied in order
+    let first = dyn_store.create(&(), ttl).await.unwrap();
+    let second = dyn_store.create(&(), ttl).await.unwrap();
+    assert_eq!(store.eviction_count(), 0);
+    assert!(dyn_store.load(&first).await.unwrap().is_some());
+    assert_eq!(store.eviction_count(), 0);
+
+    let third = dyn_store.create(&(), ttl).await.unwrap();
+
+    assert_eq!(store.eviction_count(), 1);
+    assert!(dyn_store.load(&first).await.unwrap().is_some());
+    assert!(dyn_store.load(&second).await.unwrap().is_none());
+    assert!(dyn_store.load(&third).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn capacity_settings_keep_existing_sessions() {
+    let clock = MockClock::default();
+    let store = MemoryStore::<()>::new();
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let session_key = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+
+    let store = store.max_capacity(10).clock(clock.clone());
+
+    let dyn_store: &dyn SessionStore<()> = &store;
+    assert!(dyn_store.load(&session_key).await.unwrap().is_some());
+}
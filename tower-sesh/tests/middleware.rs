@@ -1,22 +1,35 @@
-use std::sync::{
-    atomic::{AtomicUsize, Ordering::SeqCst},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
 };
 
 use axum::{body::Body, response::IntoResponse, routing, Router};
 use cookie::{Cookie, CookieJar};
 use http::{header, HeaderValue, Method, Request, Response, StatusCode};
 use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 use tower::{ServiceBuilder, ServiceExt};
-use tower_sesh::{store::MemoryStore, Session, SessionLayer};
+use tower_sesh::{
+    middleware::{ConflictPolicy, Expiry, Key, Transport},
+    migrate::Migrations,
+    store::{CachingStore, MemoryStore, SessionStore},
+    Session, SessionLayer,
+};
 use tower_sesh_core::{
-    store::{SessionStoreImpl, SessionStoreRng},
-    SessionKey,
+    backend::BackendStore,
+    codec::{Codec, MessagePack, Versioned},
+    key::KeyHasher,
+    store::{SessionIndex, SessionStoreImpl, SessionStoreRng},
+    time::{Clock, MockClock},
+    SessionKey, Ttl,
 };
 use tower_sesh_test::{support::SessionData, TestRng};
 
 mod support;
-use support::{ttl, ArbitraryKey, ArbitrarySessionKey};
+use support::{ttl, ArbitraryKey, ArbitrarySessionKey, CodecBackend};
 
 fn jar_from_response<B>(
     res: &Response<B>,
@@ -162,6 +175,109 @@ fn private_or_signed_cookie_create_and_load() {
     quickcheck::quickcheck(check as fn(_, _, _) -> _);
 }
 
+#[cfg_attr(miri, ignore)]
+#[test]
+fn retired_key_cookie_is_reissued() {
+    #[tokio::main(flavor = "current_thread")]
+    async fn check(
+        ArbitraryKey(old_key): ArbitraryKey,
+        ArbitraryKey(new_key): ArbitraryKey,
+        is_private: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        async fn session_create(session: Session<SessionData>) -> impl IntoResponse {
+            session.insert(SessionData::sample());
+        }
+
+        async fn session_load(session: Session<SessionData>) -> impl IntoResponse {
+            if session.get().is_some() {
+                StatusCode::OK
+            } else {
+                StatusCode::UNAUTHORIZED
+            }
+        }
+
+        async fn untouched() {}
+
+        fn app(
+            session_layer: SessionLayer<SessionData, MemoryStore<SessionData>>,
+            is_private: bool,
+        ) -> Router {
+            // Sessions aren't renewed on access with an absolute expiry, so any
+            // cookie in a response must have been re-issued.
+            let session_layer =
+                session_layer.expiry(Expiry::Absolute(Duration::from_secs(60 * 60)));
+            let app = Router::new()
+                .route("/create", routing::post(session_create))
+                .route("/load", routing::get(session_load))
+                .route("/untouched", routing::get(untouched));
+            if is_private {
+                app.layer(session_layer.private())
+            } else {
+                app.layer(session_layer.signed())
+            }
+        }
+
+        let store = Arc::new(MemoryStore::<SessionData>::new());
+        let old_app = app(
+            SessionLayer::new(Arc::clone(&store), old_key.clone()).cookie_name("id"),
+            is_private,
+        );
+        let rotated_app = app(
+            SessionLayer::new(Arc::clone(&store), new_key.clone())
+                .cookie_name("id")
+                .retired_keys([old_key]),
+            is_private,
+        );
+        let new_app = app(
+            SessionLayer::new(Arc::clone(&store), new_key).cookie_name("id"),
+            is_private,
+        );
+
+        let req = Request::builder()
+            .uri("/create")
+            .method(Method::POST)
+            .body(Body::empty())?;
+        let res = old_app.oneshot(req).await?;
+        let old_cookie = jar_from_response(&res)?
+            .get("id")
+            .unwrap()
+            .value()
+            .to_owned();
+
+        for uri in ["/load", "/untouched"] {
+            let req = Request::builder()
+                .uri(uri)
+                .header(header::COOKIE, format!("id={old_cookie}"))
+                .body(Body::empty())?;
+            let res = rotated_app.clone().oneshot(req).await?;
+            if !res.status().is_success() {
+                return Err("retired key was not accepted".into());
+            }
+            let reissued_cookie = jar_from_response(&res)?
+                .get("id")
+                .ok_or("cookie was not re-issued")?
+                .value()
+                .to_owned();
+
+            let req = Request::builder()
+                .uri("/load")
+                .header(header::COOKIE, format!("id={reissued_cookie}"))
+                .body(Body::empty())?;
+            let res = new_app.clone().oneshot(req).await?;
+            if !res.status().is_success() {
+                return Err("re-issued cookie was not accepted by the primary key".into());
+            }
+            if jar_from_response(&res)?.get("id").is_some() {
+                return Err("cookie opened by the primary key was re-issued".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    quickcheck::quickcheck(check as fn(_, _, _) -> _);
+}
+
 #[test]
 #[should_panic = "not implemented"]
 fn plain_to_private() {
@@ -339,6 +455,784 @@ async fn option_secure() {
     assert!(!cookie.secure().unwrap_or(false));
 }
 
+async fn load_ttl(store: &MemoryStore<()>, jar: &CookieJar) -> Ttl {
+    let session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+    store.load(&session_key).await.unwrap().unwrap().ttl
+}
+
+#[tokio::test]
+async fn cycle_key_moves_session_to_new_key() {
+    async fn create(session: Session<String>) {
+        session.insert("guest".to_owned());
+    }
+
+    async fn login(session: Session<String>) {
+        session.cycle_key();
+        session.insert("admin".to_owned());
+    }
+
+    let store = Arc::new(MemoryStore::<String>::new());
+    let session_layer = SessionLayer::plain(Arc::clone(&store)).cookie_name("id");
+    let app = Router::new()
+        .route("/create", routing::post(create))
+        .route("/login", routing::post(login))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/create")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res).unwrap();
+    let old_session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+
+    let req = Request::builder()
+        .uri("/login")
+        .method(Method::POST)
+        .header(header::COOKIE, format!("id={}", old_session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res).unwrap();
+    let new_session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+
+    assert_ne!(new_session_key, old_session_key);
+    assert!(store.load(&old_session_key).await.unwrap().is_none());
+    let record = store.load(&new_session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "admin");
+}
+
+#[tokio::test]
+async fn option_expiry_idle() {
+    async fn create(session: Session<()>) {
+        session.insert(());
+    }
+
+    async fn access(session: Session<()>) {
+        assert!(session.get().is_some());
+    }
+
+    const IDLE: Duration = Duration::from_secs(60 * 60);
+
+    let clock = MockClock::default();
+    let store = Arc::new(MemoryStore::<()>::new().clock(clock.clone()));
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .expiry(Expiry::Idle(IDLE))
+        .clock(clock.clone());
+    let app = Router::new()
+        .route("/create", routing::post(create))
+        .route("/access", routing::get(access))
+        .layer(session_layer);
+
+    let before = clock.now();
+    let req = Request::builder()
+        .uri("/create")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+
+    let jar = jar_from_response(&res).unwrap();
+    let ttl = load_ttl(&store, &jar).await;
+    assert_eq!(ttl, before + IDLE);
+    let expires = jar.get("id").unwrap().expires_datetime().unwrap();
+    assert_eq!(expires.unix_timestamp(), ttl.unix_timestamp());
+
+    clock.advance(Duration::from_secs(10 * 60));
+
+    let req = Request::builder()
+        .uri("/access")
+        .header(
+            header::COOKIE,
+            format!("id={}", jar.get("id").unwrap().value()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+
+    assert!(
+        res.headers().get(header::SET_COOKIE).is_none(),
+        "accessing a recently refreshed session should not renew it"
+    );
+    assert_eq!(load_ttl(&store, &jar).await, ttl);
+
+    clock.advance(Duration::from_secs(25 * 60));
+
+    let req = Request::builder()
+        .uri("/access")
+        .header(
+            header::COOKIE,
+            format!("id={}", jar.get("id").unwrap().value()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+
+    let renewed_jar = jar_from_response(&res).unwrap();
+    let renewed_ttl = load_ttl(&store, &jar).await;
+    assert_eq!(
+        renewed_ttl,
+        ttl + Duration::from_secs(35 * 60),
+        "accessing the session after half of the idle timeout should renew it"
+    );
+    let expires = renewed_jar.get("id").unwrap().expires_datetime().unwrap();
+    assert_eq!(expires.unix_timestamp(), renewed_ttl.unix_timestamp());
+}
+
+#[tokio::test]
+async fn option_expiry_absolute() {
+    async fn create(session: Session<()>) {
+        session.insert(());
+    }
+
+    async fn modify(session: Session<()>) {
+        session.insert(());
+    }
+
+    async fn renew(session: Session<()>) {
+        session.renew();
+    }
+
+    const LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+    let clock = MockClock::default();
+    let store = Arc::new(MemoryStore::<()>::new().clock(clock.clone()));
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .expiry(Expiry::Absolute(LIFETIME))
+        .clock(clock.clone());
+    let app = Router::new()
+        .route("/create", routing::post(create))
+        .route("/modify", routing::post(modify))
+        .route("/renew", routing::post(renew))
+        .layer(session_layer);
+
+    let before = clock.now();
+    let req = Request::builder()
+        .uri("/create")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+
+    let jar = jar_from_response(&res).unwrap();
+    let ttl = load_ttl(&store, &jar).await;
+    assert_eq!(ttl, before + LIFETIME);
+    let expires = jar.get("id").unwrap().expires_datetime().unwrap();
+    assert_eq!(expires.unix_timestamp(), ttl.unix_timestamp());
+
+    clock.advance(Duration::from_secs(10 * 60));
+
+    let req = Request::builder()
+        .uri("/modify")
+        .method(Method::POST)
+        .header(
+            header::COOKIE,
+            format!("id={}", jar.get("id").unwrap().value()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(req).await.unwrap();
+    assert_eq!(load_ttl(&store, &jar).await, ttl);
+
+    let req = Request::builder()
+        .uri("/renew")
+        .method(Method::POST)
+        .header(
+            header::COOKIE,
+            format!("id={}", jar.get("id").unwrap().value()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(load_ttl(&store, &jar).await, ttl);
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+}
+
+#[tokio::test]
+async fn option_expiry_browser_session() {
+    async fn handler(session: Session<()>) {
+        session.insert(());
+    }
+
+    const IDLE: Duration = Duration::from_secs(60 * 60);
+
+    let store = Arc::new(MemoryStore::<()>::new());
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .expiry(Expiry::BrowserSession(IDLE));
+    let app = Router::new()
+        .route("/", routing::get(handler))
+        .layer(session_layer);
+
+    let before = Ttl::now_utc();
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let res = app.oneshot(req).await.unwrap();
+
+    let jar = jar_from_response(&res).unwrap();
+    assert!(jar.get("id").unwrap().expires().is_none());
+    assert!(jar.get("id").unwrap().max_age().is_none());
+    assert!(load_ttl(&store, &jar).await >= before + IDLE);
+}
+
+#[tokio::test]
+async fn cookie_max_age_matches_ttl() {
+    async fn handler(session: Session<()>) {
+        session.insert(());
+    }
+
+    const IDLE: Duration = Duration::from_secs(60 * 60);
+
+    let store = Arc::new(MemoryStore::<()>::new());
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .expiry(Expiry::Idle(IDLE));
+    let app = Router::new()
+        .route("/", routing::get(handler))
+        .layer(session_layer);
+
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let res = app.oneshot(req).await.unwrap();
+
+    let jar = jar_from_response(&res).unwrap();
+    let cookie = jar.get("id").unwrap();
+    let max_age = cookie.max_age().unwrap();
+    assert!(max_age > IDLE - Duration::from_secs(5) && max_age <= IDLE);
+    let ttl = load_ttl(&store, &jar).await;
+    assert_eq!(
+        cookie.expires_datetime().unwrap().unix_timestamp(),
+        ttl.unix_timestamp()
+    );
+}
+
+#[tokio::test]
+async fn removal_cookie_matches_session_cookie() {
+    async fn handler(session: Session<()>) {
+        session.purge();
+    }
+
+    let store = Arc::new(MemoryStore::<()>::new());
+    let session_key = SessionKey::try_from(1).unwrap();
+    store.update(&session_key, &(), ttl()).await.unwrap();
+
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .domain("doc.rust-lang.org")
+        .path("/std")
+        .expiry(Expiry::Idle(Duration::from_secs(60 * 60)));
+    let app = Router::new()
+        .route("/", routing::get(handler))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/")
+        .header(header::COOKIE, format!("id={}", session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+
+    let jar = jar_from_response(&res).unwrap();
+    assert!(jar.iter().collect::<Vec<_>>().len() == 1);
+    let cookie = jar.get("id").unwrap();
+    assert_eq!(cookie.value(), "");
+    assert_eq!(cookie.domain(), Some("doc.rust-lang.org"));
+    assert_eq!(cookie.path(), Some("/std"));
+    assert_eq!(cookie.max_age(), Some(cookie::time::Duration::ZERO));
+    assert!(cookie.expires_datetime().unwrap() < Ttl::now_utc());
+    assert!(store.load(&session_key).await.unwrap().is_none());
+}
+
+fn transport_app(transport: Transport) -> Router {
+    async fn create(session: Session<()>) {
+        session.insert(());
+    }
+
+    async fn load(session: Session<()>) -> StatusCode {
+        if session.get().is_some() {
+            StatusCode::OK
+        } else {
+            StatusCode::UNAUTHORIZED
+        }
+    }
+
+    async fn purge(session: Session<()>) {
+        session.purge();
+    }
+
+    let session_layer = SessionLayer::new(Arc::new(MemoryStore::<()>::new()), Key::from([0; 64]))
+        .cookie_name("id")
+        .transport(transport);
+    Router::new()
+        .route("/create", routing::post(create))
+        .route("/load", routing::get(load))
+        .route("/purge", routing::post(purge))
+        .layer(session_layer)
+}
+
+#[tokio::test]
+async fn option_transport_header() {
+    let header_name = header::HeaderName::from_static("x-session");
+    let app = transport_app(Transport::Header(header_name.clone()));
+
+    let req = Request::builder()
+        .uri("/create")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+    let token = res.headers().get(&header_name).unwrap().clone();
+    assert!(!token.is_empty());
+
+    let req = Request::builder()
+        .uri("/load")
+        .header(&header_name, token.clone())
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = Request::builder()
+        .uri("/purge")
+        .method(Method::POST)
+        .header(&header_name, token)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+    assert!(res.headers().get(&header_name).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn option_transport_bearer() {
+    let header_name = header::HeaderName::from_static("x-session-token");
+    let app = transport_app(Transport::Bearer(header_name.clone()));
+
+    let req = Request::builder()
+        .uri("/create")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert!(res.headers().get(header::SET_COOKIE).is_none());
+    let token = res
+        .headers()
+        .get(&header_name)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let req = Request::builder()
+        .uri("/load")
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let mut tampered = token.into_bytes();
+    tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+    let req = Request::builder()
+        .uri("/load")
+        .header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", String::from_utf8(tampered).unwrap()),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn set_principal_indexes_session() {
+    async fn login(session: Session<String>) {
+        session.cycle_key();
+        session.insert("user".to_owned());
+        session.set_principal("42");
+    }
+
+    let store = Arc::new(MemoryStore::<String>::new());
+    let session_layer = SessionLayer::plain(Arc::clone(&store)).cookie_name("id");
+    let app = Router::new()
+        .route("/login", routing::post(login))
+        .layer(session_layer);
+
+    let mut session_keys = Vec::new();
+    for _ in 0..2 {
+        let req = Request::builder()
+            .uri("/login")
+            .method(Method::POST)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let jar = jar_from_response(&res).unwrap();
+        session_keys.push(SessionKey::decode(jar.get("id").unwrap().value()).unwrap());
+    }
+
+    let mut ids = store
+        .sessions("42")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|session| session.id.as_str().to_owned())
+        .collect::<Vec<_>>();
+    ids.sort();
+    session_keys.sort_by_key(SessionKey::encode);
+    assert_eq!(
+        ids,
+        session_keys
+            .iter()
+            .map(SessionKey::encode)
+            .collect::<Vec<_>>()
+    );
+
+    store.delete_sessions("42").await.unwrap();
+    for session_key in &session_keys {
+        assert!(store.load(session_key).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn cycle_key_keeps_principal() {
+    let store = MemoryStore::<String>::new();
+    check_cycle_key_keeps_principal(Arc::new(store)).await;
+}
+
+#[tokio::test]
+async fn cycle_key_keeps_principal_caching_store() {
+    let hasher = KeyHasher::new(&[0; 32]);
+    let store = CachingStore::from_cache_and_store(
+        MemoryStore::<String>::new().hash_keys(hasher.clone()),
+        MemoryStore::<String>::new().hash_keys(hasher),
+    );
+    check_cycle_key_keeps_principal(Arc::new(store)).await;
+}
+
+async fn check_cycle_key_keeps_principal<S>(store: Arc<S>)
+where
+    S: SessionStore<String> + SessionIndex,
+{
+    async fn login(session: Session<String>) {
+        session.insert("user".to_owned());
+        session.set_principal("42");
+    }
+
+    async fn elevate(session: Session<String>) {
+        session.cycle_key();
+    }
+
+    let session_layer = SessionLayer::plain(Arc::clone(&store)).cookie_name("id");
+    let app = Router::new()
+        .route("/login", routing::post(login))
+        .route("/elevate", routing::post(elevate))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/login")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res).unwrap();
+    let old_session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+
+    let req = Request::builder()
+        .uri("/elevate")
+        .method(Method::POST)
+        .header(header::COOKIE, format!("id={}", old_session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res).unwrap();
+    let new_session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+    assert_ne!(new_session_key, old_session_key);
+    assert!(store.load(&new_session_key).await.unwrap().is_some());
+
+    assert_eq!(store.sessions("42").await.unwrap().len(), 1);
+    assert_eq!(
+        store.principal(&new_session_key).await.unwrap().as_deref(),
+        Some("42")
+    );
+
+    store.delete_sessions("42").await.unwrap();
+    assert!(store.load(&new_session_key).await.unwrap().is_none());
+    assert!(store.load(&old_session_key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn hashed_keys_store_sessions_under_hash() {
+    let hasher = KeyHasher::new(&[0; 32]);
+    let store = MemoryStore::<String>::new().hash_keys(hasher.clone());
+    check_hashed_keys(Arc::new(store), &hasher).await;
+}
+
+#[tokio::test]
+async fn hashed_keys_caching_store() {
+    let hasher = KeyHasher::new(&[0; 32]);
+    let store = CachingStore::from_cache_and_store(
+        MemoryStore::<String>::new().hash_keys(hasher.clone()),
+        MemoryStore::<String>::new().hash_keys(hasher.clone()),
+    );
+    check_hashed_keys(Arc::new(store), &hasher).await;
+}
+
+async fn check_hashed_keys<S>(store: Arc<S>, hasher: &KeyHasher)
+where
+    S: SessionStore<String> + SessionIndex,
+{
+    async fn login(session: Session<String>) {
+        session.insert("user".to_owned());
+        session.set_principal("42");
+    }
+
+    let session_layer = SessionLayer::plain(Arc::clone(&store)).cookie_name("id");
+    let app = Router::new()
+        .route("/login", routing::post(login))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/login")
+        .method(Method::POST)
+        .body(Body::empty())
+        .unwrap();
+    let res = app.clone().oneshot(req).await.unwrap();
+    let jar = jar_from_response(&res).unwrap();
+    let session_key = SessionKey::decode(jar.get("id").unwrap().value()).unwrap();
+
+    // The cookie carries the session key, which the store hashes
+    let record = store.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "user");
+
+    // The index lists the session by its hash, which it can delete it by
+    let sessions = store.sessions("42").await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id.as_str(), hasher.hash(&session_key).encode());
+
+    store.delete_session("42", &sessions[0].id).await.unwrap();
+    assert!(store.load(&session_key).await.unwrap().is_none());
+    assert!(store.sessions("42").await.unwrap().is_empty());
+}
+
+#[derive(Deserialize, Serialize)]
+struct SessionV1 {
+    user_id: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct SessionV2 {
+    user_id: u64,
+    theme: String,
+}
+
+fn migrations() -> Migrations<SessionV2, MessagePack> {
+    Migrations::<SessionV1, _>::new(MessagePack).step(|v1| SessionV2 {
+        user_id: v1.user_id,
+        theme: "light".to_owned(),
+    })
+}
+
+/// Sends a request with a session cookie for `session_key`, returning the
+/// session data seen by the handler.
+async fn load_migrated(
+    store: Arc<BackendStore<CodecBackend<Versioned<MessagePack>>>>,
+    session_key: &SessionKey,
+) -> Option<SessionV2> {
+    let seen = Arc::new(parking_lot::Mutex::new(None));
+    let handler = {
+        let seen = Arc::clone(&seen);
+        move |session: Session<SessionV2>| async move {
+            *seen.lock() = session.get().clone();
+        }
+    };
+    let session_layer = SessionLayer::plain(store)
+        .cookie_name("id")
+        .migrations(migrations());
+    let app = Router::new()
+        .route("/", routing::get(handler))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/")
+        .header(header::COOKIE, format!("id={}", session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let seen = seen.lock().take();
+    seen
+}
+
+#[tokio::test]
+async fn migrations_upgrade_outdated_session() {
+    let codec = migrations().codec();
+    let store = Arc::new(BackendStore::new(CodecBackend::new(codec)));
+
+    // Stored before schema versions were recorded
+    let legacy = MessagePack.encode(&SessionV1 { user_id: 42 }).unwrap();
+    let session_key = store.get_ref().insert_raw(legacy);
+
+    let expected = SessionV2 {
+        user_id: 42,
+        theme: "light".to_owned(),
+    };
+    let data = load_migrated(Arc::clone(&store), &session_key).await;
+    assert_eq!(data.as_ref(), Some(&expected));
+
+    // The upgraded session is written back with the current schema version
+    let bytes = store.get_ref().get_raw(&session_key).unwrap();
+    assert_eq!(codec.decode::<SessionV2>(&bytes).unwrap(), expected);
+    let data = load_migrated(Arc::clone(&store), &session_key).await;
+    assert_eq!(data.as_ref(), Some(&expected));
+}
+
+#[tokio::test]
+async fn migrations_without_match_yield_empty_session() {
+    let codec = migrations().codec();
+    let store = Arc::new(BackendStore::new(CodecBackend::new(codec)));
+
+    // Stored with a newer schema version than the current one
+    let data = SessionV2 {
+        user_id: 42,
+        theme: "dark".to_owned(),
+    };
+    let newer = Versioned::new(MessagePack, 5).encode(&data).unwrap();
+    let session_key = store.get_ref().insert_raw(newer);
+    assert_eq!(load_migrated(Arc::clone(&store), &session_key).await, None);
+
+    // Stored with an old schema version, but not deserializable as it
+    let garbage = MessagePack.encode(&"not a session").unwrap();
+    let session_key = store.get_ref().insert_raw(garbage);
+    assert_eq!(load_migrated(Arc::clone(&store), &session_key).await, None);
+}
+
+/// Sends a request which modifies a session from "a" to "c", while another
+/// write to the same session (from "a" to "b") happens in between loading and
+/// syncing the session. Returns the response status and the session data left
+/// in the store.
+async fn modify_concurrently(
+    conflict_policy: ConflictPolicy<String>,
+) -> (StatusCode, Option<String>) {
+    let store = Arc::new(MemoryStore::<String>::new());
+    let session_key = store.create(&"a".to_owned(), ttl()).await.unwrap();
+
+    let handler = {
+        let store = Arc::clone(&store);
+        let session_key = session_key.clone();
+        move |session: Session<String>| async move {
+            assert_eq!(session.get().as_deref(), Some("a"));
+            store
+                .update(&session_key, &"b".to_owned(), ttl())
+                .await
+                .unwrap();
+            session.insert("c".to_owned());
+            "saved"
+        }
+    };
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .conflict_policy(conflict_policy);
+    let app = Router::new()
+        .route("/", routing::post(handler))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/")
+        .method(Method::POST)
+        .header(header::COOKIE, format!("id={}", session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    let status = res.status();
+    if status == StatusCode::CONFLICT {
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    let data = store
+        .load(&session_key)
+        .await
+        .unwrap()
+        .map(|record| record.data);
+    (status, data)
+}
+
+#[tokio::test]
+async fn conflict_policy_last_write_wins() {
+    let (status, data) = modify_concurrently(ConflictPolicy::LastWriteWins).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data.as_deref(), Some("c"));
+}
+
+#[tokio::test]
+async fn conflict_policy_reject() {
+    let (status, data) = modify_concurrently(ConflictPolicy::Reject).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(data.as_deref(), Some("b"));
+}
+
+#[tokio::test]
+async fn conflict_policy_merge() {
+    let (status, data) =
+        modify_concurrently(ConflictPolicy::merge(|ours: String, theirs: String| {
+            theirs + &ours
+        }))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data.as_deref(), Some("bc"));
+}
+
+/// Sends a request which modifies a session, while the session is deleted
+/// (such as by logging out) in between loading and syncing it. Returns the
+/// session data left in the store.
+async fn delete_concurrently(conflict_policy: ConflictPolicy<String>) -> Option<String> {
+    let store = Arc::new(MemoryStore::<String>::new());
+    let session_key = store.create(&"a".to_owned(), ttl()).await.unwrap();
+
+    let handler = {
+        let store = Arc::clone(&store);
+        let session_key = session_key.clone();
+        move |session: Session<String>| async move {
+            store.delete(&session_key).await.unwrap();
+            session.insert("c".to_owned());
+        }
+    };
+    let session_layer = SessionLayer::plain(Arc::clone(&store))
+        .cookie_name("id")
+        .conflict_policy(conflict_policy);
+    let app = Router::new()
+        .route("/", routing::post(handler))
+        .layer(session_layer);
+
+    let req = Request::builder()
+        .uri("/")
+        .method(Method::POST)
+        .header(header::COOKIE, format!("id={}", session_key.encode()))
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    store
+        .load(&session_key)
+        .await
+        .unwrap()
+        .map(|record| record.data)
+}
+
+#[tokio::test]
+async fn conflict_policy_does_not_restore_deleted_session() {
+    assert_eq!(delete_concurrently(ConflictPolicy::Reject).await, None);
+    let merge = ConflictPolicy::merge(|ours: String, theirs: String| theirs + &ours);
+    assert_eq!(delete_concurrently(merge).await, None);
+}
+
 #[tokio::test]
 #[should_panic = "called more than once!"]
 async fn multiple_session_layers() {
@@ -0,0 +1,143 @@
+#![cfg(feature = "memory-store-snapshot")]
+
+use std::{sync::Arc, time::Duration};
+
+use tower_sesh::store::{MemoryStore, SessionStore};
+use tower_sesh_core::{
+    key::KeyHasher,
+    time::{Clock, MockClock},
+    Ttl,
+};
+
+fn ttl(clock: &MockClock) -> Ttl {
+    clock.now() + Duration::from_secs(60)
+}
+
+#[tokio::test]
+async fn snapshot_restores_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sessions.snapshot");
+    let clock = MockClock::default();
+
+    let store = MemoryStore::<String>::new().clock(clock.clone());
+    let dyn_store: &dyn SessionStore<String> = &store;
+    let session_key = dyn_store
+        .create(&"hello".to_owned(), ttl(&clock))
+        .await
+        .unwrap();
+    assert_eq!(store.save_snapshot(&path).unwrap(), 1);
+
+    let restored = MemoryStore::<String>::new().clock(clock.clone());
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 1);
+
+    let dyn_restored: &dyn SessionStore<String> = &restored;
+    let record = dyn_restored.load(&session_key).await.unwrap().unwrap();
+    assert_eq!(record.data, "hello");
+    assert_eq!(record.ttl, ttl(&clock));
+}
+
+#[tokio::test]
+async fn snapshot_skips_sessions_expired_by_load_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sessions.snapshot");
+    let clock = MockClock::default();
+
+    let store = MemoryStore::<()>::new().clock(clock.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let short_lived = dyn_store.create(&(), ttl(&clock)).await.unwrap();
+    let long_lived = dyn_store
+        .create(&(), clock.now() + Duration::from_secs(10 * 60))
+        .await
+        .unwrap();
+    assert_eq!(store.save_snapshot(&path).unwrap(), 2);
+
+    clock.advance(Duration::from_secs(2 * 60));
+
+    let restored = MemoryStore::<()>::new().clock(clock.clone());
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 1);
+
+    let dyn_restored: &dyn SessionStore<()> = &restored;
+    assert!(dyn_restored.load(&short_lived).await.unwrap().is_none());
+    assert!(dyn_restored.load(&long_lived).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn snapshot_restores_index_and_hashed_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sessions.snapshot");
+    let hasher = KeyHasher::new(&[7; 32]);
+
+    let store = MemoryStore::<()>::new().hash_keys(hasher.clone());
+    let dyn_store: &dyn SessionStore<()> = &store;
+    let ttl = Ttl::now_utc() + Duration::from_secs(60);
+    let session_key = dyn_store.create(&(), ttl).await.unwrap();
+    dyn_store
+        .as_index()
+        .unwrap()
+        .associate(&session_key, "alice")
+        .await
+        .unwrap();
+    store.save_snapshot(&path).unwrap();
+
+    let bytes = std::fs::read(&path).unwrap();
+    let encoded = session_key.encode();
+    assert!(
+        !bytes
+            .windows(encoded.len())
+            .any(|w| w == encoded.as_bytes()),
+        "raw session keys should not be saved"
+    );
+
+    let restored = MemoryStore::<()>::new().hash_keys(hasher);
+    restored.load_snapshot(&path).unwrap();
+
+    let dyn_restored: &dyn SessionStore<()> = &restored;
+    assert!(dyn_restored.load(&session_key).await.unwrap().is_some());
+    let index = dyn_restored.as_index().unwrap();
+    assert_eq!(index.sessions("alice").await.unwrap().len(), 1);
+    index.delete_sessions("alice").await.unwrap();
+    assert!(dyn_restored.load(&session_key).await.unwrap().is_none());
+}
+
+#[test]
+fn loading_missing_snapshot_restores_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = MemoryStore::<()>::new();
+
+    assert_eq!(store.load_snapshot(dir.path().join("missing")).unwrap(), 0);
+}
+
+#[test]
+fn loading_invalid_snapshot_is_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sessions.snapshot");
+    std::fs::write(&path, b"not a snapshot").unwrap();
+    let store = MemoryStore::<()>::new();
+
+    let err = store.load_snapshot(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn snapshots_are_saved_on_shutdown() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sessions.snapshot");
+
+    let store = Arc::new(MemoryStore::<()>::new());
+    let snapshots = store.spawn_snapshots(&path, Duration::from_secs(60 * 60));
+
+    let dyn_store: &dyn SessionStore<()> = store.as_ref();
+    let session_key = dyn_store
+        .create(&(), Ttl::now_utc() + Duration::from_secs(60))
+        .await
+        .unwrap();
+    snapshots.shutdown().unwrap();
+
+    let restored = MemoryStore::<()>::new();
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 1);
+    let dyn_restored: &dyn SessionStore<()> = &restored;
+    assert!(dyn_restored.load(&session_key).await.unwrap().is_some());
+
+    // Only the snapshot remains
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
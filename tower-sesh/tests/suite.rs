@@ -1,6 +1,17 @@
 mod support;
 
 mod memory_store {
+    use tower_sesh::store::MemoryStore;
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    test_suite! {
+        clock: clock = MockClock::default(),
+        store: MemoryStore::new().clock(clock.clone()),
+    }
+}
+
+mod memory_store_system_clock {
     use tower_sesh::store::MemoryStore;
     use tower_sesh_test::test_suite;
 
@@ -11,16 +22,29 @@ mod memory_store {
 
 mod memory_store_caching_store {
     use tower_sesh::store::{CachingStore, MemoryStore};
+    use tower_sesh_core::time::MockClock;
     use tower_sesh_test::test_suite;
 
     test_suite! {
+        clock: clock = MockClock::default(),
         store: CachingStore::from_cache_and_store(
-            MemoryStore::new(),
-            MemoryStore::new(),
+            MemoryStore::new().clock(clock.clone()),
+            MemoryStore::new().clock(clock.clone()),
         ),
     }
 }
 
+mod minimal_backend {
+    use tower_sesh_core::backend::BackendStore;
+    use tower_sesh_test::test_suite;
+
+    use super::support::MinimalBackend;
+
+    test_suite! {
+        store: BackendStore::new(MinimalBackend::new()),
+    }
+}
+
 #[cfg(not(miri))]
 mod mock_store {
     use tower_sesh_test::test_suite;
@@ -31,3 +55,43 @@ mod mock_store {
         store: MockStore::new(),
     }
 }
+
+#[cfg(feature = "encrypted-store")]
+mod encrypted_store {
+    use tower_sesh::store::{EncryptedStore, EncryptionKey, MemoryStore};
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    test_suite! {
+        clock: clock = MockClock::default(),
+        store: EncryptedStore::new(
+            MemoryStore::new().clock(clock.clone()),
+            EncryptionKey::new(1, [1; 32]),
+        ),
+    }
+}
+
+#[cfg(all(feature = "file-store", not(miri)))]
+mod file_store {
+    use tower_sesh::store::FileStore;
+    use tower_sesh_core::time::MockClock;
+    use tower_sesh_test::test_suite;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        clock: clock = MockClock::default(),
+        store: FileStore::open(dir.path()).unwrap().clock(clock.clone()),
+    }
+}
+
+#[cfg(all(feature = "file-store", not(miri)))]
+mod file_store_hashed_keys {
+    use tower_sesh::store::FileStore;
+    use tower_sesh_core::key::KeyHasher;
+    use tower_sesh_test::test_suite;
+
+    test_suite! {
+        guard: dir = tempfile::tempdir().unwrap(),
+        store: FileStore::open(dir.path()).unwrap().hash_keys(KeyHasher::new(&[1; 32])),
+    }
+}
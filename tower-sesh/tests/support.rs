@@ -9,15 +9,18 @@ use async_trait::async_trait;
 use parking_lot::Mutex;
 use quickcheck::Arbitrary;
 use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
 use tower_sesh::middleware::Key;
 use tower_sesh_core::{
+    backend::SessionBackend,
+    codec::Codec,
     store::{self, Result, SessionStoreImpl},
     Record, SessionKey, SessionStore, Ttl,
 };
 
 /// Arbitrary TTL far into the future.
 pub fn ttl() -> Ttl {
-    let now = Ttl::now_local().unwrap();
+    let now = Ttl::now_utc();
     now + Duration::from_secs(10 * 60)
 }
 
@@ -149,6 +152,169 @@ where
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// `MinimalBackend`
+////////////////////////////////////////////////////////////////////////////////
+
+/// An implementation of `SessionBackend` that only implements the required
+/// methods, relying on the default implementations of optional capabilities.
+pub struct MinimalBackend<T> {
+    inner: Mutex<MinimalBackendInner<T>>,
+}
+
+struct MinimalBackendInner<T> {
+    map: HashMap<SessionKey, Record<T>>,
+    rng: Option<Box<dyn rand::CryptoRng + Send + 'static>>,
+}
+
+impl<T> MinimalBackend<T> {
+    pub fn new() -> Self {
+        MinimalBackend {
+            inner: Mutex::new(MinimalBackendInner {
+                map: HashMap::new(),
+                rng: None,
+            }),
+        }
+    }
+}
+
+impl<T> Default for MinimalBackend<T> {
+    fn default() -> Self {
+        MinimalBackend::new()
+    }
+}
+
+#[async_trait]
+impl<T> SessionBackend<T> for MinimalBackend<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let mut guard = self.inner.lock();
+
+        const MAX_ITERATIONS: usize = 8;
+        for _ in 0..MAX_ITERATIONS {
+            let session_key = match &mut guard.rng {
+                Some(rng) => rng.random::<SessionKey>(),
+                None => rand::rng().random::<SessionKey>(),
+            };
+            let occupied = guard
+                .map
+                .get(&session_key)
+                .is_some_and(|record| record.ttl >= tower_sesh_core::time::now());
+            if !occupied {
+                guard
+                    .map
+                    .insert(session_key.clone(), Record::new(data.clone(), ttl));
+                return Ok(session_key);
+            }
+        }
+
+        Err(store::Error::max_iterations_reached())
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let record = self
+            .inner
+            .lock()
+            .map
+            .get(session_key)
+            .cloned()
+            .filter(|record| record.ttl >= tower_sesh_core::time::now());
+        Ok(record)
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        self.inner
+            .lock()
+            .map
+            .insert(session_key.clone(), Record::new(data.clone(), ttl));
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        self.inner.lock().map.remove(session_key);
+        Ok(())
+    }
+}
+
+impl<T, Rng> tower_sesh_core::store::SessionStoreRng<Rng> for MinimalBackend<T>
+where
+    Rng: rand::CryptoRng + Send + 'static,
+{
+    fn rng(&mut self, rng: Rng) {
+        self.inner.lock().rng = Some(Box::new(rng));
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// `CodecBackend`
+////////////////////////////////////////////////////////////////////////////////
+
+/// An implementation of `SessionBackend` that serializes session data with a
+/// codec, and allows the stored bytes to be inspected.
+pub struct CodecBackend<K> {
+    codec: K,
+    map: Mutex<HashMap<SessionKey, (Vec<u8>, Ttl)>>,
+}
+
+impl<K> CodecBackend<K> {
+    pub fn new(codec: K) -> Self {
+        CodecBackend {
+            codec,
+            map: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stores `bytes` as is under a new session key.
+    pub fn insert_raw(&self, bytes: Vec<u8>) -> SessionKey {
+        let session_key = rand::rng().random::<SessionKey>();
+        self.map.lock().insert(session_key.clone(), (bytes, ttl()));
+        session_key
+    }
+
+    /// Returns the bytes stored under `session_key`.
+    pub fn get_raw(&self, session_key: &SessionKey) -> Option<Vec<u8>> {
+        self.map
+            .lock()
+            .get(session_key)
+            .map(|(bytes, _)| bytes.clone())
+    }
+}
+
+#[async_trait]
+impl<T, K> SessionBackend<T> for CodecBackend<K>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    K: Codec,
+{
+    async fn create(&self, data: &T, ttl: Ttl) -> Result<SessionKey> {
+        let bytes = self.codec.encode(data)?;
+        let session_key = rand::rng().random::<SessionKey>();
+        self.map.lock().insert(session_key.clone(), (bytes, ttl));
+        Ok(session_key)
+    }
+
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<Record<T>>> {
+        let Some((bytes, ttl)) = self.map.lock().get(session_key).cloned() else {
+            return Ok(None);
+        };
+        let data = self.codec.decode(&bytes)?;
+        Ok(Some(Record::new(data, ttl)))
+    }
+
+    async fn update(&self, session_key: &SessionKey, data: &T, ttl: Ttl) -> Result<()> {
+        let bytes = self.codec.encode(data)?;
+        self.map.lock().insert(session_key.clone(), (bytes, ttl));
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<()> {
+        self.map.lock().remove(session_key);
+        Ok(())
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // `MockStore`
 ////////////////////////////////////////////////////////////////////////////////
@@ -432,7 +598,7 @@ where
                     data,
                     ttl,
                 } => {
-                    let result = if latest_ttl.unwrap_or(*ttl) >= Ttl::now_local().unwrap() {
+                    let result = if latest_ttl.unwrap_or(*ttl) >= Ttl::now_utc() {
                         LoadResult::Occupied {
                             data: data.to_owned(),
                             ttl: latest_ttl.unwrap_or(*ttl),
@@ -448,7 +614,7 @@ where
                     ttl,
                 } => {
                     if latest_ttl.is_none() {
-                        if *ttl >= Ttl::now_local().unwrap() {
+                        if *ttl >= Ttl::now_utc() {
                             latest_ttl = Some(*ttl);
                             continue;
                         } else {
@@ -499,7 +665,7 @@ where
                     session_key: _,
                     ttl,
                 } => {
-                    if *ttl >= Ttl::now_local().unwrap() {
+                    if *ttl >= Ttl::now_utc() {
                     } else {
                         *state = EntryState::Expired;
                     }
@@ -6,6 +6,7 @@ use tokio::sync::mpsc;
 use tower::ServiceExt;
 use tower_sesh::{session::SessionRejection, store::MemoryStore, Session, SessionLayer};
 use tower_sesh_core::{
+    backend::BackendStore,
     store::{self},
     SessionKey,
 };
@@ -13,7 +14,7 @@ use tracing::Level;
 use tracing_mock::{expect, subscriber};
 
 mod support;
-use support::ErrStore;
+use support::{ErrStore, MinimalBackend};
 
 const ERROR_MESSAGE: &str = "`ErrStore` always returns an error";
 
@@ -77,6 +78,35 @@ async fn session_sync_error() {
     handle.assert_finished();
 }
 
+#[tokio::test]
+async fn unindexed_store_warns_once() {
+    let (subscriber, handle) = subscriber::mock()
+        .with_filter(|meta| meta.target() == "tower_sesh::middleware")
+        .event(expect::event().at_level(Level::WARN))
+        .only()
+        .run_with_handle();
+
+    async fn handler(session: Session<()>) -> impl IntoResponse {
+        session.insert(());
+        session.set_principal("42");
+    }
+
+    {
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let store = Arc::new(BackendStore::new(MinimalBackend::<()>::new()));
+        let app = Router::new()
+            .route("/", routing::get(handler))
+            .layer(SessionLayer::plain(store));
+
+        for _ in 0..2 {
+            let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+    }
+
+    handle.assert_finished();
+}
+
 #[tokio::test]
 async fn extractor_rejection() {
     let (subscriber, handle) = subscriber::mock()